@@ -4,14 +4,89 @@ pub const FIELD_MANAGER_SCOPE: &str = "sparkcluster";
 
 pub const PORT_NAME_WEB: &str = "http";
 pub const PORT_NAME_SPARK: &str = "spark";
+pub const PORT_NAME_JMX: &str = "jmx";
+pub const PORT_NAME_REST: &str = "rest";
+
+/// Port the Prometheus JMX exporter agent serves metrics on
+pub const JMX_EXPORTER_PORT: i32 = 8090;
+/// Path of the JMX exporter agent jar in the Spark image
+pub const JMX_EXPORTER_JAR: &str = "/opt/jmx_exporter/jmx_prometheus_javaagent.jar";
+/// Directory the JMX exporter config is mounted to
+pub const JMX_EXPORTER_CONFIG_DIR: &str = "/etc/jmx_exporter";
+/// Name of the JMX exporter config file
+pub const JMX_EXPORTER_CONFIG: &str = "config.yml";
+/// Key of the JMX exporter config in the rolegroup `ConfigMap`
+pub const JMX_EXPORTER_CONFIG_MAP_KEY: &str = "jmx-exporter-config.yml";
+/// Environment variable with JVM options for all Spark daemons
+pub const SPARK_DAEMON_JAVA_OPTS: &str = "SPARK_DAEMON_JAVA_OPTS";
 
 pub const DEFAULT_LOG_DIR: &str = "/tmp/spark-events";
+pub const DEFAULT_EVENT_LOG_VOLUME_ACCESS_MODE: &str = "ReadWriteMany";
+pub const DEFAULT_EVENT_LOG_VOLUME_SIZE: &str = "10Gi";
+pub const DEFAULT_MASTER_TERMINATION_GRACE_PERIOD_SECONDS: i64 = 300;
+pub const DEFAULT_WORKER_TERMINATION_GRACE_PERIOD_SECONDS: i64 = 120;
+pub const DEFAULT_MASTER_API_TIMEOUT_SECONDS: u64 = 5;
+pub const DEFAULT_MASTER_DRAIN_TIMEOUT_SECONDS: u64 = 600;
+
+/// Node label carrying the machine family on GKE
+pub const LABEL_GKE_MACHINE_FAMILY: &str = "cloud.google.com/machine-family";
+/// Well-known node label carrying the instance type (used by EKS and AKS)
+pub const LABEL_INSTANCE_TYPE: &str = "node.kubernetes.io/instance-type";
+
+/// Well-known node label carrying the node name
+pub const LABEL_HOSTNAME: &str = "kubernetes.io/hostname";
+
+/// Label marking `Secret`s whose rotation restarts the pods of the cluster named by the
+/// `app.kubernetes.io/instance` label of the `Secret`
+pub const MANAGED_SECRET_LABEL: &str = "spark.stackable.tech/managed-secret";
+/// Pod annotation holding a hash of the managed secrets' resource versions
+pub const MANAGED_SECRETS_HASH_ANNOTATION: &str = "spark.stackable.tech/managed-secrets-hash";
+
+/// Pod annotation holding a hash of the rolegroup `ConfigMap` content
+pub const CONFIG_HASH_ANNOTATION: &str = "spark.stackable.tech/config-hash";
+/// `StatefulSet` annotation holding a hash of its pod template
+pub const POD_TEMPLATE_HASH_ANNOTATION: &str = "spark.stackable.tech/pod-template-hash";
+
+/// Key of the master URL in the `ConfigMap` referenced by `publishMasterUrl`
+pub const MASTER_URL_CONFIG_MAP_KEY: &str = "SPARK_MASTER_URL";
+/// Finalizer deleting the `ConfigMap` of `publishMasterUrl`, which is not garbage collected
+pub const MASTER_URL_FINALIZER: &str = "spark.stackable.tech/published-master-url";
+
+/// Label domains reserved for Kubernetes, cluster labels in these are not forwarded to pods
+pub const RESERVED_LABEL_DOMAINS: &[&str] = &["kubernetes.io", "k8s.io"];
+
+/// Annotations that are never added to pods from `podAnnotations`
+pub const DENIED_POD_ANNOTATIONS: &[&str] = &["kubectl.kubernetes.io/last-applied-configuration"];
+/// Pod annotation marking workers that run the external shuffle service
+pub const SHUFFLE_SERVICE_ANNOTATION: &str = "spark.stackable.tech/shuffle-service";
+/// Annotations of the Vault agent injector
+pub const VAULT_AGENT_INJECT_ANNOTATION: &str = "vault.hashicorp.com/agent-inject";
+pub const VAULT_ROLE_ANNOTATION: &str = "vault.hashicorp.com/role";
+/// Prefix of the per-secret annotations, the secret file name is appended
+pub const VAULT_AGENT_INJECT_SECRET_ANNOTATION_PREFIX: &str =
+    "vault.hashicorp.com/agent-inject-secret-";
+pub const VAULT_SECRET_VOLUME_PATH_ANNOTATION_PREFIX: &str =
+    "vault.hashicorp.com/secret-volume-path-";
+/// Annotation prefix reserved for annotations managed by the operator
+pub const OPERATOR_ANNOTATION_PREFIX: &str = "spark.stackable.tech/";
+/// Pods with this label set to `true` may connect to the masters with the `NetworkPolicy`
+pub const SPARK_CLIENT_LABEL: &str = "spark.stackable.tech/client";
+pub const DEFAULT_INGRESS_CONTROLLER_NAMESPACE: &str = "ingress-nginx";
+/// `app.kubernetes.io/name` of the operator pods, allowed to reach the web UIs with the
+/// `NetworkPolicy`
+pub const OPERATOR_APP_NAME: &str = "spark-operator";
+/// Label every namespace carries with its own name (Kubernetes 1.21+)
+pub const LABEL_NAMESPACE_NAME: &str = "kubernetes.io/metadata.name";
 
 /// Value for the APP_NAME_LABEL label key
 pub const APP_NAME: &str = "spark";
 pub const SPARK_DEFAULTS_CONF: &str = "spark-defaults.conf";
 /// Name of the environment variables file where spark nodes look for configuration data
 pub const SPARK_ENV_SH: &str = "spark-env.sh";
+/// Name of the log4j configuration file
+pub const LOG4J_PROPERTIES: &str = "log4j.properties";
+/// Name of the fair scheduler pool configuration file
+pub const FAIR_SCHEDULER_XML: &str = "fairscheduler.xml";
 /// Name of the metrics properties file to enable e.g. JMX metrics.
 pub const SPARK_METRICS_PROPERTIES: &str = "metrics.properties";
 /// Basic start up parameter: We need to point the spark nodes to "our" configuration
@@ -29,15 +104,27 @@ pub const SPARK_DEFAULTS_AUTHENTICATE: &str = "spark.authenticate";
 /// Common parameter: Must be set on all nodes (Master, Worker, HistoryServer) to activate
 /// a secret password which needs to be supplied via spark-submit.
 pub const SPARK_DEFAULTS_AUTHENTICATE_SECRET: &str = "spark.authenticate.secret";
+/// Environment variable with the shared secret of `authentication`, read from the `Secret` key
+/// [`SPARK_DEFAULTS_AUTHENTICATE_SECRET`]
+pub const SPARK_AUTHENTICATE_SECRET_ENV: &str = "SPARK_AUTHENTICATE_SECRET";
 /// Common parameter: Must be set to '0' on all nodes (Master, Worker, HistoryServer) to disable
 /// automatic port search. Otherwise the nodes will increase their given port if it's already in use.
 pub const SPARK_DEFAULTS_PORT_MAX_RETRIES: &str = "spark.port.maxRetries";
+/// The master URL applications connect to by default.
+pub const SPARK_DEFAULTS_MASTER: &str = "spark.master";
 /// Master specific parameter: Set the master port in environment variables.
 pub const SPARK_DEFAULTS_MASTER_PORT: &str = "spark.master.port";
 /// Master specific parameter: Set the master port in environment variables.
 pub const SPARK_ENV_MASTER_PORT: &str = "SPARK_MASTER_PORT";
 /// Master specific parameter: Set the master web ui port in environment variables.
 pub const SPARK_ENV_MASTER_WEBUI_PORT: &str = "SPARK_MASTER_WEBUI_PORT";
+/// Master specific parameter: Set the JVM options of the master in environment variables.
+pub const SPARK_ENV_MASTER_OPTS: &str = "SPARK_MASTER_OPTS";
+/// Worker specific parameter: Set the JVM options of the worker in environment variables.
+pub const SPARK_ENV_WORKER_OPTS: &str = "SPARK_WORKER_OPTS";
+/// HistoryServer specific parameter: Set the JVM options of the history server in
+/// environment variables.
+pub const SPARK_ENV_HISTORY_OPTS: &str = "SPARK_HISTORY_OPTS";
 /// Worker specific parameter: Set the worker cores in environment variables.
 pub const SPARK_ENV_WORKER_CORES: &str = "SPARK_WORKER_CORES";
 /// Worker specific parameter: Set the worker memory (500m, 2g) in environment variables.
@@ -54,3 +141,112 @@ pub const SPARK_DEFAULTS_HISTORY_FS_LOG_DIRECTORY: &str = "spark.history.fs.logD
 pub const SPARK_DEFAULTS_HISTORY_STORE_PATH: &str = "spark.history.store.path";
 /// HistoryServer specific parameter: Set HistoryServer web ui port to access the common logs.
 pub const SPARK_DEFAULTS_HISTORY_WEBUI_PORT: &str = "spark.history.ui.port";
+/// Common parameter: Prefix of the executor resource profile properties. The profile id and
+/// the resource (e.g. `executor.cores`) are appended.
+pub const SPARK_DEFAULTS_RESOURCE_PROFILES: &str = "spark.resourceProfiles";
+/// Resource profile ids that are reserved by Spark and must not be used for custom profiles.
+pub const RESERVED_RESOURCE_PROFILE_IDS: &[&str] = &["0", "default"];
+/// Common parameter: The class used to serialize objects sent over the network or cached.
+pub const SPARK_DEFAULTS_SERIALIZER: &str = "spark.serializer";
+/// Common parameter: Comma-separated list of classes to register with Kryo.
+pub const SPARK_DEFAULTS_KRYO_CLASSES_TO_REGISTER: &str = "spark.kryo.classesToRegister";
+/// Serializer class for Kryo serialization.
+pub const KRYO_SERIALIZER_CLASS: &str = "org.apache.spark.serializer.KryoSerializer";
+pub const SPARK_DEFAULTS_AUTO_BROADCAST_JOIN_THRESHOLD: &str =
+    "spark.sql.autoBroadcastJoinThreshold";
+/// Dynamic partition pruning properties.
+pub const SPARK_DEFAULTS_DPP_ENABLED: &str = "spark.sql.optimizer.dynamicPartitionPruning.enabled";
+pub const SPARK_DEFAULTS_DPP_REUSE_BROADCAST_ONLY: &str =
+    "spark.sql.optimizer.dynamicPartitionPruning.reuseBroadcastOnly";
+pub const SPARK_DEFAULTS_DPP_FALLBACK_FILTER_RATIO: &str =
+    "spark.sql.optimizer.dynamicPartitionPruning.fallbackFilterRatio";
+pub const SPARK_DEFAULTS_SCHEDULER_MODE: &str = "spark.scheduler.mode";
+pub const SPARK_DEFAULTS_SCHEDULER_ALLOCATION_FILE: &str = "spark.scheduler.allocation.file";
+
+pub const CONDITION_AVAILABLE: &str = "Available";
+pub const CONDITION_PROGRESSING: &str = "Progressing";
+pub const CONDITION_DEGRADED: &str = "Degraded";
+pub const CONDITION_NO_MATCHING_NODES: &str = "NoMatchingNodes";
+pub const CONDITION_NO_GPU_NODES: &str = "NoGpuNodes";
+pub const CONDITION_NO_SHUFFLE_SERVICE: &str = "NoShuffleService";
+pub const CONDITION_STALE_APPLICATION: &str = "StaleApplication";
+
+pub const SPARK_DEFAULTS_EVENT_LOG_COMPRESS: &str = "spark.eventLog.compress";
+pub const SPARK_DEFAULTS_EVENT_LOG_COMPRESSION_CODEC: &str = "spark.eventLog.compression.codec";
+pub const DEFAULT_EVENT_LOG_COMPRESSION_CODEC: &str = "zstd";
+pub const SPARK_DEFAULTS_S3A_ENDPOINT: &str = "spark.hadoop.fs.s3a.endpoint";
+pub const SPARK_DEFAULTS_S3_IMPL: &str = "spark.hadoop.fs.s3.impl";
+pub const S3A_FILE_SYSTEM_CLASS: &str = "org.apache.hadoop.fs.s3a.S3AFileSystem";
+/// Keys of the S3 credentials `Secret`
+pub const S3_ACCESS_KEY: &str = "accessKey";
+pub const S3_SECRET_KEY: &str = "secretKey";
+/// Environment variables read by the AWS credentials chain of S3A
+pub const AWS_ACCESS_KEY_ID: &str = "AWS_ACCESS_KEY_ID";
+pub const AWS_SECRET_ACCESS_KEY: &str = "AWS_SECRET_ACCESS_KEY";
+pub const AWS_REGION: &str = "AWS_REGION";
+/// `ServiceAccount` annotation with the IAM role of the pods (IRSA)
+pub const EKS_ROLE_ARN_ANNOTATION: &str = "eks.amazonaws.com/role-arn";
+pub const SPARK_DEFAULTS_S3A_CREDENTIALS_PROVIDER: &str =
+    "spark.hadoop.fs.s3a.aws.credentials.provider";
+/// Reads the web identity token that EKS mounts into pods of annotated `ServiceAccounts`
+pub const WEB_IDENTITY_CREDENTIALS_PROVIDER_CLASS: &str =
+    "com.amazonaws.auth.WebIdentityTokenFileCredentialsProvider";
+
+/// `ServiceAccount` annotation with the GCP service account of the pods (Workload Identity)
+pub const GKE_SERVICE_ACCOUNT_ANNOTATION: &str = "iam.gke.io/gcp-service-account";
+/// Lets the GCS connector use the credentials of the GKE metadata server
+pub const SPARK_DEFAULTS_GOOGLE_CLOUD_AUTH_TYPE: &str = "spark.hadoop.google.cloud.auth.type";
+
+pub const SPARK_DEFAULTS_HADOOP_DEFAULT_FS: &str = "spark.hadoop.fs.defaultFS";
+/// The key of the `hdfsConfigMap`, mounted as file of the same name
+pub const HDFS_CORE_SITE: &str = "core-site.xml";
+
+/// Name of the Hive configuration file, also the key of the `hiveConfigMapRef`
+pub const HIVE_SITE_XML: &str = "hive-site.xml";
+pub const HIVE_METASTORE_URIS: &str = "hive.metastore.uris";
+pub const SPARK_DEFAULTS_HIVE_METASTORE_URIS: &str = "spark.hadoop.hive.metastore.uris";
+pub const SPARK_DEFAULTS_SQL_CATALOG_IMPLEMENTATION: &str = "spark.sql.catalogImplementation";
+
+pub const SPARK_DEFAULTS_DEPLOY_RECOVERY_MODE: &str = "spark.deploy.recoveryMode";
+pub const SPARK_DEFAULTS_DEPLOY_ZOOKEEPER_URL: &str = "spark.deploy.zookeeper.url";
+pub const SPARK_DEFAULTS_DEPLOY_ZOOKEEPER_DIR: &str = "spark.deploy.zookeeper.dir";
+/// The number of masters required by `haConfig`
+pub const MINIMUM_MASTER_COUNT_FOR_HA: u32 = 2;
+
+/// Worker pods that may be evicted at the same time unless `workerMaxUnavailable` is set
+pub const DEFAULT_WORKER_MAX_UNAVAILABLE: i32 = 1;
+
+pub const SPARK_DEFAULTS_UI_REVERSE_PROXY: &str = "spark.ui.reverseProxy";
+/// Rewrites the path of ingress-nginx, referencing capture groups of the path
+pub const ANNOTATION_NGINX_REWRITE_TARGET: &str = "nginx.ingress.kubernetes.io/rewrite-target";
+
+/// Extended resource of NVIDIA GPUs
+pub const RESOURCE_NVIDIA_GPU: &str = "nvidia.com/gpu";
+pub const SPARK_WORKER_RESOURCE_GPU_AMOUNT: &str = "spark.worker.resource.gpu.amount";
+pub const SPARK_EXECUTOR_RESOURCE_GPU_AMOUNT: &str = "spark.executor.resource.gpu.amount";
+pub const SPARK_WORKER_RESOURCE_GPU_DISCOVERY_SCRIPT: &str =
+    "spark.worker.resource.gpu.discoveryScript";
+/// Directory the GPU discovery script of the workers is mounted to
+pub const GPU_DISCOVERY_SCRIPT_DIR: &str = "/stackable/gpu";
+/// Name of the GPU discovery script, also its key in the rolegroup `ConfigMap`
+pub const GPU_DISCOVERY_SCRIPT: &str = "getGpusResources.sh";
+
+pub const SPARK_DEFAULTS_DYNAMIC_ALLOCATION_ENABLED: &str = "spark.dynamicAllocation.enabled";
+pub const SPARK_DEFAULTS_DYNAMIC_ALLOCATION_MIN_EXECUTORS: &str =
+    "spark.dynamicAllocation.minExecutors";
+pub const SPARK_DEFAULTS_DYNAMIC_ALLOCATION_MAX_EXECUTORS: &str =
+    "spark.dynamicAllocation.maxExecutors";
+pub const SPARK_DEFAULTS_DYNAMIC_ALLOCATION_INITIAL_EXECUTORS: &str =
+    "spark.dynamicAllocation.initialExecutors";
+pub const SPARK_DEFAULTS_SHUFFLE_SERVICE_ENABLED: &str = "spark.shuffle.service.enabled";
+
+pub const SPARK_DEFAULTS_SQL_EXTENSIONS: &str = "spark.sql.extensions";
+/// Prefix of the catalog properties, the catalog name is appended
+pub const SPARK_DEFAULTS_SQL_CATALOG: &str = "spark.sql.catalog";
+pub const SPARK_DEFAULTS_JARS: &str = "spark.jars";
+
+pub const SPARK_DEFAULTS_EXECUTOR_CORES: &str = "spark.executor.cores";
+pub const SPARK_DEFAULTS_EXECUTOR_MEMORY: &str = "spark.executor.memory";
+
+pub const SPARK_DEFAULTS_MASTER_REST_PORT: &str = "spark.master.rest.port";
+pub const DEFAULT_MASTER_REST_PORT: &str = "6066";
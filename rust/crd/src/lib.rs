@@ -7,6 +7,13 @@ use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use stackable_operator::role_utils::RoleGroupRef;
 use stackable_operator::{
+    k8s_openapi::{
+        api::core::v1::{
+            Container, EnvFromSource, EnvVar, NodeSelectorRequirement, PodDNSConfig,
+            PodSecurityContext, PodTemplateSpec, SecurityContext, TopologySpreadConstraint,
+        },
+        apimachinery::pkg::apis::meta::v1::{Condition, Time},
+    },
     kube::{runtime::reflector::ObjectRef, CustomResource},
     product_config_utils::{ConfigError, Configuration},
     role_utils::{CommonConfiguration, Role},
@@ -23,6 +30,13 @@ use strum_macros::EnumIter;
     kind = "SparkCluster",
     shortname = "sc",
     status = "SparkClusterStatus",
+    scale = r#"{"specReplicasPath":".spec.workers.roleGroups.default.replicas","statusReplicasPath":".status.workerCount"}"#,
+    printcolumn = r#"{"name":"Masters","type":"integer","jsonPath":".status.masterCount"}"#,
+    printcolumn = r#"{"name":"Workers","type":"integer","jsonPath":".status.workerCount"}"#,
+    printcolumn = r#"{"name":"Version","type":"string","jsonPath":".spec.version"}"#,
+    printcolumn = r#"{"name":"Phase","type":"string","jsonPath":".status.phase"}"#,
+    printcolumn = r#"{"name":"Active_Apps","type":"integer","jsonPath":".status.activeApplicationCount"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#,
     namespaced,
     crates(
         kube_core = "stackable_operator::kube::core",
@@ -44,6 +58,424 @@ pub struct SparkClusterSpec {
     pub config: Option<CommonConfiguration<CommonConfig>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub stopped: Option<bool>,
+    /// A `<namespace>/<name>` reference to a `ConfigMap` the master URL is published to.
+    /// The `ConfigMap` may live in a different namespace than the cluster itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish_master_url: Option<String>,
+    /// Fraction of the total worker memory a broadcast join may use, see
+    /// [`SparkClusterSpec::auto_broadcast_join_threshold`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_broadcast_join_threshold_fraction: Option<f64>,
+    /// The number of jobs expected to run at the same time, defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_jobs: Option<usize>,
+    /// Run the Prometheus JMX exporter as a Java agent in all Spark daemons.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jmx_exporter_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamic_partition_pruning: Option<DynamicPartitionPruningConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamic_allocation: Option<DynamicAllocationConfig>,
+    /// Classes of Spark SQL extensions, e.g. `io.delta.sql.DeltaSparkSessionExtension`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sql_extensions: Option<Vec<String>>,
+    /// Compress the event logs, defaults to `true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_log_compress: Option<bool>,
+    /// The codec of compressed event logs, defaults to `zstd`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_log_codec: Option<String>,
+    /// Spark SQL catalogs by name, e.g. Iceberg or Delta Lake catalogs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalogs: Option<BTreeMap<String, SparkCatalogConfig>>,
+    /// JARs added to the classpath of the applications, e.g. those of the SQL extensions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_jars: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduler_mode: Option<SparkSchedulerMode>,
+    /// The type of the history server `Service`, defaults to `ClusterIP`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_server_service_type: Option<ServiceType>,
+    /// Names of `Secret`s in the cluster namespace used to pull the Spark image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_pull_secrets: Option<Vec<String>>,
+    /// The `ServiceAccount` the Spark pods run as. If not set, the operator manages a
+    /// `ServiceAccount` named `<cluster>-spark` without any permissions.
+    ///
+    /// Drivers running in client mode inside the cluster only need API access if they manage
+    /// executor pods themselves; this requires a `Role` granting `create`, `delete`, `get`,
+    /// `list` and `watch` on `pods`, `services` and `configmaps`, bound to the `ServiceAccount`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_account_name: Option<String>,
+    /// The IAM role of the pods on EKS (IRSA), annotated on the `ServiceAccount` as
+    /// `eks.amazonaws.com/role-arn`. S3 is then accessed with the web identity token of the
+    /// pods instead of the keys of `s3.credentialsSecret`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aws_role_arn: Option<String>,
+    /// The AWS region of the pods, set as `AWS_REGION`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aws_region: Option<String>,
+    /// The GCP service account of the pods on GKE (Workload Identity), annotated on the
+    /// `ServiceAccount` as `iam.gke.io/gcp-service-account`, e.g.
+    /// `spark@my-project.iam.gserviceaccount.com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gcp_service_account: Option<String>,
+    /// Annotations added to all Spark pods, e.g. for Prometheus scraping or Vault agent injection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_annotations: Option<BTreeMap<String, String>>,
+    /// A complete `log4j.properties` used by all Spark processes instead of the generated one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log4j_config: Option<String>,
+    /// `Secret`s mounted into all Spark containers, e.g. TLS certificates or Kerberos keytabs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_volumes: Option<Vec<SecretVolumeSpec>>,
+    /// Spreading of the worker pods across nodes, enabled by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_spread: Option<WorkerSpread>,
+    /// The `PriorityClass` of all Spark pods, can be overridden per role and role group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority_class_name: Option<String>,
+    /// A `PersistentVolumeClaim` shared by all pods for the event logs, instead of one
+    /// volume per pod. It is kept when the cluster is deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_log_volume: Option<EventLogVolume>,
+    /// A `ConfigMap` with the `core-site.xml` of the HDFS cluster of an `hdfs://` `logDir`,
+    /// mounted into the Spark configuration directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hdfs_config_map: Option<String>,
+    /// Thrift URIs of the Hive metastore, e.g. `thrift://hive:9083`, enabling Hive as catalog
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hive_metastore_uris: Option<Vec<String>>,
+    /// A `ConfigMap` with a `hive-site.xml`, mounted into the Spark configuration directory
+    /// instead of the one generated from `hiveMetastoreUris`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hive_config_map_ref: Option<String>,
+    /// Number of worker pods that may be evicted at the same time, e.g. during node drains,
+    /// defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_max_unavailable: Option<i32>,
+    /// High availability of the masters via ZooKeeper, requires at least two masters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ha_config: Option<SparkHaConfig>,
+    /// An `Ingress` `<cluster>-master-ui` for the web UI of the masters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master_ui_ingress: Option<IngressConfig>,
+    /// An `Ingress` `<cluster>-history-ui` for the web UI of the history servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_server_ingress: Option<IngressConfig>,
+    /// Restrict the ingress traffic of the Spark pods with a `NetworkPolicy`, defaults to `false`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enable_network_policy: Option<bool>,
+    /// The namespace of the ingress controller, whose pods the `NetworkPolicy` allows to reach
+    /// the web UIs. Defaults to `ingress-nginx`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress_controller_namespace: Option<String>,
+    /// Authentication of all Spark connections with a shared secret from a `Secret`, takes
+    /// precedence over `config.secret`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication: Option<SparkAuthConfig>,
+    /// Node label expressions all Spark pods require, e.g. for dedicated node pools.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_node_affinity: Option<Vec<NodeSelectorRequirement>>,
+    /// How long the operator waits for the state of a master, defaults to 5 seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master_api_timeout_seconds: Option<u64>,
+    /// Applications running longer than this many hours are reported as possibly stuck via the
+    /// `StaleApplication` condition. Not checked unless set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_app_threshold_hours: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SparkAuthConfig {
+    /// The `Secret` with the shared secret in the key `spark.authenticate.secret`
+    pub secret_ref: String,
+}
+
+/// An `Ingress` routing a host (and path prefix) to a web UI.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngressConfig {
+    pub host: String,
+    /// A `Secret` with the TLS certificate of the host, plain HTTP without
+    pub tls_secret: Option<String>,
+    /// The `IngressClass`, defaults to the default `IngressClass` of the cluster
+    pub ingress_class_name: Option<String>,
+    /// Defaults to `/`
+    pub path_prefix: Option<String>,
+}
+
+/// The ZooKeeper ensemble the masters use for leader election and recovery.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SparkHaConfig {
+    /// The ZooKeeper connection string, e.g. `zk-0.zk:2181,zk-1.zk:2181`
+    pub zookeeper_url: String,
+    /// The ZooKeeper directory of the recovery state, defaults to `/spark`
+    pub zookeeper_dir: Option<String>,
+}
+
+/// The shared `PersistentVolumeClaim` `<cluster>-history-events` for the event logs.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventLogVolume {
+    /// The `StorageClass` of the claim, defaults to the default `StorageClass` of the cluster
+    pub storage_class: Option<String>,
+    /// Defaults to `ReadWriteMany`, the pods of all nodes write to the volume
+    pub access_mode: Option<String>,
+    /// Defaults to `10Gi`
+    pub storage_size: Option<String>,
+}
+
+/// A preferred pod anti-affinity between all workers of a cluster.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerSpread {
+    /// Defaults to `true`
+    pub enabled: Option<bool>,
+    /// The node label defining the topology domain to spread over, defaults to
+    /// `kubernetes.io/hostname`
+    pub topology_key: Option<String>,
+    /// The weight (1 to 100) of the preference, defaults to 100
+    pub weight: Option<i32>,
+}
+
+/// A `Secret` mounted as a directory, with one file per key.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretVolumeSpec {
+    pub secret_name: String,
+    pub mount_path: String,
+}
+
+/// Kubernetes `Service` types supported for role services.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum ServiceType {
+    ClusterIP,
+    NodePort,
+}
+
+impl Default for ServiceType {
+    fn default() -> Self {
+        ServiceType::ClusterIP
+    }
+}
+
+impl ServiceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceType::ClusterIP => "ClusterIP",
+            ServiceType::NodePort => "NodePort",
+        }
+    }
+}
+
+/// The scheduling mode of jobs within a Spark application.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SparkSchedulerMode {
+    /// Jobs run in submission order (Spark default)
+    Fifo,
+    /// Jobs share the resources round-robin, organized in the given pools
+    Fair(Vec<FairSchedulerPool>),
+}
+
+impl SparkSchedulerMode {
+    /// The content of `fairscheduler.xml`, only needed for [`SparkSchedulerMode::Fair`]
+    pub fn fair_scheduler_xml(&self) -> Option<String> {
+        let pools = match self {
+            SparkSchedulerMode::Fifo => return None,
+            SparkSchedulerMode::Fair(pools) => pools,
+        };
+        let mut xml = String::from("<?xml version=\"1.0\"?>\n<allocations>\n");
+        for pool in pools {
+            xml.push_str(&format!("  <pool name=\"{}\">\n", xml_escape(&pool.name)));
+            if let Some(scheduling_mode) = &pool.scheduling_mode {
+                xml.push_str(&format!(
+                    "    <schedulingMode>{}</schedulingMode>\n",
+                    scheduling_mode.as_spark_value()
+                ));
+            }
+            if let Some(weight) = pool.weight {
+                xml.push_str(&format!("    <weight>{}</weight>\n", weight));
+            }
+            if let Some(min_share) = pool.min_share {
+                xml.push_str(&format!("    <minShare>{}</minShare>\n", min_share));
+            }
+            xml.push_str("  </pool>\n");
+        }
+        xml.push_str("</allocations>\n");
+        Some(xml)
+    }
+}
+
+/// A pool of the fair scheduler, jobs select it via the `spark.scheduler.pool` local property.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FairSchedulerPool {
+    pub name: String,
+    /// Scheduling of the jobs within the pool, defaults to FIFO.
+    pub scheduling_mode: Option<PoolSchedulingMode>,
+    /// Share of the cluster relative to other pools, defaults to 1.
+    pub weight: Option<u32>,
+    /// Minimum number of CPU cores the pool gets, defaults to 0.
+    pub min_share: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PoolSchedulingMode {
+    Fifo,
+    Fair,
+}
+
+impl PoolSchedulingMode {
+    fn as_spark_value(&self) -> &'static str {
+        match self {
+            PoolSchedulingMode::Fifo => "FIFO",
+            PoolSchedulingMode::Fair => "FAIR",
+        }
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Dynamic partition pruning of Spark SQL queries on partitioned tables.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DynamicPartitionPruningConfig {
+    pub enabled: bool,
+    /// Only prune if the result of a broadcast exchange can be reused.
+    pub use_broadcasting_cache: bool,
+    /// Fallback selectivity (0.0 to 1.0) of the pruning filter if statistics are missing.
+    pub fallback_filter_ratio: f64,
+}
+
+/// Dynamic allocation of executors. Shuffle files are served by the external shuffle service
+/// of the workers, so that executors can be removed without losing them.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DynamicAllocationConfig {
+    pub enabled: bool,
+    pub min_executors: u32,
+    pub max_executors: u32,
+    /// Number of executors at the start of an application, defaults to `minExecutors`.
+    pub initial_executors: Option<u32>,
+}
+
+/// A Spark SQL catalog, written to `spark.sql.catalog.<name>`.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SparkCatalogConfig {
+    /// The catalog implementation, e.g. `org.apache.iceberg.spark.SparkCatalog`
+    pub class: String,
+    /// Options of the catalog, written to `spark.sql.catalog.<name>.<option>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<BTreeMap<String, String>>,
+}
+
+impl SparkClusterSpec {
+    /// Whether Spark SQL uses the Hive metastore as catalog
+    pub fn hive_enabled(&self) -> bool {
+        self.hive_config_map_ref.is_some()
+            || self
+                .hive_metastore_uris
+                .as_ref()
+                .map_or(false, |uris| !uris.is_empty())
+    }
+
+    /// The content of the generated `hive-site.xml`, not needed with `hive_config_map_ref`
+    pub fn hive_site_xml(&self) -> Option<String> {
+        if self.hive_config_map_ref.is_some() {
+            return None;
+        }
+        let uris = self
+            .hive_metastore_uris
+            .as_ref()
+            .filter(|uris| !uris.is_empty())?;
+        Some(format!(
+            "<?xml version=\"1.0\"?>\n\
+             <configuration>\n  \
+             <property>\n    \
+             <name>{}</name>\n    \
+             <value>{}</value>\n  \
+             </property>\n\
+             </configuration>\n",
+            HIVE_METASTORE_URIS,
+            xml_escape(&uris.join(","))
+        ))
+    }
+
+    /// The total memory of all workers in MiB, if every worker role group has its memory set.
+    pub fn total_worker_memory_mb(&self) -> Option<u64> {
+        let workers = self.workers.as_ref()?;
+        let role_memory = workers
+            .config
+            .config
+            .as_ref()
+            .and_then(|c| c.spark_worker_memory());
+        workers
+            .role_groups
+            .values()
+            .map(|rg| {
+                let memory = rg
+                    .config
+                    .config
+                    .as_ref()
+                    .and_then(|c| c.spark_worker_memory())
+                    .or_else(|| role_memory.clone())?;
+                Some(u64::from(rg.replicas.unwrap_or(0)) * parse_memory_mb(&memory)?)
+            })
+            .sum()
+    }
+
+    /// The `spark.sql.autoBroadcastJoinThreshold` in bytes, computed as
+    /// `fraction * total_worker_memory / max_concurrent_jobs`.
+    pub fn auto_broadcast_join_threshold(&self) -> Option<u64> {
+        let fraction = self.auto_broadcast_join_threshold_fraction?;
+        let total_worker_memory_mb = self.total_worker_memory_mb()?;
+        let max_concurrent_jobs = self.max_concurrent_jobs.unwrap_or(1).max(1);
+        let threshold_mb = fraction * total_worker_memory_mb as f64 / max_concurrent_jobs as f64;
+        Some((threshold_mb * 1024.0 * 1024.0) as u64)
+    }
+}
+
+/// Parses a memory amount like `8Gi` (Kubernetes) or `8g` (Spark) into MiB.
+/// Amounts without a unit are interpreted as MiB, like Spark does for worker memory.
+fn parse_memory_mb(memory: &str) -> Option<u64> {
+    let memory = memory.trim();
+    let split = memory
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| memory.len());
+    let (amount, unit) = memory.split_at(split);
+    let amount = amount.parse::<u64>().ok()?;
+    match unit.to_ascii_lowercase().as_str() {
+        "k" | "ki" | "kb" => Some(amount / 1024),
+        "" | "m" | "mi" | "mb" => Some(amount),
+        "g" | "gi" | "gb" => Some(amount * 1024),
+        "t" | "ti" | "tb" => Some(amount * 1024 * 1024),
+        _ => None,
+    }
+}
+
+/// Parses a CPU amount like `2`, `1.5` (cores) or `500m` (millicores) into millicores.
+fn parse_cpu_millis(cpu: &str) -> Option<u64> {
+    let cpu = cpu.trim();
+    if let Some(millis) = cpu.strip_suffix('m') {
+        return millis.parse().ok();
+    }
+    let cores = cpu.parse::<f64>().ok()?;
+    if !cores.is_finite() || cores < 0.0 {
+        return None;
+    }
+    Some((cores * 1000.0) as u64)
 }
 
 #[derive(Clone, Default, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
@@ -52,6 +484,91 @@ pub struct SparkClusterStatus {
     /// An opaque value that changes every time a discovery detail does
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub discovery_hash: Option<String>,
+    /// Number of ready master pods
+    #[serde(default)]
+    pub master_count: i32,
+    /// Number of ready worker pods
+    #[serde(default)]
+    pub worker_count: i32,
+    /// Number of ready history server pods
+    #[serde(default)]
+    pub history_server_count: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<SparkClusterPhase>,
+    /// The Spark version all pods run, the previous version during an upgrade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The hash of the managed `Secret`s, see `spark.stackable.tech/managed-secret`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_secrets_hash: Option<String>,
+    /// When the managed `Secret`s were last rotated, which restarted the pods
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_rotation_time: Option<Time>,
+    /// The `<namespace>/<name>` of the `ConfigMap` the master URL was published to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_master_url: Option<String>,
+    /// Since when the replacement of master pods waits for running applications to finish
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master_drain_start_time: Option<Time>,
+    /// Cores of all alive workers, unknown while no master can be reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_cores: Option<usize>,
+    /// Cores used by the executors of all applications
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores_used: Option<usize>,
+    /// Memory of all alive workers in MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_memory_mb: Option<usize>,
+    /// Memory used by the executors of all applications in MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_used_mb: Option<usize>,
+    /// Running applications, unknown while no master can be reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_application_count: Option<i32>,
+    /// Applications the master still lists as completed, see `spark.deploy.retainedApplications`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_application_count: Option<i32>,
+    /// The `Available`, `Progressing` and `Degraded` conditions of the cluster
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    /// The labels, pod annotations and logging the pods currently run with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_non_critical_config: Option<NonCriticalConfig>,
+    /// The non-critical changes (`labels`, `podAnnotations`, `logging`) deferred until the
+    /// cluster is `Running` again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_non_critical_changes: Vec<String>,
+}
+
+/// The settings of a cluster that can wait for a running cluster to be rolled out.
+///
+/// Changing them still replaces the pods, which is deferred while the cluster is upgrading or
+/// not all of its pods are ready. Image versions, resources and ports are always applied.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NonCriticalConfig {
+    /// The cluster labels forwarded to the pods
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_annotations: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log4j_config: Option<String>,
+    /// The merged `logLevel` of each rolegroup by the name of its `StatefulSet`
+    #[serde(default)]
+    pub log_levels: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum SparkClusterPhase {
+    /// Not all pods of the cluster are ready (yet)
+    Pending,
+    /// All pods of the cluster are ready
+    Running,
+    /// The pods are being replaced with the ones of a new `spec.version`
+    UpgradeInProgress,
+    /// The cluster is stopped via `spec.stopped`
+    Stopped,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
@@ -61,29 +578,375 @@ pub struct CommonConfig {
     pub log_dir: Option<String>,
     pub max_port_retries: Option<usize>,
     pub enable_monitoring: Option<bool>,
+    pub resource_profiles: Option<Vec<SparkResourceProfile>>,
+    pub serializer: Option<SparkSerializer>,
+    /// Access to S3 if `logDir` is an `s3a://` or `s3://` URL
+    pub s3: Option<S3Config>,
+}
+
+/// The S3 endpoint and credentials used to write and read the event logs.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Config {
+    /// The endpoint of S3 compatible storage like MinIO, defaults to AWS S3
+    pub endpoint: Option<String>,
+    /// A `Secret` with the keys `accessKey` and `secretKey`
+    pub credentials_secret: Option<String>,
+}
+
+/// Whether the event log directory is stored in Google Cloud Storage instead of a volume.
+pub fn is_gcs_url(log_dir: &str) -> bool {
+    log_dir.starts_with("gs://")
+}
+
+/// Whether the event log directory is stored in S3 instead of a volume.
+pub fn is_s3_url(log_dir: &str) -> bool {
+    log_dir.starts_with("s3a://") || log_dir.starts_with("s3://")
+}
+
+/// The serializer used for shuffled and cached data.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SparkSerializer {
+    /// Spark's default Java serialization
+    Java,
+    /// Kryo serialization without class registration
+    Kryo,
+    /// Kryo serialization with the given classes registered up front
+    KryoWithClasses(Vec<String>),
+}
+
+impl Default for SparkSerializer {
+    fn default() -> Self {
+        SparkSerializer::Java
+    }
 }
 
+/// An executor resource profile (Spark 3.1+) that jobs can refer to by its `id`.
 #[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
+pub struct SparkResourceProfile {
+    pub id: String,
+    pub executor_cores: usize,
+    pub executor_memory: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MasterConfig {
     pub master_port: Option<u16>,
     pub master_web_ui_port: Option<u16>,
+    /// Port of the REST submission server, defaults to 6066. The server itself has to be
+    /// enabled via `spark.master.rest.enabled`
+    pub master_rest_port: Option<u16>,
+    #[serde(flatten)]
+    pub node: NodeConfig,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WorkerConfig {
     pub cores: Option<usize>,
     pub memory: Option<String>,
     pub worker_port: Option<u16>,
     pub worker_web_ui_port: Option<u16>,
+    #[serde(flatten)]
+    pub node: NodeConfig,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+impl WorkerConfig {
+    /// The cores available to executors: `cores` if set, otherwise derived from the container
+    /// CPU limit rounded down (but at least 1).
+    pub fn spark_worker_cores(&self) -> Option<usize> {
+        if let Some(cores) = self.cores {
+            return Some(cores);
+        }
+        let cpu_limit_millis = parse_cpu_millis(self.node.cpu_limit.as_deref()?)?;
+        Some((cpu_limit_millis / 1000).max(1) as usize)
+    }
+
+    /// The memory available to executors: `memory` if set, otherwise derived from the
+    /// container memory limit minus 1 GiB for the worker daemon (but at least half of it).
+    pub fn spark_worker_memory(&self) -> Option<String> {
+        if let Some(memory) = &self.memory {
+            return Some(memory.clone());
+        }
+        let memory_limit_mb = parse_memory_mb(self.node.memory_limit.as_deref()?)?;
+        Some(format!(
+            "{}m",
+            memory_limit_mb
+                .saturating_sub(1024)
+                .max(memory_limit_mb / 2)
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct HistoryServerConfig {
     pub store_path: Option<String>,
     pub history_web_ui_port: Option<u16>,
+    #[serde(flatten)]
+    pub node: NodeConfig,
+}
+
+/// Pod settings shared by all Spark node types (master, worker and history server).
+///
+/// Settings of the role apply to all of its role groups, settings of a role group
+/// take precedence over the ones of its role.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeConfig {
+    pub node_pool_selector: Option<NodePoolSelector>,
+    /// Node labels the pods must be scheduled on
+    pub node_selector: Option<BTreeMap<String, String>>,
+    /// CPU request of the Spark container, e.g. `500m` or `2`
+    pub cpu_request: Option<String>,
+    /// CPU limit of the Spark container
+    pub cpu_limit: Option<String>,
+    /// Memory request of the Spark container, e.g. `4Gi`
+    pub memory_request: Option<String>,
+    /// Memory limit of the Spark container
+    pub memory_limit: Option<String>,
+    /// Number of `nvidia.com/gpu` GPUs of the Spark container, also offered to the executors of
+    /// workers
+    pub gpu_limit: Option<i64>,
+    /// Default `spark.executor.cores` of applications submitted from the pods
+    pub executor_cores: Option<u32>,
+    /// Default `spark.executor.memory` of applications submitted from the pods, e.g. `4g`
+    pub executor_memory: Option<String>,
+    /// The Vault role of the pods, enables the injection of the Vault agent
+    pub vault_role: Option<String>,
+    /// Secrets the Vault agent writes into the pods
+    pub vault_secrets: Option<Vec<VaultSecretSpec>>,
+    pub liveness_probe: Option<ProbeConfig>,
+    pub readiness_probe: Option<ProbeConfig>,
+    /// The root log level of the Spark process, e.g. `WARN`, defaults to `INFO`
+    pub log_level: Option<String>,
+    /// JVM options of the Spark daemon, e.g. `-XX:+UseG1GC`, written to `SPARK_MASTER_OPTS`,
+    /// `SPARK_WORKER_OPTS` or `SPARK_HISTORY_OPTS`
+    pub jvm_opts: Option<String>,
+    /// Sources of environment variables of the Spark container, e.g. `Secret`s with credentials
+    pub env_from: Option<Vec<EnvFromSource>>,
+    /// Additional environment variables of the Spark container, e.g. single `Secret` keys
+    pub env: Option<Vec<EnvVar>>,
+    /// Spreading of the pods across topology domains like zones. Constraints without a
+    /// label selector apply to all pods of the role.
+    pub topology_spread_constraints: Option<Vec<TopologySpreadConstraint>>,
+    /// The `PriorityClass` of the pods, overrides the one of the cluster
+    pub priority_class_name: Option<String>,
+    /// Run the pods in the network namespace of their node, defaults to `false`. The ports are
+    /// bound on the node and `NetworkPolicies` do not apply
+    pub host_network: Option<bool>,
+    /// The DNS policy of the pods, defaults to `ClusterFirst` (`ClusterFirstWithHostNet` with
+    /// `hostNetwork`)
+    pub dns_policy: Option<String>,
+    /// Additional DNS settings of the pods, e.g. `ndots` or search domains
+    pub dns_config: Option<PodDNSConfig>,
+    /// The `RuntimeClass` of the pods, e.g. `gvisor` for sandboxed history servers
+    pub runtime_class_name: Option<String>,
+    /// Containers run to completion before the Spark container starts, e.g. to download JARs
+    pub init_containers: Option<Vec<Container>>,
+    /// Containers run next to the Spark container, e.g. log shippers
+    pub sidecar_containers: Option<Vec<Container>>,
+    /// Seconds between `SIGTERM` and `SIGKILL` of the pods, defaults to 300 for masters and
+    /// 120 for workers so that they can hand over their applications
+    pub termination_grace_period_seconds: Option<i64>,
+    /// Maximum seconds to wait for running applications before master pods are replaced,
+    /// defaults to 600. Ignored for other roles
+    pub drain_timeout_seconds: Option<u64>,
+    /// The `preStop` command of the Spark container, defaults to `sbin/stop-slave.sh` for workers
+    /// so that they deregister from the master before they are stopped
+    pub pre_stop_command: Option<Vec<String>>,
+    /// The security context of the pods, e.g. the `fsGroup` of volumes. Unset by default
+    pub pod_security_context: Option<PodSecurityContext>,
+    /// The security context of the Spark container, e.g. to drop all capabilities. Unset by
+    /// default
+    pub container_security_context: Option<SecurityContext>,
+    /// An escape hatch to change the generated pod template, e.g. to debug a pod.
+    ///
+    /// The override is deep-merged into the template of the operator: objects are merged field
+    /// by field, lists of named entries like containers, volumes or environment variables entry
+    /// by entry by `name`, and all other values of the override replace the generated ones.
+    /// The pod labels are always the ones of the operator. The override of a role group
+    /// replaces the one of its role.
+    pub pod_template_override: Option<PodTemplateSpec>,
+}
+
+impl NodeConfig {
+    /// Fill all fields that are not set with the ones of `fallback`.
+    pub fn merge(&self, fallback: &NodeConfig) -> NodeConfig {
+        NodeConfig {
+            node_pool_selector: self
+                .node_pool_selector
+                .clone()
+                .or_else(|| fallback.node_pool_selector.clone()),
+            node_selector: self
+                .node_selector
+                .clone()
+                .or_else(|| fallback.node_selector.clone()),
+            cpu_request: self
+                .cpu_request
+                .clone()
+                .or_else(|| fallback.cpu_request.clone()),
+            cpu_limit: self
+                .cpu_limit
+                .clone()
+                .or_else(|| fallback.cpu_limit.clone()),
+            memory_request: self
+                .memory_request
+                .clone()
+                .or_else(|| fallback.memory_request.clone()),
+            memory_limit: self
+                .memory_limit
+                .clone()
+                .or_else(|| fallback.memory_limit.clone()),
+            gpu_limit: self.gpu_limit.or(fallback.gpu_limit),
+            executor_cores: self.executor_cores.or(fallback.executor_cores),
+            executor_memory: self
+                .executor_memory
+                .clone()
+                .or_else(|| fallback.executor_memory.clone()),
+            vault_role: self
+                .vault_role
+                .clone()
+                .or_else(|| fallback.vault_role.clone()),
+            vault_secrets: self
+                .vault_secrets
+                .clone()
+                .or_else(|| fallback.vault_secrets.clone()),
+            liveness_probe: self
+                .liveness_probe
+                .clone()
+                .or_else(|| fallback.liveness_probe.clone()),
+            readiness_probe: self
+                .readiness_probe
+                .clone()
+                .or_else(|| fallback.readiness_probe.clone()),
+            log_level: self
+                .log_level
+                .clone()
+                .or_else(|| fallback.log_level.clone()),
+            jvm_opts: self.jvm_opts.clone().or_else(|| fallback.jvm_opts.clone()),
+            env_from: self.env_from.clone().or_else(|| fallback.env_from.clone()),
+            env: self.env.clone().or_else(|| fallback.env.clone()),
+            topology_spread_constraints: self
+                .topology_spread_constraints
+                .clone()
+                .or_else(|| fallback.topology_spread_constraints.clone()),
+            priority_class_name: self
+                .priority_class_name
+                .clone()
+                .or_else(|| fallback.priority_class_name.clone()),
+            host_network: self.host_network.or(fallback.host_network),
+            dns_policy: self
+                .dns_policy
+                .clone()
+                .or_else(|| fallback.dns_policy.clone()),
+            dns_config: self
+                .dns_config
+                .clone()
+                .or_else(|| fallback.dns_config.clone()),
+            runtime_class_name: self
+                .runtime_class_name
+                .clone()
+                .or_else(|| fallback.runtime_class_name.clone()),
+            init_containers: self
+                .init_containers
+                .clone()
+                .or_else(|| fallback.init_containers.clone()),
+            sidecar_containers: self
+                .sidecar_containers
+                .clone()
+                .or_else(|| fallback.sidecar_containers.clone()),
+            termination_grace_period_seconds: self
+                .termination_grace_period_seconds
+                .or(fallback.termination_grace_period_seconds),
+            drain_timeout_seconds: self
+                .drain_timeout_seconds
+                .or(fallback.drain_timeout_seconds),
+            pre_stop_command: self
+                .pre_stop_command
+                .clone()
+                .or_else(|| fallback.pre_stop_command.clone()),
+            pod_security_context: self
+                .pod_security_context
+                .clone()
+                .or_else(|| fallback.pod_security_context.clone()),
+            container_security_context: self
+                .container_security_context
+                .clone()
+                .or_else(|| fallback.container_security_context.clone()),
+            pod_template_override: self
+                .pod_template_override
+                .clone()
+                .or_else(|| fallback.pod_template_override.clone()),
+        }
+    }
+}
+
+/// A secret the Vault agent injector renders into the pods.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultSecretSpec {
+    /// The Vault path of the secret, e.g. `database/creds/spark`
+    pub path: String,
+    /// The file the secret is written to, e.g. `/vault/secrets/db-creds`. Its name has to be
+    /// unique within the pod.
+    pub mount_path: String,
+}
+
+/// A container probe against the web UI port of the Spark process.
+///
+/// Unset fields keep the defaults of the operator.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeConfig {
+    pub check: Option<ProbeCheck>,
+    pub initial_delay_seconds: Option<i32>,
+    pub period_seconds: Option<i32>,
+    pub timeout_seconds: Option<i32>,
+    pub failure_threshold: Option<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProbeCheck {
+    /// HTTP GET request of the given path
+    HttpGet { path: String },
+    /// Opening a TCP connection
+    TcpSocket,
+}
+
+/// Schedules pods on nodes of a certain instance (machine) type.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodePoolSelector {
+    pub cloud_provider: CloudProvider,
+    pub instance_type: String,
+}
+
+impl NodePoolSelector {
+    /// The node label (key and value) selecting the configured instance type
+    pub fn node_label(&self) -> (String, String) {
+        let key = match &self.cloud_provider {
+            CloudProvider::Gke => LABEL_GKE_MACHINE_FAMILY,
+            CloudProvider::Eks | CloudProvider::Aks => LABEL_INSTANCE_TYPE,
+            CloudProvider::Custom(label_key) => label_key.as_str(),
+        };
+        (key.to_string(), self.instance_type.clone())
+    }
+}
+
+/// The cloud provider determines the node label that carries the instance type.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CloudProvider {
+    Gke,
+    Eks,
+    Aks,
+    /// Any other provider, using the given node label key.
+    Custom(String),
 }
 
 /// Reference to a single `Pod` that is a component of a [`SparkCluster`]
@@ -122,6 +985,28 @@ impl SparkCluster {
         ))
     }
 
+    /// The name of the role-level history server `Service`
+    pub fn history_server_role_service_name(&self) -> Option<String> {
+        Some(format!(
+            "{}-{}",
+            self.metadata.name.as_ref()?,
+            SparkRole::HistoryServer
+        ))
+    }
+
+    /// The name of the shared event log `PersistentVolumeClaim`
+    pub fn event_log_volume_claim_name(&self) -> Option<String> {
+        Some(format!("{}-history-events", self.metadata.name.as_ref()?))
+    }
+
+    /// The name of the `ServiceAccount` the pods run as
+    pub fn service_account_name(&self) -> Option<String> {
+        match &self.spec.service_account_name {
+            Some(service_account_name) => Some(service_account_name.clone()),
+            None => Some(format!("{}-{}", self.metadata.name.as_ref()?, APP_NAME)),
+        }
+    }
+
     /// Metadata about a server rolegroup
     pub fn server_rolegroup_ref(
         &self,
@@ -190,11 +1075,54 @@ impl SparkCluster {
             }))
     }
 
-    pub fn enable_monitoring(&self) -> Option<bool> {
+    /// The common configuration shared by all roles, if any
+    pub fn common_config(&self) -> Option<&CommonConfig> {
         self.spec
             .config
             .as_ref()
             .and_then(|common_configuration| common_configuration.config.as_ref())
+    }
+
+    /// The [`NodeConfig`] of a role group, merged with the one of its role
+    pub fn node_config(&self, role: &SparkRole, role_group: &str) -> NodeConfig {
+        let (role_config, group_config) = match role {
+            SparkRole::Master => {
+                let role = self.spec.masters.as_ref();
+                (
+                    role.and_then(|r| r.config.config.as_ref()).map(|c| &c.node),
+                    role.and_then(|r| r.role_groups.get(role_group))
+                        .and_then(|rg| rg.config.config.as_ref())
+                        .map(|c| &c.node),
+                )
+            }
+            SparkRole::Worker => {
+                let role = self.spec.workers.as_ref();
+                (
+                    role.and_then(|r| r.config.config.as_ref()).map(|c| &c.node),
+                    role.and_then(|r| r.role_groups.get(role_group))
+                        .and_then(|rg| rg.config.config.as_ref())
+                        .map(|c| &c.node),
+                )
+            }
+            SparkRole::HistoryServer => {
+                let role = self.spec.history_servers.as_ref();
+                (
+                    role.and_then(|r| r.config.config.as_ref()).map(|c| &c.node),
+                    role.and_then(|r| r.role_groups.get(role_group))
+                        .and_then(|rg| rg.config.config.as_ref())
+                        .map(|c| &c.node),
+                )
+            }
+        };
+        let role_config = role_config.cloned().unwrap_or_default();
+        match group_config {
+            Some(group_config) => group_config.merge(&role_config),
+            None => role_config,
+        }
+    }
+
+    pub fn enable_monitoring(&self) -> Option<bool> {
+        self.common_config()
             .and_then(|common_config| common_config.enable_monitoring)
     }
 }
@@ -237,9 +1165,18 @@ impl Configuration for MasterConfig {
                         Some(web_ui_port.to_string()),
                     );
                 }
+                if let Some(jvm_opts) = &self.node.jvm_opts {
+                    config.insert(SPARK_ENV_MASTER_OPTS.to_string(), Some(jvm_opts.clone()));
+                }
             }
             SPARK_DEFAULTS_CONF => {
-                add_common_spark_defaults(role_name, &mut config, &resource.spec)
+                add_common_spark_defaults(role_name, &mut config, &resource.spec);
+                if let Some(rest_port) = &self.master_rest_port {
+                    config.insert(
+                        SPARK_DEFAULTS_MASTER_REST_PORT.to_string(),
+                        Some(rest_port.to_string()),
+                    );
+                }
             }
             _ => {}
         }
@@ -277,14 +1214,11 @@ impl Configuration for WorkerConfig {
 
         match file {
             SPARK_ENV_SH => {
-                if let Some(cores) = &self.cores {
+                if let Some(cores) = self.spark_worker_cores() {
                     config.insert(SPARK_ENV_WORKER_CORES.to_string(), Some(cores.to_string()));
                 }
-                if let Some(memory) = &self.memory {
-                    config.insert(
-                        SPARK_ENV_WORKER_MEMORY.to_string(),
-                        Some(memory.to_string()),
-                    );
+                if let Some(memory) = self.spark_worker_memory() {
+                    config.insert(SPARK_ENV_WORKER_MEMORY.to_string(), Some(memory));
                 }
                 if let Some(port) = &self.worker_port {
                     config.insert(SPARK_ENV_WORKER_PORT.to_string(), Some(port.to_string()));
@@ -295,6 +1229,10 @@ impl Configuration for WorkerConfig {
                         Some(web_ui_port.to_string()),
                     );
                 }
+                // The GPU options are added by the controller from the merged node config
+                if let Some(jvm_opts) = &self.node.jvm_opts {
+                    config.insert(SPARK_ENV_WORKER_OPTS.to_string(), Some(jvm_opts.clone()));
+                }
             }
             SPARK_DEFAULTS_CONF => {
                 add_common_spark_defaults(role_name, &mut config, &resource.spec)
@@ -334,7 +1272,11 @@ impl Configuration for HistoryServerConfig {
         let mut config = BTreeMap::new();
 
         match file {
-            SPARK_ENV_SH => {}
+            SPARK_ENV_SH => {
+                if let Some(jvm_opts) = &self.node.jvm_opts {
+                    config.insert(SPARK_ENV_HISTORY_OPTS.to_string(), Some(jvm_opts.clone()));
+                }
+            }
             SPARK_DEFAULTS_CONF => {
                 if let Some(store_path) = &self.store_path {
                     config.insert(
@@ -363,12 +1305,170 @@ fn add_common_spark_defaults(
     config: &mut BTreeMap<String, Option<String>>,
     spec: &SparkClusterSpec,
 ) {
+    if let Some(ha_config) = &spec.ha_config {
+        config.insert(
+            SPARK_DEFAULTS_DEPLOY_RECOVERY_MODE.to_string(),
+            Some("ZOOKEEPER".to_string()),
+        );
+        config.insert(
+            SPARK_DEFAULTS_DEPLOY_ZOOKEEPER_URL.to_string(),
+            Some(ha_config.zookeeper_url.clone()),
+        );
+        if let Some(zookeeper_dir) = &ha_config.zookeeper_dir {
+            config.insert(
+                SPARK_DEFAULTS_DEPLOY_ZOOKEEPER_DIR.to_string(),
+                Some(zookeeper_dir.clone()),
+            );
+        }
+    }
+
+    if let Some(threshold) = spec.auto_broadcast_join_threshold() {
+        config.insert(
+            SPARK_DEFAULTS_AUTO_BROADCAST_JOIN_THRESHOLD.to_string(),
+            Some(threshold.to_string()),
+        );
+    }
+
+    if let Some(SparkSchedulerMode::Fair(_)) = &spec.scheduler_mode {
+        config.insert(
+            SPARK_DEFAULTS_SCHEDULER_MODE.to_string(),
+            Some("FAIR".to_string()),
+        );
+    }
+
+    if let Some(dpp) = &spec.dynamic_partition_pruning {
+        config.insert(
+            SPARK_DEFAULTS_DPP_ENABLED.to_string(),
+            Some(dpp.enabled.to_string()),
+        );
+        config.insert(
+            SPARK_DEFAULTS_DPP_REUSE_BROADCAST_ONLY.to_string(),
+            Some(dpp.use_broadcasting_cache.to_string()),
+        );
+        config.insert(
+            SPARK_DEFAULTS_DPP_FALLBACK_FILTER_RATIO.to_string(),
+            Some(dpp.fallback_filter_ratio.to_string()),
+        );
+    }
+
+    if let Some(dynamic_allocation) = spec.dynamic_allocation.as_ref().filter(|d| d.enabled) {
+        config.insert(
+            SPARK_DEFAULTS_DYNAMIC_ALLOCATION_ENABLED.to_string(),
+            Some("true".to_string()),
+        );
+        config.insert(
+            SPARK_DEFAULTS_DYNAMIC_ALLOCATION_MIN_EXECUTORS.to_string(),
+            Some(dynamic_allocation.min_executors.to_string()),
+        );
+        config.insert(
+            SPARK_DEFAULTS_DYNAMIC_ALLOCATION_MAX_EXECUTORS.to_string(),
+            Some(dynamic_allocation.max_executors.to_string()),
+        );
+        if let Some(initial_executors) = dynamic_allocation.initial_executors {
+            config.insert(
+                SPARK_DEFAULTS_DYNAMIC_ALLOCATION_INITIAL_EXECUTORS.to_string(),
+                Some(initial_executors.to_string()),
+            );
+        }
+        // The workers read this as well and start the shuffle service
+        config.insert(
+            SPARK_DEFAULTS_SHUFFLE_SERVICE_ENABLED.to_string(),
+            Some("true".to_string()),
+        );
+    }
+
+    // The history server reads the codec from the name of each event log file
+    if spec.event_log_compress.unwrap_or(true) {
+        config.insert(
+            SPARK_DEFAULTS_EVENT_LOG_COMPRESS.to_string(),
+            Some("true".to_string()),
+        );
+        config.insert(
+            SPARK_DEFAULTS_EVENT_LOG_COMPRESSION_CODEC.to_string(),
+            Some(
+                spec.event_log_codec
+                    .as_deref()
+                    .unwrap_or(DEFAULT_EVENT_LOG_COMPRESSION_CODEC)
+                    .to_string(),
+            ),
+        );
+    }
+
+    if spec.hive_enabled() {
+        config.insert(
+            SPARK_DEFAULTS_SQL_CATALOG_IMPLEMENTATION.to_string(),
+            Some("hive".to_string()),
+        );
+    }
+    if let Some(uris) = spec
+        .hive_metastore_uris
+        .as_ref()
+        .filter(|uris| !uris.is_empty())
+    {
+        config.insert(
+            SPARK_DEFAULTS_HIVE_METASTORE_URIS.to_string(),
+            Some(uris.join(",")),
+        );
+    }
+
+    if let Some(sql_extensions) = spec.sql_extensions.as_ref().filter(|e| !e.is_empty()) {
+        config.insert(
+            SPARK_DEFAULTS_SQL_EXTENSIONS.to_string(),
+            Some(sql_extensions.join(",")),
+        );
+    }
+
+    for (name, catalog) in spec.catalogs.iter().flatten() {
+        let catalog_property = format!("{}.{}", SPARK_DEFAULTS_SQL_CATALOG, name);
+        for (option, value) in catalog.options.iter().flatten() {
+            config.insert(
+                format!("{}.{}", catalog_property, option),
+                Some(value.clone()),
+            );
+        }
+        config.insert(catalog_property, Some(catalog.class.clone()));
+    }
+
+    if let Some(extra_jars) = spec.extra_jars.as_ref().filter(|j| !j.is_empty()) {
+        config.insert(SPARK_DEFAULTS_JARS.to_string(), Some(extra_jars.join(",")));
+    }
+
+    if spec.aws_role_arn.is_some() {
+        config.insert(
+            SPARK_DEFAULTS_S3A_CREDENTIALS_PROVIDER.to_string(),
+            Some(WEB_IDENTITY_CREDENTIALS_PROVIDER_CLASS.to_string()),
+        );
+    }
+
+    if spec.gcp_service_account.is_some() {
+        config.insert(
+            SPARK_DEFAULTS_GOOGLE_CLOUD_AUTH_TYPE.to_string(),
+            Some("APPLICATION_DEFAULT".to_string()),
+        );
+    }
+
+    if spec.authentication.is_some() {
+        config.insert(
+            SPARK_DEFAULTS_AUTHENTICATE.to_string(),
+            Some("true".to_string()),
+        );
+        // Spark expands the environment variable, so the secret never shows up in the ConfigMap
+        config.insert(
+            SPARK_DEFAULTS_AUTHENTICATE_SECRET.to_string(),
+            Some(format!("${{env:{}}}", SPARK_AUTHENTICATE_SECRET_ENV)),
+        );
+    }
+
     if let Some(CommonConfiguration {
         config: Some(common_config),
         ..
     }) = &spec.config
     {
-        if let Some(secret) = &common_config.secret {
+        if let Some(secret) = common_config
+            .secret
+            .as_ref()
+            .filter(|_| spec.authentication.is_none())
+        {
             config.insert(
                 SPARK_DEFAULTS_AUTHENTICATE.to_string(),
                 Some("true".to_string()),
@@ -380,6 +1480,43 @@ fn add_common_spark_defaults(
             );
         }
 
+        for profile in common_config.resource_profiles.iter().flatten() {
+            config.insert(
+                format!(
+                    "{}.{}.executor.cores",
+                    SPARK_DEFAULTS_RESOURCE_PROFILES, profile.id
+                ),
+                Some(profile.executor_cores.to_string()),
+            );
+            config.insert(
+                format!(
+                    "{}.{}.executor.memory",
+                    SPARK_DEFAULTS_RESOURCE_PROFILES, profile.id
+                ),
+                Some(profile.executor_memory.clone()),
+            );
+        }
+
+        match &common_config.serializer {
+            None | Some(SparkSerializer::Java) => {}
+            Some(SparkSerializer::Kryo) => {
+                config.insert(
+                    SPARK_DEFAULTS_SERIALIZER.to_string(),
+                    Some(KRYO_SERIALIZER_CLASS.to_string()),
+                );
+            }
+            Some(SparkSerializer::KryoWithClasses(classes)) => {
+                config.insert(
+                    SPARK_DEFAULTS_SERIALIZER.to_string(),
+                    Some(KRYO_SERIALIZER_CLASS.to_string()),
+                );
+                config.insert(
+                    SPARK_DEFAULTS_KRYO_CLASSES_TO_REGISTER.to_string(),
+                    Some(classes.join(",")),
+                );
+            }
+        }
+
         let max_port_retries = &common_config.max_port_retries.unwrap_or(0);
         config.insert(
             SPARK_DEFAULTS_PORT_MAX_RETRIES.to_string(),
@@ -396,6 +1533,36 @@ fn add_common_spark_defaults(
             SPARK_DEFAULTS_EVENT_LOG_DIR.to_string(),
             Some(log_dir.to_string()),
         );
+        if is_s3_url(log_dir) {
+            // Hadoop only maps s3a:// to the S3A filesystem by default
+            if log_dir.starts_with("s3://") {
+                config.insert(
+                    SPARK_DEFAULTS_S3_IMPL.to_string(),
+                    Some(S3A_FILE_SYSTEM_CLASS.to_string()),
+                );
+            }
+            if let Some(endpoint) = common_config
+                .s3
+                .as_ref()
+                .and_then(|s3| s3.endpoint.as_ref())
+            {
+                config.insert(
+                    SPARK_DEFAULTS_S3A_ENDPOINT.to_string(),
+                    Some(endpoint.clone()),
+                );
+            }
+        }
+        // Without a namenode in the URL (hdfs:///path) the one of the core-site.xml is used
+        if let Some(namenode) = log_dir
+            .strip_prefix("hdfs://")
+            .and_then(|path| path.split('/').next())
+            .filter(|namenode| !namenode.is_empty())
+        {
+            config.insert(
+                SPARK_DEFAULTS_HADOOP_DEFAULT_FS.to_string(),
+                Some(format!("hdfs://{}", namenode)),
+            );
+        }
     }
 }
 
@@ -426,3 +1593,176 @@ pub enum SparkRole {
     #[strum(serialize = "history-server")]
     HistoryServer,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_amounts_are_parsed_into_mib() {
+        assert_eq!(parse_memory_mb("512"), Some(512));
+        assert_eq!(parse_memory_mb("512m"), Some(512));
+        assert_eq!(parse_memory_mb("8Gi"), Some(8192));
+        assert_eq!(parse_memory_mb(" 2g "), Some(2048));
+        assert_eq!(parse_memory_mb("1t"), Some(1024 * 1024));
+        assert_eq!(parse_memory_mb("2048k"), Some(2));
+        assert_eq!(parse_memory_mb("8 GiB"), None);
+        assert_eq!(parse_memory_mb("lots"), None);
+    }
+
+    #[test]
+    fn broadcast_join_threshold_is_shared_by_the_concurrent_jobs() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              autoBroadcastJoinThresholdFraction: 0.01
+              maxConcurrentJobs: 2
+              workers:
+                config:
+                  memory: 4g
+                roleGroups:
+                  default:
+                    replicas: 2
+                  large:
+                    replicas: 1
+                    config:
+                      memory: 8g
+            ",
+        )
+        .unwrap();
+        assert_eq!(sc.spec.total_worker_memory_mb(), Some(16384));
+        // 1% of 16 GiB for each of 2 jobs
+        assert_eq!(
+            sc.spec.auto_broadcast_join_threshold(),
+            Some((0.01 * 16384.0 / 2.0 * 1024.0 * 1024.0) as u64)
+        );
+    }
+
+    #[test]
+    fn broadcast_join_threshold_needs_the_memory_of_all_workers() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              autoBroadcastJoinThresholdFraction: 0.01
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 2
+                    config:
+                      memory: 4g
+                  unknown:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert_eq!(sc.spec.total_worker_memory_mb(), None);
+        assert_eq!(sc.spec.auto_broadcast_join_threshold(), None);
+    }
+
+    #[test]
+    fn worker_memory_leaves_room_for_the_worker_daemon() {
+        let worker = |memory: Option<&str>, memory_limit: Option<&str>| WorkerConfig {
+            memory: memory.map(str::to_string),
+            node: NodeConfig {
+                memory_limit: memory_limit.map(str::to_string),
+                ..NodeConfig::default()
+            },
+            ..WorkerConfig::default()
+        };
+        assert_eq!(
+            worker(None, Some("8Gi")).spark_worker_memory().as_deref(),
+            Some("7168m")
+        );
+        // At least half of small limits
+        assert_eq!(
+            worker(None, Some("1Gi")).spark_worker_memory().as_deref(),
+            Some("512m")
+        );
+        assert_eq!(
+            worker(Some("6g"), Some("8Gi"))
+                .spark_worker_memory()
+                .as_deref(),
+            Some("6g")
+        );
+        assert_eq!(worker(None, None).spark_worker_memory(), None);
+    }
+
+    #[test]
+    fn cpu_quantities_are_parsed_into_millicores() {
+        assert_eq!(parse_cpu_millis("500m"), Some(500));
+        assert_eq!(parse_cpu_millis("2"), Some(2000));
+        assert_eq!(parse_cpu_millis("1.5"), Some(1500));
+        assert_eq!(parse_cpu_millis("-1"), None);
+        assert_eq!(parse_cpu_millis("two"), None);
+    }
+
+    #[test]
+    fn worker_cores_are_derived_from_the_cpu_limit() {
+        let worker = |cores: Option<usize>, cpu_limit: Option<&str>| WorkerConfig {
+            cores,
+            node: NodeConfig {
+                cpu_limit: cpu_limit.map(str::to_string),
+                ..NodeConfig::default()
+            },
+            ..WorkerConfig::default()
+        };
+        assert_eq!(worker(None, Some("3500m")).spark_worker_cores(), Some(3));
+        // At least one core for fractional limits
+        assert_eq!(worker(None, Some("500m")).spark_worker_cores(), Some(1));
+        assert_eq!(worker(Some(2), Some("4")).spark_worker_cores(), Some(2));
+        assert_eq!(worker(None, None).spark_worker_cores(), None);
+    }
+
+    #[test]
+    fn scale_subresource_scales_the_default_worker_role_group() {
+        use stackable_operator::kube::CustomResourceExt;
+
+        let crd = SparkCluster::crd();
+        let scale = crd.spec.versions[0]
+            .subresources
+            .as_ref()
+            .and_then(|subresources| subresources.scale.as_ref())
+            .unwrap();
+        assert_eq!(
+            scale.spec_replicas_path,
+            ".spec.workers.roleGroups.default.replicas"
+        );
+        assert_eq!(scale.status_replicas_path, ".status.workerCount");
+    }
+
+    #[test]
+    fn printer_columns_show_the_cluster_status() {
+        use stackable_operator::kube::CustomResourceExt;
+
+        let crd = SparkCluster::crd();
+        let columns = crd.spec.versions[0]
+            .additional_printer_columns
+            .iter()
+            .flatten()
+            .map(|column| (column.name.as_str(), column.json_path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns,
+            vec![
+                ("Masters", ".status.masterCount"),
+                ("Workers", ".status.workerCount"),
+                ("Version", ".spec.version"),
+                ("Phase", ".status.phase"),
+                ("Active_Apps", ".status.activeApplicationCount"),
+                ("Age", ".metadata.creationTimestamp"),
+            ]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use snafu::Snafu;
+use stackable_operator::kube;
 use stackable_operator::kube::runtime::reflector::ObjectRef;
 use stackable_operator::role_utils::RoleGroupRef;
 use stackable_spark_crd::SparkCluster;
@@ -23,11 +24,86 @@ pub enum Error {
         source: stackable_operator::error::Error,
         sc: ObjectRef<SparkCluster>,
     },
+    #[snafu(display("failed to apply ServiceAccount for {}", sc))]
+    ApplyServiceAccount {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to apply master PodDisruptionBudget for {}", sc))]
+    ApplyMasterPodDisruptionBudget {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to delete master PodDisruptionBudget for {}", sc))]
+    DeleteMasterPodDisruptionBudget {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to apply worker PodDisruptionBudget for {}", sc))]
+    ApplyWorkerPodDisruptionBudget {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to delete worker PodDisruptionBudget for {}", sc))]
+    DeleteWorkerPodDisruptionBudget {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to apply event log PersistentVolumeClaim for {}", sc))]
+    ApplyEventLogVolumeClaim {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to apply history server Service for {}", sc))]
+    ApplyHistoryServerService {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to delete history server Service for {}", sc))]
+    DeleteHistoryServerService {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to apply master Ingress for {}", sc))]
+    ApplyMasterIngress {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to delete master Ingress for {}", sc))]
+    DeleteMasterIngress {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to apply history server Ingress for {}", sc))]
+    ApplyHistoryServerIngress {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to delete history server Ingress for {}", sc))]
+    DeleteHistoryServerIngress {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to apply NetworkPolicy for {}", sc))]
+    ApplyNetworkPolicy {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to delete NetworkPolicy for {}", sc))]
+    DeleteNetworkPolicy {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
     #[snafu(display("failed to apply Service for {}", rolegroup))]
     ApplyRoleGroupService {
         source: stackable_operator::error::Error,
         rolegroup: RoleGroupRef<SparkCluster>,
     },
+    #[snafu(display("failed to apply the pod template override of {}", rolegroup))]
+    PodTemplateOverride {
+        source: serde_json::Error,
+        rolegroup: RoleGroupRef<SparkCluster>,
+    },
     #[snafu(display("failed to build ConfigMap for {}", rolegroup))]
     BuildRoleGroupConfig {
         source: stackable_operator::error::Error,
@@ -43,6 +119,99 @@ pub enum Error {
         source: stackable_operator::error::Error,
         rolegroup: RoleGroupRef<SparkCluster>,
     },
+    #[snafu(display(
+        "failed to recreate StatefulSet for {} with changed volume claim templates",
+        rolegroup
+    ))]
+    RecreateRoleGroupStatefulSet {
+        source: kube::Error,
+        rolegroup: RoleGroupRef<SparkCluster>,
+    },
+    #[snafu(display("failed to get StatefulSet for {}", rolegroup))]
+    GetRoleGroupStatefulSet {
+        source: kube::Error,
+        rolegroup: RoleGroupRef<SparkCluster>,
+    },
+    #[snafu(display("{} defines no master instances, at least one is required", sc))]
+    InvalidMasterInstanceCount { sc: ObjectRef<SparkCluster> },
+    #[snafu(display(
+        "high availability requires at least {} masters, found {}",
+        minimum,
+        count
+    ))]
+    MinimumMasterCountForHA { count: u32, minimum: u32 },
+    #[snafu(display(
+        "{} overrides the authentication secret, it must be the same for all role groups",
+        rolegroup
+    ))]
+    InconsistentAuthenticationSecret {
+        rolegroup: RoleGroupRef<SparkCluster>,
+    },
+    #[snafu(display(
+        "{} sets {} both via the role group and via configOverrides",
+        rolegroup,
+        property
+    ))]
+    ConflictingExecutorConfig {
+        rolegroup: RoleGroupRef<SparkCluster>,
+        property: String,
+    },
+    #[snafu(display("invalid master URL target '{}', expected <namespace>/<name>", target))]
+    InvalidPublishMasterUrlTarget { target: String },
+    #[snafu(display("failed to publish master URL to ConfigMap {}", target))]
+    PublishMasterUrlFailed {
+        source: stackable_operator::error::Error,
+        target: String,
+    },
+    #[snafu(display("failed to delete published master URL ConfigMap {}", target))]
+    DeletePublishedMasterUrl {
+        source: stackable_operator::error::Error,
+        target: String,
+    },
+    #[snafu(display("failed to update finalizers of {}", sc))]
+    UpdateFinalizers {
+        source: kube::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("resource profile '{}' is defined more than once", id))]
+    DuplicateResourceProfile { id: String },
+    #[snafu(display("resource profile id '{}' is reserved by Spark", id))]
+    ReservedResourceProfile { id: String },
+    #[snafu(display(
+        "dynamic partition pruning fallback filter ratio {} is not between 0.0 and 1.0",
+        ratio
+    ))]
+    InvalidFallbackFilterRatio { ratio: f64 },
+    #[snafu(display(
+        "event log directory {} is on S3 but neither s3.credentialsSecret nor awsRoleArn is set",
+        log_dir
+    ))]
+    InvalidEventLogConfig { log_dir: String },
+    #[snafu(display(
+        "event log directory {} is not a valid GCS URL, expected gs://<bucket>/<path>",
+        log_dir
+    ))]
+    InvalidGcsEventLogDir { log_dir: String },
+    #[snafu(display("failed to list managed Secrets for {}", sc))]
+    ListManagedSecrets {
+        source: kube::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
+    #[snafu(display("failed to list Nodes matching the node selector of {}", rolegroup))]
+    ListNodes {
+        source: kube::Error,
+        rolegroup: RoleGroupRef<SparkCluster>,
+    },
+    #[snafu(display("failed to list Nodes with GPUs for {}", rolegroup))]
+    ListGpuNodes {
+        source: kube::Error,
+        rolegroup: RoleGroupRef<SparkCluster>,
+    },
+    #[snafu(display("failed to update status of {}", sc))]
+    UpdateStatus {
+        source: stackable_operator::error::Error,
+        sc: ObjectRef<SparkCluster>,
+    },
     #[snafu(display("invalid product config for {}", sc))]
     InvalidProductConfig {
         source: stackable_operator::error::Error,
@@ -63,4 +232,142 @@ pub enum Error {
         source: <i32 as FromStr>::Err,
         rolegroup_ref: RoleGroupRef<SparkCluster>,
     },
+    #[snafu(display("failed to reconcile {} ({} consecutive failures)", sc, failures))]
+    Reconcile {
+        source: Box<Error>,
+        sc: ObjectRef<SparkCluster>,
+        failures: u32,
+    },
+}
+
+impl Error {
+    /// Whether the error was caused by the Kubernetes API server being unreachable, as opposed
+    /// to the API server rejecting a request.
+    pub fn is_api_unavailable(&self) -> bool {
+        let source = match self {
+            Error::Reconcile { source, .. } => return source.is_api_unavailable(),
+            Error::ApplyRoleService { source, .. }
+            | Error::ApplyServiceAccount { source, .. }
+            | Error::ApplyMasterPodDisruptionBudget { source, .. }
+            | Error::DeleteMasterPodDisruptionBudget { source, .. }
+            | Error::ApplyWorkerPodDisruptionBudget { source, .. }
+            | Error::DeleteWorkerPodDisruptionBudget { source, .. }
+            | Error::ApplyEventLogVolumeClaim { source, .. }
+            | Error::ApplyHistoryServerService { source, .. }
+            | Error::DeleteHistoryServerService { source, .. }
+            | Error::ApplyMasterIngress { source, .. }
+            | Error::DeleteMasterIngress { source, .. }
+            | Error::ApplyHistoryServerIngress { source, .. }
+            | Error::DeleteHistoryServerIngress { source, .. }
+            | Error::ApplyNetworkPolicy { source, .. }
+            | Error::DeleteNetworkPolicy { source, .. }
+            | Error::ApplyRoleGroupService { source, .. }
+            | Error::ApplyRoleGroupConfig { source, .. }
+            | Error::ApplyRoleGroupStatefulSet { source, .. }
+            | Error::PublishMasterUrlFailed { source, .. }
+            | Error::DeletePublishedMasterUrl { source, .. }
+            | Error::UpdateStatus { source, .. } => source,
+            Error::ListManagedSecrets { source, .. }
+            | Error::ListNodes { source, .. }
+            | Error::ListGpuNodes { source, .. }
+            | Error::UpdateFinalizers { source, .. }
+            | Error::RecreateRoleGroupStatefulSet { source, .. }
+            | Error::GetRoleGroupStatefulSet { source, .. } => return is_kube_unavailable(source),
+            // Listed explicitly, so that new variants have to be classified as well
+            Error::ObjectMissingMetadataForOwnerRef { .. }
+            | Error::ObjectHasNoVersion { .. }
+            | Error::MissingRoleGroup { .. }
+            | Error::GlobalServiceNameNotFound { .. }
+            | Error::PodTemplateOverride { .. }
+            | Error::BuildRoleGroupConfig { .. }
+            | Error::InvalidMasterInstanceCount { .. }
+            | Error::MinimumMasterCountForHA { .. }
+            | Error::InconsistentAuthenticationSecret { .. }
+            | Error::ConflictingExecutorConfig { .. }
+            | Error::InvalidPublishMasterUrlTarget { .. }
+            | Error::DuplicateResourceProfile { .. }
+            | Error::ReservedResourceProfile { .. }
+            | Error::InvalidFallbackFilterRatio { .. }
+            | Error::InvalidEventLogConfig { .. }
+            | Error::InvalidGcsEventLogDir { .. }
+            | Error::InvalidProductConfig { .. }
+            | Error::SerializeSparkDefaults { .. }
+            | Error::SerializeSparkEnv { .. }
+            | Error::MasterRoleGroupDefaultExpected
+            | Error::InvalidPort { .. } => return false,
+        };
+        matches!(
+            source,
+            stackable_operator::error::Error::KubeError { source, .. } if is_kube_unavailable(source)
+        )
+    }
+}
+
+/// Whether a request of the Kubernetes client failed because the API server could not be
+/// reached, as opposed to the API server answering with an error.
+fn is_kube_unavailable(error: &kube::Error) -> bool {
+    matches!(error, kube::Error::HyperError(_) | kube::Error::Service(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn unreachable() -> kube::Error {
+        kube::Error::Service(Box::new(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "connection refused",
+        )))
+    }
+
+    fn rolegroup() -> RoleGroupRef<SparkCluster> {
+        RoleGroupRef {
+            cluster: ObjectRef::new("simple").within("default"),
+            role: "slave".to_string(),
+            role_group: "default".to_string(),
+        }
+    }
+
+    #[test]
+    fn list_errors_of_unreachable_api_server_are_unavailable() {
+        let errors = vec![
+            Error::ListManagedSecrets {
+                source: unreachable(),
+                sc: ObjectRef::new("simple").within("default"),
+            },
+            Error::ListNodes {
+                source: unreachable(),
+                rolegroup: rolegroup(),
+            },
+            Error::ListGpuNodes {
+                source: unreachable(),
+                rolegroup: rolegroup(),
+            },
+        ];
+        for error in errors {
+            assert!(error.is_api_unavailable(), "{}", error);
+        }
+    }
+
+    #[test]
+    fn wrapped_errors_are_classified_by_their_source() {
+        let error = Error::Reconcile {
+            source: Box::new(Error::ListNodes {
+                source: unreachable(),
+                rolegroup: rolegroup(),
+            }),
+            sc: ObjectRef::new("simple").within("default"),
+            failures: 1,
+        };
+        assert!(error.is_api_unavailable());
+    }
+
+    #[test]
+    fn invalid_specs_are_not_unavailable() {
+        let error = Error::InvalidMasterInstanceCount {
+            sc: ObjectRef::new("simple").within("default"),
+        };
+        assert!(!error.is_api_unavailable());
+    }
 }
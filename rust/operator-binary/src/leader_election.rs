@@ -0,0 +1,207 @@
+//! Leader election between operator replicas via a `coordination.k8s.io` [`Lease`].
+//!
+//! Only the replica holding the lease runs the controller. It renews the lease periodically and
+//! stops when it loses the lease, so that one of the standby replicas can take over.
+
+use anyhow::bail;
+use stackable_operator::k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::MicroTime,
+    chrono::{self, Utc},
+};
+use stackable_operator::kube::{
+    self,
+    api::{ObjectMeta, PostParams},
+    Api,
+};
+use std::time::{Duration, Instant};
+
+/// How long a lease stays valid without being renewed.
+const LEASE_DURATION: Duration = Duration::from_secs(15);
+/// How often the leader renews the lease.
+const RENEW_PERIOD: Duration = Duration::from_secs(5);
+/// How often standby replicas try to acquire the lease.
+const RETRY_PERIOD: Duration = Duration::from_secs(2);
+/// The namespace of the operator pod, used for the lease unless configured otherwise.
+pub const SERVICE_ACCOUNT_NAMESPACE_FILE: &str =
+    "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+pub struct LeaderElection {
+    api: Api<Lease>,
+    lease_name: String,
+    holder_identity: String,
+}
+
+impl LeaderElection {
+    /// Configure the leader election from the environment, `None` if `SPARK_OPERATOR_LEASE_NAME`
+    /// is not set. The lease namespace defaults to the namespace of the operator pod and the
+    /// holder identity to the pod name (`HOSTNAME`).
+    pub fn from_env(client: &stackable_operator::client::Client) -> anyhow::Result<Option<Self>> {
+        let lease_name = match std::env::var("SPARK_OPERATOR_LEASE_NAME") {
+            Ok(lease_name) => lease_name,
+            Err(_) => return Ok(None),
+        };
+        let namespace = match std::env::var("SPARK_OPERATOR_LEASE_NAMESPACE") {
+            Ok(namespace) => namespace,
+            Err(_) => std::fs::read_to_string(SERVICE_ACCOUNT_NAMESPACE_FILE)?
+                .trim()
+                .to_string(),
+        };
+        let holder_identity = match std::env::var("SPARK_OPERATOR_LEASE_HOLDER") {
+            Ok(holder_identity) => holder_identity,
+            Err(_) => std::env::var("HOSTNAME")?,
+        };
+        Ok(Some(LeaderElection {
+            api: client.get_namespaced_api(&namespace),
+            lease_name,
+            holder_identity,
+        }))
+    }
+
+    /// Wait until this replica holds the lease.
+    pub async fn acquire(&self) {
+        tracing::info!(
+            lease = %self.lease_name,
+            holder = %self.holder_identity,
+            "Waiting for the leader lease"
+        );
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(error) => tracing::warn!(%error, "Failed to acquire the leader lease"),
+            }
+            tokio::time::sleep(RETRY_PERIOD).await;
+        }
+        tracing::info!(lease = %self.lease_name, "Acquired the leader lease");
+    }
+
+    /// Renew the lease until it is lost, which is returned as error.
+    pub async fn hold(&self) -> anyhow::Result<()> {
+        let mut last_renewal = Instant::now();
+        loop {
+            tokio::time::sleep(RENEW_PERIOD).await;
+            match self.try_acquire_or_renew().await {
+                Ok(true) => last_renewal = Instant::now(),
+                Ok(false) => bail!("the leader lease {} was taken over", self.lease_name),
+                Err(error) => {
+                    if last_renewal.elapsed() >= LEASE_DURATION {
+                        bail!(
+                            "failed to renew the leader lease {} in time: {}",
+                            self.lease_name,
+                            error
+                        );
+                    }
+                    tracing::warn!(%error, "Failed to renew the leader lease, retrying");
+                }
+            }
+        }
+    }
+
+    /// Take the lease if it is free or expired, or renew it if it is already held.
+    /// Returns whether this replica holds the lease afterwards.
+    async fn try_acquire_or_renew(&self) -> Result<bool, kube::Error> {
+        let now = Utc::now();
+        let mut lease = match self.api.get(&self.lease_name).await {
+            Ok(lease) => lease,
+            Err(kube::Error::Api(response)) if response.code == 404 => {
+                let lease = Lease {
+                    metadata: ObjectMeta {
+                        name: Some(self.lease_name.clone()),
+                        ..ObjectMeta::default()
+                    },
+                    spec: Some(self.lease_spec(Some(MicroTime(now)), now, 0)),
+                };
+                return accept_conflict(self.api.create(&PostParams::default(), &lease).await);
+            }
+            Err(error) => return Err(error),
+        };
+
+        let spec = lease.spec.take().unwrap_or_default();
+        let held = spec.holder_identity.as_ref() == Some(&self.holder_identity);
+        if !held && !lease_expired(&spec, now) {
+            return Ok(false);
+        }
+
+        let transitions = spec.lease_transitions.unwrap_or(0);
+        lease.spec = Some(if held {
+            self.lease_spec(spec.acquire_time, now, transitions)
+        } else {
+            self.lease_spec(Some(MicroTime(now)), now, transitions + 1)
+        });
+        // The read resourceVersion makes concurrent takeovers conflict instead of both succeeding
+        accept_conflict(
+            self.api
+                .replace(&self.lease_name, &PostParams::default(), &lease)
+                .await,
+        )
+    }
+
+    fn lease_spec(
+        &self,
+        acquire_time: Option<MicroTime>,
+        renew_time: chrono::DateTime<Utc>,
+        lease_transitions: i32,
+    ) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: Some(self.holder_identity.clone()),
+            lease_duration_seconds: Some(LEASE_DURATION.as_secs() as i32),
+            acquire_time,
+            renew_time: Some(MicroTime(renew_time)),
+            lease_transitions: Some(lease_transitions),
+        }
+    }
+}
+
+/// Whether the holder of a lease failed to renew it within its duration, leases that were never
+/// renewed are expired.
+fn lease_expired(spec: &LeaseSpec, now: chrono::DateTime<Utc>) -> bool {
+    match &spec.renew_time {
+        Some(MicroTime(renew_time)) => {
+            *renew_time + chrono::Duration::seconds(spec.lease_duration_seconds.unwrap_or(0).into())
+                < now
+        }
+        None => true,
+    }
+}
+
+/// A conflict means that another replica changed the lease first.
+fn accept_conflict(result: Result<Lease, kube::Error>) -> Result<bool, kube::Error> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(response)) if response.code == 409 => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leases_expire_after_their_duration_without_renewal() {
+        let now = Utc::now();
+        let lease = |renewed_seconds_ago: Option<i64>| LeaseSpec {
+            holder_identity: Some("spark-operator-0".to_string()),
+            lease_duration_seconds: Some(15),
+            renew_time: renewed_seconds_ago
+                .map(|seconds| MicroTime(now - chrono::Duration::seconds(seconds))),
+            ..LeaseSpec::default()
+        };
+        assert!(!lease_expired(&lease(Some(5)), now));
+        assert!(!lease_expired(&lease(Some(15)), now));
+        assert!(lease_expired(&lease(Some(16)), now));
+        assert!(lease_expired(&lease(None), now));
+    }
+
+    #[test]
+    fn leases_without_duration_expire_right_away() {
+        let now = Utc::now();
+        let lease = LeaseSpec {
+            holder_identity: Some("spark-operator-0".to_string()),
+            renew_time: Some(MicroTime(now - chrono::Duration::seconds(1))),
+            ..LeaseSpec::default()
+        };
+        assert!(lease_expired(&lease, now));
+    }
+}
@@ -1,18 +1,27 @@
 mod error;
+mod leader_election;
+mod master_state;
+mod metrics;
 mod spark_controller;
 
 #[macro_use]
 extern crate lazy_static;
 
+use futures::future;
 use futures::stream::StreamExt;
 use stackable_operator::cli::Command;
 use stackable_operator::k8s_openapi::api::apps::v1::StatefulSet;
-use stackable_operator::k8s_openapi::api::core::v1::{ConfigMap, Endpoints, Service};
+use stackable_operator::k8s_openapi::api::core::v1::{ConfigMap, Endpoints, Pod, Secret, Service};
+use stackable_operator::k8s_openapi::api::policy::v1::PodDisruptionBudget;
 use stackable_operator::kube::api::{DynamicObject, ListParams};
-use stackable_operator::kube::runtime::controller::{Context, Controller, ReconcilerAction};
+use stackable_operator::kube::runtime::controller::{self, Context, Controller, ReconcilerAction};
 use stackable_operator::kube::runtime::reflector::ObjectRef;
-use stackable_operator::kube::{CustomResourceExt, Resource};
+use stackable_operator::kube::runtime::watcher;
+use stackable_operator::kube::{Api, CustomResourceExt, Resource, ResourceExt};
+use stackable_operator::labels::{APP_INSTANCE_LABEL, APP_NAME_LABEL};
+use stackable_spark_crd::constants::{APP_NAME, MANAGED_SECRET_LABEL};
 use stackable_spark_crd::SparkCluster;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -38,6 +47,46 @@ fn erase_controller_result_type<K: Resource, E: std::error::Error + Send + Sync
     Ok((obj_ref.erase(), action))
 }
 
+/// The requeue delay of failed reconciles from `SPARK_OPERATOR_RECONCILE_REQUEUE_SECONDS` or,
+/// if unset, `RECONCILE_REQUEUE_SECONDS`. An invalid value is logged and replaced by the default
+/// instead of stopping the operator.
+fn error_requeue_from_env() -> Duration {
+    let (name, seconds) = match [
+        "SPARK_OPERATOR_RECONCILE_REQUEUE_SECONDS",
+        "RECONCILE_REQUEUE_SECONDS",
+    ]
+    .iter()
+    .find_map(|name| std::env::var(name).ok().map(|seconds| (*name, seconds)))
+    {
+        Some(variable) => variable,
+        None => return spark_controller::DEFAULT_ERROR_REQUEUE,
+    };
+    match seconds.trim().parse() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(error) => {
+            tracing::warn!(
+                %error,
+                variable = name,
+                value = %seconds,
+                "Invalid requeue delay, using the default of {:?}",
+                spark_controller::DEFAULT_ERROR_REQUEUE
+            );
+            spark_controller::DEFAULT_ERROR_REQUEUE
+        }
+    }
+}
+
+/// The API of a kind watched by the controller, restricted to `watch_namespace` if given.
+fn watched_api<K: Resource<DynamicType = ()>>(
+    client: &stackable_operator::client::Client,
+    watch_namespace: Option<&str>,
+) -> Api<K> {
+    match watch_namespace {
+        Some(namespace) => client.get_namespaced_api(namespace),
+        None => client.get_all_api(),
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     stackable_operator::logging::initialize_logging("SPARK_OPERATOR_LOG");
@@ -62,13 +111,88 @@ async fn main() -> anyhow::Result<()> {
             let client =
                 stackable_operator::client::create_client(Some("spark.stackable.tech".to_string()))
                     .await?;
-            let controller_builder =
-                Controller::new(client.get_all_api::<SparkCluster>(), ListParams::default());
+            let leader_election = leader_election::LeaderElection::from_env(&client)?;
+            if let Some(leader_election) = &leader_election {
+                leader_election.acquire().await;
+            }
+            let error_requeue = error_requeue_from_env();
+            // Not available if the operator runs outside of Kubernetes
+            let operator_namespace =
+                std::fs::read_to_string(leader_election::SERVICE_ACCOUNT_NAMESPACE_FILE)
+                    .ok()
+                    .map(|namespace| namespace.trim().to_string());
+            let ctx = Context::new(spark_controller::Ctx::new(
+                client.clone(),
+                product_config,
+                error_requeue,
+                operator_namespace,
+            ));
+
+            let metrics_port = match std::env::var("SPARK_OPERATOR_METRICS_PORT") {
+                Ok(port) => port.parse()?,
+                Err(_) => metrics::DEFAULT_METRICS_PORT,
+            };
+            let metrics = ctx.get_ref().metrics.clone();
+            tokio::spawn(async move {
+                if let Err(error) = metrics::serve(metrics, metrics_port).await {
+                    tracing::error!(%error, "Failed to serve metrics");
+                }
+            });
+
+            let watch_namespace = std::env::var("SPARK_OPERATOR_WATCH_NAMESPACE").ok();
+
+            // The pods are created by the StatefulSets, their events are only counted
+            let pod_metrics = ctx.get_ref().metrics.clone();
+            let pods = watched_api::<Pod>(&client, watch_namespace.as_deref());
+            tokio::spawn(async move {
+                watcher(
+                    pods,
+                    ListParams::default().labels(&format!("{}={}", APP_NAME_LABEL, APP_NAME)),
+                )
+                .for_each(|event| {
+                    match event {
+                        Ok(event) => pod_metrics.record_pod_event(event),
+                        Err(error) => tracing::warn!(%error, "Failed to watch Spark pods"),
+                    }
+                    future::ready(())
+                })
+                .await
+            });
+            // Failures of deleted clusters are not reset by a reconcile anymore
+            let failures_ctx = ctx.clone();
+            let clusters = watched_api::<SparkCluster>(&client, watch_namespace.as_deref());
+            tokio::spawn(async move {
+                watcher(clusters, ListParams::default())
+                    .for_each(|event| {
+                        match event {
+                            Ok(watcher::Event::Deleted(sc)) => failures_ctx
+                                .get_ref()
+                                .forget_cluster(&ObjectRef::from_obj(&sc)),
+                            Ok(watcher::Event::Restarted(clusters)) => {
+                                failures_ctx.get_ref().retain_clusters(
+                                    &clusters.iter().map(ObjectRef::from_obj).collect(),
+                                )
+                            }
+                            Ok(watcher::Event::Applied(_)) => {}
+                            Err(error) => tracing::warn!(%error, "Failed to watch Spark clusters"),
+                        }
+                        future::ready(())
+                    })
+                    .await
+            });
+            let controller_builder = Controller::new(
+                watched_api::<SparkCluster>(&client, watch_namespace.as_deref()),
+                ListParams::default(),
+            );
             let sc_store = controller_builder.store();
+            let secret_sc_store = controller_builder.store();
             let controller = controller_builder
-                .owns(client.get_all_api::<Service>(), ListParams::default())
+                .owns(
+                    watched_api::<Service>(&client, watch_namespace.as_deref()),
+                    ListParams::default(),
+                )
                 .watches(
-                    client.get_all_api::<Endpoints>(),
+                    watched_api::<Endpoints>(&client, watch_namespace.as_deref()),
                     ListParams::default(),
                     move |endpoints| {
                         sc_store
@@ -82,18 +206,53 @@ async fn main() -> anyhow::Result<()> {
                             .map(|sc| ObjectRef::from_obj(&sc))
                     },
                 )
-                .owns(client.get_all_api::<StatefulSet>(), ListParams::default())
-                .owns(client.get_all_api::<ConfigMap>(), ListParams::default())
+                .watches(
+                    watched_api::<Secret>(&client, watch_namespace.as_deref()),
+                    // Only Secrets that belong to a cluster
+                    ListParams::default().labels(&format!(
+                        "{}=true,{}",
+                        MANAGED_SECRET_LABEL, APP_INSTANCE_LABEL
+                    )),
+                    move |secret| {
+                        secret_sc_store
+                            .state()
+                            .into_iter()
+                            .filter(move |sc| {
+                                sc.metadata.namespace == secret.metadata.namespace
+                                    && sc.metadata.name.as_ref()
+                                        == secret.labels().get(APP_INSTANCE_LABEL)
+                            })
+                            .map(|sc| ObjectRef::from_obj(&sc))
+                    },
+                )
+                .owns(
+                    watched_api::<StatefulSet>(&client, watch_namespace.as_deref()),
+                    ListParams::default(),
+                )
+                .owns(
+                    watched_api::<ConfigMap>(&client, watch_namespace.as_deref()),
+                    ListParams::default(),
+                )
+                .owns(
+                    watched_api::<PodDisruptionBudget>(&client, watch_namespace.as_deref()),
+                    ListParams::default(),
+                )
                 .run(
                     spark_controller::reconcile,
                     spark_controller::error_policy,
-                    Context::new(spark_controller::Ctx {
-                        client: client.clone(),
-                        product_config,
-                    }),
+                    ctx.clone(),
                 );
 
-            controller
+            let controller = controller
+                .filter(|res| {
+                    // Failures during an API server outage are reported once by the error policy
+                    let reported = matches!(
+                        res,
+                        Err(controller::Error::ReconcilerFailed(err, _))
+                            if err.is_api_unavailable() && ctx.get_ref().api_unavailable_backoff()
+                    );
+                    future::ready(!reported)
+                })
                 .map(erase_controller_result_type)
                 .for_each(|res| async {
                     match res {
@@ -105,8 +264,15 @@ async fn main() -> anyhow::Result<()> {
                             )
                         }
                     }
-                })
-                .await;
+                });
+            match &leader_election {
+                // Stop reconciling as soon as another replica may have taken over
+                Some(leader_election) => tokio::select! {
+                    _ = controller => {},
+                    result = leader_election.hold() => result?,
+                },
+                None => controller.await,
+            }
         }
     }
 
@@ -0,0 +1,130 @@
+//! The state of a Spark master, read from the JSON endpoint `/json` of its web UI.
+//!
+//! The state is only informational, a master that cannot be reached or answers with something
+//! else than its state leaves the utilization in the status unknown.
+
+use hyper::{body, Client, Uri};
+use serde::{de::IgnoredAny, Deserialize};
+use std::time::Duration;
+
+/// The `status` of the master that is elected leader, standby masters have no workers.
+pub const MASTER_STATUS_ALIVE: &str = "ALIVE";
+
+/// The parts of the master state aggregated into the `SparkClusterStatus`.
+#[derive(Debug, Deserialize)]
+pub struct MasterState {
+    pub status: String,
+    /// Cores of all alive workers
+    pub cores: usize,
+    #[serde(rename = "coresused")]
+    pub cores_used: usize,
+    /// Memory of all alive workers in MiB
+    pub memory: usize,
+    #[serde(rename = "memoryused")]
+    pub memory_used: usize,
+    #[serde(rename = "activeapps")]
+    pub active_apps: Vec<MasterApplication>,
+    #[serde(rename = "completedapps")]
+    pub completed_apps: Vec<IgnoredAny>,
+}
+
+/// An application registered at the master.
+#[derive(Debug, Deserialize)]
+pub struct MasterApplication {
+    pub id: String,
+    /// Milliseconds since the epoch
+    #[serde(rename = "starttime")]
+    pub start_time: i64,
+}
+
+/// Fetch the state of the master serving its web UI at `url`, `None` if it cannot be read
+/// within `timeout`.
+pub async fn fetch(url: &str, timeout: Duration) -> Option<MasterState> {
+    let uri = match format!("{}/json", url).parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(error) => {
+            tracing::debug!(%url, %error, "Invalid master web UI URL");
+            return None;
+        }
+    };
+    let request = async {
+        let response = Client::new().get(uri).await?;
+        let status = response.status();
+        body::to_bytes(response.into_body())
+            .await
+            .map(|bytes| (status, bytes))
+    };
+    match tokio::time::timeout(timeout, request).await {
+        Ok(Ok((status, bytes))) if status.is_success() => match serde_json::from_slice(&bytes) {
+            Ok(state) => Some(state),
+            Err(error) => {
+                tracing::debug!(%url, %error, "Failed to parse master state");
+                None
+            }
+        },
+        Ok(Ok((status, _))) => {
+            tracing::debug!(%url, %status, "Failed to read master state");
+            None
+        }
+        Ok(Err(error)) => {
+            tracing::debug!(%url, %error, "Failed to read master state");
+            None
+        }
+        Err(_) => {
+            tracing::debug!(%url, "Timed out reading master state");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn master_state_is_parsed_from_the_json_endpoint() {
+        let state: MasterState = serde_json::from_str(
+            r#"{
+                "url": "spark://simple-master-default-0:7077",
+                "workers": [],
+                "aliveworkers": 2,
+                "cores": 8,
+                "coresused": 3,
+                "memory": 14336,
+                "memoryused": 4096,
+                "resources": [],
+                "resourcesused": [],
+                "activeapps": [
+                    {"id": "app-20211201120000-0002", "starttime": 1638360000000, "state": "RUNNING"}
+                ],
+                "completedapps": [
+                    {"id": "app-20211201110000-0000", "state": "FINISHED"},
+                    {"id": "app-20211201113000-0001", "state": "KILLED"}
+                ],
+                "activedrivers": [],
+                "completeddrivers": [],
+                "status": "ALIVE"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(state.status, MASTER_STATUS_ALIVE);
+        assert_eq!((state.cores, state.cores_used), (8, 3));
+        assert_eq!((state.memory, state.memory_used), (14336, 4096));
+        assert_eq!(state.active_apps[0].id, "app-20211201120000-0002");
+        assert_eq!(state.active_apps[0].start_time, 1638360000000);
+        assert_eq!(state.completed_apps.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unresponsive_masters_time_out() {
+        // Connections are accepted by the backlog, but never answered
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let started = std::time::Instant::now();
+        assert!(fetch(&url, Duration::from_millis(200)).await.is_none());
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(2));
+    }
+}
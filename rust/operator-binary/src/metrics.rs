@@ -0,0 +1,246 @@
+//! Prometheus metrics of the operator, served in the text exposition format on `/metrics`.
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, Opts, Registry, TextEncoder};
+use stackable_operator::k8s_openapi::api::core::v1::Pod;
+use stackable_operator::kube::runtime::watcher;
+use stackable_operator::kube::ResourceExt;
+use stackable_operator::labels::APP_COMPONENT_LABEL;
+use stackable_spark_crd::SparkRole;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Port of the metrics endpoint unless `SPARK_OPERATOR_METRICS_PORT` is set.
+pub const DEFAULT_METRICS_PORT: u16 = 9090;
+
+/// Upper bounds in seconds of the buckets of `spark_operator_reconcile_duration_seconds`.
+const RECONCILE_DURATION_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub struct Metrics {
+    registry: Registry,
+    reconciles: IntCounterVec,
+    reconcile_duration: Histogram,
+    pods_created: IntCounterVec,
+    pods_deleted: IntCounterVec,
+    /// The node type of each known Spark pod by UID, `None` until the pods were listed once
+    known_pods: Mutex<Option<HashMap<String, &'static str>>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let reconciles = IntCounterVec::new(
+            Opts::new(
+                "spark_operator_reconcile_total",
+                "Number of finished reconciles of SparkClusters",
+            ),
+            &["result"],
+        )
+        .unwrap();
+        let reconcile_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "spark_operator_reconcile_duration_seconds",
+                "Duration of the reconciles of SparkClusters",
+            )
+            .buckets(RECONCILE_DURATION_BUCKETS.to_vec()),
+        )
+        .unwrap();
+        let pods_created = IntCounterVec::new(
+            Opts::new(
+                "spark_operator_pod_created_total",
+                "Number of created Spark pods",
+            ),
+            &["node_type"],
+        )
+        .unwrap();
+        let pods_deleted = IntCounterVec::new(
+            Opts::new(
+                "spark_operator_pod_deleted_total",
+                "Number of deleted Spark pods",
+            ),
+            &["node_type"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(reconciles.clone())).unwrap();
+        registry
+            .register(Box::new(reconcile_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(pods_created.clone())).unwrap();
+        registry.register(Box::new(pods_deleted.clone())).unwrap();
+        Metrics {
+            registry,
+            reconciles,
+            reconcile_duration,
+            pods_created,
+            pods_deleted,
+            known_pods: Mutex::new(None),
+        }
+    }
+}
+
+impl Metrics {
+    /// Count a finished reconcile and its duration.
+    pub fn record_reconcile(&self, success: bool, duration: Duration) {
+        self.reconciles
+            .with_label_values(&[if success { "success" } else { "error" }])
+            .inc();
+        self.reconcile_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Count the Spark pods created and deleted since the first list of the pod watch.
+    ///
+    /// A relist after a lost watch counts the pods created and deleted in the meantime.
+    pub fn record_pod_event(&self, event: watcher::Event<Pod>) {
+        let mut known_pods = self.known_pods.lock().unwrap();
+        match event {
+            watcher::Event::Applied(pod) => {
+                if let (Some(known_pods), Some(uid)) = (known_pods.as_mut(), pod.uid()) {
+                    if known_pods.insert(uid, node_type(&pod)).is_none() {
+                        self.pods_created
+                            .with_label_values(&[node_type(&pod)])
+                            .inc();
+                    }
+                }
+            }
+            watcher::Event::Deleted(pod) => {
+                if let (Some(known_pods), Some(uid)) = (known_pods.as_mut(), pod.uid()) {
+                    if let Some(node_type) = known_pods.remove(&uid) {
+                        self.pods_deleted.with_label_values(&[node_type]).inc();
+                    }
+                }
+            }
+            watcher::Event::Restarted(pods) => {
+                let listed = pods
+                    .iter()
+                    .filter_map(|pod| Some((pod.uid()?, node_type(pod))))
+                    .collect::<HashMap<_, _>>();
+                if let Some(previous) = known_pods.as_ref() {
+                    for (uid, node_type) in &listed {
+                        if !previous.contains_key(uid) {
+                            self.pods_created.with_label_values(&[*node_type]).inc();
+                        }
+                    }
+                    for (uid, node_type) in previous {
+                        if !listed.contains_key(uid) {
+                            self.pods_deleted.with_label_values(&[*node_type]).inc();
+                        }
+                    }
+                }
+                *known_pods = Some(listed);
+            }
+        }
+    }
+
+    /// Render all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut text)
+            .unwrap();
+        String::from_utf8(text).unwrap()
+    }
+}
+
+/// The `node_type` label of a Spark pod, derived from its component label.
+fn node_type(pod: &Pod) -> &'static str {
+    match pod
+        .labels()
+        .get(APP_COMPONENT_LABEL)
+        .and_then(|component| SparkRole::from_str(component).ok())
+    {
+        Some(SparkRole::Master) => "master",
+        Some(SparkRole::Worker) => "worker",
+        Some(SparkRole::HistoryServer) => "history_server",
+        None => "unknown",
+    }
+}
+
+/// Serve the metrics on `/metrics` of all interfaces until the server fails.
+pub async fn serve(metrics: Arc<Metrics>, port: u16) -> hyper::Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_connection| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let metrics = metrics.clone();
+                async move { Ok::<_, Infallible>(respond(&metrics, &request)) }
+            }))
+        }
+    });
+    tracing::info!(%addr, "Serving metrics");
+    Server::try_bind(&addr)?.serve(make_service).await
+}
+
+fn respond(metrics: &Metrics, request: &Request<Body>) -> Response<Body> {
+    if request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let mut response = Response::new(Body::from(metrics.render()));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod(uid: &str, component: &str) -> Pod {
+        serde_yaml::from_str(&format!(
+            "
+            metadata:
+              name: simple-{component}-default-0
+              uid: {uid}
+              labels:
+                app.kubernetes.io/component: {component}
+            ",
+            uid = uid,
+            component = component
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn pods_are_counted_once_after_the_first_list() {
+        let metrics = Metrics::default();
+        metrics.record_pod_event(watcher::Event::Restarted(vec![pod("a", "master")]));
+        metrics.record_pod_event(watcher::Event::Applied(pod("a", "master")));
+        metrics.record_pod_event(watcher::Event::Applied(pod("b", "slave")));
+        metrics.record_pod_event(watcher::Event::Applied(pod("b", "slave")));
+        metrics.record_pod_event(watcher::Event::Deleted(pod("a", "master")));
+        // Created and deleted while the watch was lost
+        metrics.record_pod_event(watcher::Event::Restarted(vec![pod("c", "history-server")]));
+
+        let text = metrics.render();
+        assert!(text.contains("spark_operator_pod_created_total{node_type=\"worker\"} 1\n"));
+        assert!(text.contains("spark_operator_pod_created_total{node_type=\"history_server\"} 1\n"));
+        assert!(!text.contains("spark_operator_pod_created_total{node_type=\"master\"}"));
+        assert!(text.contains("spark_operator_pod_deleted_total{node_type=\"master\"} 1\n"));
+        assert!(text.contains("spark_operator_pod_deleted_total{node_type=\"worker\"} 1\n"));
+    }
+
+    #[test]
+    fn reconciles_are_counted_by_result() {
+        let metrics = Metrics::default();
+        metrics.record_reconcile(true, Duration::from_millis(70));
+        metrics.record_reconcile(false, Duration::from_secs(20));
+
+        let text = metrics.render();
+        assert!(text.contains("spark_operator_reconcile_total{result=\"success\"} 1\n"));
+        assert!(text.contains("spark_operator_reconcile_total{result=\"error\"} 1\n"));
+        assert!(text.contains("spark_operator_reconcile_duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("spark_operator_reconcile_duration_seconds_count 2\n"));
+    }
+}
@@ -2,6 +2,10 @@
 
 use crate::error::Error;
 use crate::error::Error::*;
+use crate::master_state::{self, MasterState, MASTER_STATUS_ALIVE};
+use crate::metrics::Metrics;
+use futures::future;
+use sha2::{Digest, Sha256};
 use stackable_operator::k8s_openapi::api::core::v1::ContainerPort;
 use stackable_operator::product_config_utils::Configuration;
 use stackable_operator::role_utils::{Role, RoleGroupRef};
@@ -9,37 +13,67 @@ use stackable_operator::{
     builder::{ConfigMapBuilder, ContainerBuilder, ObjectMetaBuilder, PodBuilder},
     k8s_openapi::{
         api::{
-            apps::v1::{StatefulSet, StatefulSetSpec},
+            apps::v1::{StatefulSet, StatefulSetSpec, StatefulSetUpdateStrategy},
             core::v1::{
-                ConfigMap, ConfigMapVolumeSource, EnvVar, HTTPGetAction, PersistentVolumeClaim,
-                PersistentVolumeClaimSpec, Probe, ResourceRequirements, Service, ServicePort,
-                ServiceSpec, Volume,
+                Affinity, ConfigMap, ConfigMapVolumeSource, EnvVar, EnvVarSource, Event,
+                EventSource, ExecAction, HTTPGetAction, Handler, KeyToPath, Lifecycle,
+                LocalObjectReference, Node, NodeAffinity, NodeSelector, NodeSelectorTerm,
+                ObjectReference, PersistentVolumeClaim, PersistentVolumeClaimSpec,
+                PersistentVolumeClaimVolumeSource, PodAffinityTerm, PodAntiAffinity,
+                PodTemplateSpec, Probe, ResourceRequirements, Secret, SecretKeySelector,
+                SecretVolumeSource, Service, ServiceAccount, ServicePort, ServiceSpec,
+                TCPSocketAction, Volume, VolumeMount, WeightedPodAffinityTerm,
             },
+            networking::v1::{
+                HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+                IngressServiceBackend, IngressSpec, IngressTLS, NetworkPolicy,
+                NetworkPolicyIngressRule, NetworkPolicyPeer, NetworkPolicyPort, NetworkPolicySpec,
+                ServiceBackendPort,
+            },
+            policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
         },
         apimachinery::pkg::{
-            api::resource::Quantity, apis::meta::v1::LabelSelector, util::intstr::IntOrString,
+            api::resource::Quantity,
+            apis::meta::v1::{Condition, LabelSelector, Time},
+            util::intstr::IntOrString,
         },
+        chrono::{DateTime, Utc},
     },
     kube::{
-        api::ObjectMeta,
+        self,
+        api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PropagationPolicy},
         runtime::{
             controller::{Context, ReconcilerAction},
             reflector::ObjectRef,
         },
+        Resource, ResourceExt,
+    },
+    labels::{
+        role_group_selector_labels, role_selector_labels, APP_INSTANCE_LABEL, APP_NAME_LABEL,
     },
-    labels::{role_group_selector_labels, role_selector_labels},
     product_config::{types::PropertyNameKind, ProductConfigManager},
-    product_config_utils::{transform_all_roles_to_config, validate_all_roles_and_groups_config},
+    product_config_utils::{
+        transform_all_roles_to_config, validate_all_roles_and_groups_config,
+        ValidatedRoleConfigByPropertyKind,
+    },
 };
 use stackable_spark_crd::constants::*;
-use stackable_spark_crd::{SparkCluster, SparkRole};
+use stackable_spark_crd::{
+    is_gcs_url, is_s3_url, IngressConfig, NodeConfig, NonCriticalConfig, ProbeCheck, ProbeConfig,
+    SparkCluster, SparkClusterPhase, SparkClusterStatus, SparkRole,
+};
 use std::{
-    collections::{BTreeMap, HashMap},
-    time::Duration,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 lazy_static! {
-    /// Liveliness probe used by all master, worker and history containers.
+    /// Default probe of all master, worker and history containers, see [`build_probe`].
     static ref PROBE: Probe = Probe {
         http_get: Some(HTTPGetAction {
             port: IntOrString::String(String::from(PORT_NAME_WEB)),
@@ -51,19 +85,179 @@ lazy_static! {
     };
 }
 
+/// Prometheus JMX exporter config exporting all MBeans.
+const JMX_EXPORTER_CONFIG_CONTENT: &str = "\
+    ---\n\
+    startDelaySeconds: 0\n\
+    lowercaseOutputName: true\n\
+    lowercaseOutputLabelNames: true\n\
+    rules:\n\
+    - pattern: \".*\"\n";
+
+/// GPU discovery script of the workers, printing the GPUs visible to the container in the
+/// format expected by `spark.worker.resource.gpu.discoveryScript`.
+const GPU_DISCOVERY_SCRIPT_CONTENT: &str = r#"#!/usr/bin/env bash
+ADDRS=$(nvidia-smi --query-gpu=index --format=csv,noheader | paste -sd, - | sed 's/,/","/g')
+echo "{\"name\": \"gpu\", \"addresses\": [\"$ADDRS\"]}"
+"#;
+
+/// Default requeue delay after a failed reconcile, see `SPARK_OPERATOR_RECONCILE_REQUEUE_SECONDS`.
+pub const DEFAULT_ERROR_REQUEUE: Duration = Duration::from_secs(5);
+/// Upper bound of the requeue delay of a cluster that failed repeatedly.
+const MAX_ERROR_REQUEUE: Duration = Duration::from_secs(5 * 60);
+/// Upper bound of the requeue delay while the API server is unreachable.
+const MAX_API_UNAVAILABLE_REQUEUE: Duration = Duration::from_secs(60);
+/// Number of consecutive failures caused by an unreachable API server before backing off.
+const API_UNAVAILABLE_THRESHOLD: u32 = 3;
+/// Requeue delay while a rolegroup cannot be scheduled because no node matches its selector.
+const NO_MATCHING_NODES_REQUEUE: Duration = Duration::from_secs(30);
+/// Requeue delay while a StatefulSet with changed volume claim templates is deleted.
+const RECREATE_STATEFULSET_REQUEUE: Duration = Duration::from_secs(5);
+/// Requeue delay while non-critical changes are deferred, see [`defer_non_critical_changes`].
+const DEFERRED_CHANGES_REQUEUE: Duration = Duration::from_secs(30);
+/// Requeue delay while master pods wait for running applications, see
+/// [`defer_master_replacement`].
+const MASTER_DRAIN_REQUEUE: Duration = Duration::from_secs(30);
+
+const EVENT_TYPE_NORMAL: &str = "Normal";
+const EVENT_TYPE_WARNING: &str = "Warning";
+/// The component reported as source of the published events.
+const EVENT_SOURCE_COMPONENT: &str = "spark-operator";
+/// Conditions reporting a problem of the cluster definition while `True`, which does not go
+/// away by waiting. Their warning is also published if they are `True` right away.
+const SPEC_PROBLEM_CONDITIONS: &[&str] = &[
+    CONDITION_NO_MATCHING_NODES,
+    CONDITION_NO_GPU_NODES,
+    CONDITION_NO_SHUFFLE_SERVICE,
+];
+
 pub struct Ctx {
     pub client: stackable_operator::client::Client,
     pub product_config: ProductConfigManager,
+    pub metrics: Arc<Metrics>,
+    /// Requeue delay after a failed reconcile while the API server is reachable.
+    error_requeue: Duration,
+    /// Consecutive reconcile failures per cluster, reset by a successful reconcile.
+    reconcile_failures: Mutex<HashMap<ObjectRef<SparkCluster>, u32>>,
+    /// Failures caused by an unreachable API server, shared by all clusters because they all
+    /// talk to the same API server.
+    api_availability: ApiAvailability,
+    /// The namespace of the operator pods, `None` if the operator runs outside of Kubernetes.
+    operator_namespace: Option<String>,
+}
+
+impl Ctx {
+    pub fn new(
+        client: stackable_operator::client::Client,
+        product_config: ProductConfigManager,
+        error_requeue: Duration,
+        operator_namespace: Option<String>,
+    ) -> Self {
+        Ctx {
+            client,
+            product_config,
+            metrics: Arc::new(Metrics::default()),
+            error_requeue,
+            reconcile_failures: Mutex::new(HashMap::new()),
+            api_availability: ApiAvailability::default(),
+            operator_namespace,
+        }
+    }
+
+    /// Whether the API server has been unreachable often enough to back off.
+    /// Failures are reported once when entering the backoff mode instead of individually.
+    pub fn api_unavailable_backoff(&self) -> bool {
+        self.api_availability.backoff()
+    }
+
+    /// Count a finished reconcile of the cluster and return its consecutive failures.
+    fn record_reconcile_result(&self, sc_ref: &ObjectRef<SparkCluster>, success: bool) -> u32 {
+        // The map stays consistent even if a thread panicked while holding the lock
+        let mut reconcile_failures = self
+            .reconcile_failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if success {
+            reconcile_failures.remove(sc_ref);
+            return 0;
+        }
+        let failures = reconcile_failures.entry(sc_ref.clone()).or_insert(0);
+        *failures += 1;
+        *failures
+    }
+
+    /// Forget the failures of a deleted cluster, it is not reconciled anymore. A cluster with a
+    /// finalizer is already forgotten by its last, successful reconcile.
+    pub fn forget_cluster(&self, sc_ref: &ObjectRef<SparkCluster>) {
+        self.reconcile_failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(sc_ref);
+    }
+
+    /// Forget the failures of all clusters except `existing`, e.g. after the watch of the
+    /// clusters was restarted and deletions may have been missed.
+    pub fn retain_clusters(&self, existing: &HashSet<ObjectRef<SparkCluster>>) {
+        self.reconcile_failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|sc_ref, _| existing.contains(sc_ref));
+    }
 }
 
 /// The main reconcile loop.
 ///
 /// For each rolegroup a [`StatefulSet`] and a [`ClusterIP`] service is created.
+/// Failures are published as `ReconcileFailed` warning events of the cluster.
 pub async fn reconcile(sc: SparkCluster, ctx: Context<Ctx>) -> Result<ReconcilerAction, Error> {
+    let start = Instant::now();
+    let result = reconcile_cluster(sc.clone(), ctx.clone()).await;
+    ctx.get_ref()
+        .metrics
+        .record_reconcile(result.is_ok(), start.elapsed());
+    let sc_ref = ObjectRef::from_obj(&sc);
+    let failures = ctx
+        .get_ref()
+        .record_reconcile_result(&sc_ref, result.is_ok());
+    let error = match result {
+        Ok(action) => {
+            ctx.get_ref().api_availability.record_available();
+            return Ok(action);
+        }
+        Err(error) => error,
+    };
+    // Events cannot be published while the API server is unreachable
+    if !error.is_api_unavailable() {
+        publish_event(
+            &ctx.get_ref().client,
+            &sc,
+            EVENT_TYPE_WARNING,
+            "ReconcileFailed",
+            &error.to_string(),
+        )
+        .await;
+    }
+    Err(Error::Reconcile {
+        source: Box::new(error),
+        sc: sc_ref,
+        failures,
+    })
+}
+
+async fn reconcile_cluster(sc: SparkCluster, ctx: Context<Ctx>) -> Result<ReconcilerAction, Error> {
     tracing::info!("Starting reconcile");
     let sc_ref = ObjectRef::from_obj(&sc);
     let client = &ctx.get_ref().client;
 
+    if sc.metadata.deletion_timestamp.is_some() {
+        return finalize_cluster(client, &sc).await;
+    }
+
+    validate_spec(&sc)?;
+
+    // Non-critical changes wait until the cluster is running, the objects keep the applied ones
+    let (sc, pending_non_critical_changes) = defer_non_critical_changes(sc);
+
     let validated_config = validate_all_roles_and_groups_config(
         version(&sc)?,
         &transform_all_roles_to_config(&sc, build_spark_role_properties(&sc)),
@@ -76,6 +270,16 @@ pub async fn reconcile(sc: SparkCluster, ctx: Context<Ctx>) -> Result<Reconciler
         sc: sc_ref.clone(),
     })?;
 
+    for (role_name, group_config) in validated_config.iter() {
+        for (rolegroup_name, rolegroup_config) in group_config.iter() {
+            validate_authentication(
+                &sc,
+                &sc.server_rolegroup_ref(role_name, rolegroup_name),
+                rolegroup_config,
+            )?;
+        }
+    }
+
     // Extract the master default group ports here because they are needed twice:
     // 1. For the NodePort service built by build_master_role_service()
     // 2. For the worker's master URL built with build_worker_stateful_set()
@@ -120,16 +324,142 @@ pub async fn reconcile(sc: SparkCluster, ctx: Context<Ctx>) -> Result<Reconciler
             sc: sc_ref.clone(),
         })?;
 
+    reconcile_master_ingress(client, &sc).await?;
+    reconcile_network_policy(client, &sc, ctx.get_ref().operator_namespace.as_deref()).await?;
+
+    if let Some(service_account) = build_service_account(&sc)? {
+        client
+            .apply_patch(FIELD_MANAGER_SCOPE, &service_account, &service_account)
+            .await
+            .map_err(|e| ApplyServiceAccount {
+                source: e,
+                sc: sc_ref.clone(),
+            })?;
+    }
+
+    match build_master_pod_disruption_budget(&sc)? {
+        Some(master_pdb) => client
+            .apply_patch(FIELD_MANAGER_SCOPE, &master_pdb, &master_pdb)
+            .await
+            .map(drop)
+            .map_err(|e| ApplyMasterPodDisruptionBudget {
+                source: e,
+                sc: sc_ref.clone(),
+            })?,
+        None => delete_pod_disruption_budget(client, &sc, &SparkRole::Master.to_string())
+            .await
+            .map_err(|e| DeleteMasterPodDisruptionBudget {
+                source: e,
+                sc: sc_ref.clone(),
+            })?,
+    }
+    match build_worker_pod_disruption_budget(&sc)? {
+        Some(worker_pdb) => client
+            .apply_patch(FIELD_MANAGER_SCOPE, &worker_pdb, &worker_pdb)
+            .await
+            .map(drop)
+            .map_err(|e| ApplyWorkerPodDisruptionBudget {
+                source: e,
+                sc: sc_ref.clone(),
+            })?,
+        None => delete_pod_disruption_budget(client, &sc, &SparkRole::Worker.to_string())
+            .await
+            .map_err(|e| DeleteWorkerPodDisruptionBudget {
+                source: e,
+                sc: sc_ref.clone(),
+            })?,
+    }
+
+    if let Some(event_log_volume_claim) = build_event_log_volume_claim(&sc)? {
+        client
+            .apply_patch(
+                FIELD_MANAGER_SCOPE,
+                &event_log_volume_claim,
+                &event_log_volume_claim,
+            )
+            .await
+            .map_err(|e| ApplyEventLogVolumeClaim {
+                source: e,
+                sc: sc_ref.clone(),
+            })?;
+    }
+
+    // The history server Service and Ingress use the config of the first history server rolegroup
+    let first_history_server_rolegroup = validated_config
+        .iter()
+        .filter(|(role_name, _groups)| SparkRole::HistoryServer.to_string().eq(*role_name))
+        .flat_map(|(_role_name, groups)| groups)
+        .min_by_key(|(group_name, _group_config)| *group_name);
+    let history_server_ports = first_history_server_rolegroup
+        .map(|(rolegroup, rolegroup_config)| {
+            build_ports(
+                &sc,
+                &sc.server_rolegroup_ref(SparkRole::HistoryServer.to_string(), rolegroup),
+                rolegroup_config,
+            )
+        })
+        .transpose()?;
+    let history_server_reverse_proxy =
+        first_history_server_rolegroup.map(|(_rolegroup, rolegroup_config)| {
+            rolegroup_config
+                .get(&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string()))
+                .and_then(|spark_defaults| spark_defaults.get(SPARK_DEFAULTS_UI_REVERSE_PROXY))
+                .map_or(false, |reverse_proxy| reverse_proxy == "true")
+        });
+    reconcile_history_server_service(client, &sc, history_server_ports).await?;
+    reconcile_history_server_ingress(client, &sc, history_server_reverse_proxy).await?;
+
+    let shuffle_service_missing = shuffle_service_missing(&sc, &validated_config);
+
+    // A ConfigMap published before is left behind when publishMasterUrl changes
+    let published_master_url = sc
+        .status
+        .as_ref()
+        .and_then(|status| status.published_master_url.clone());
+    if let Some(previous) = published_master_url
+        .as_ref()
+        .filter(|previous| sc.spec.publish_master_url.as_ref() != Some(previous))
+    {
+        delete_master_url_config_map(client, previous).await?;
+    }
+    update_master_url_finalizer(client, &sc, sc.spec.publish_master_url.is_some()).await?;
+
+    let managed_secrets_hash = managed_secrets_hash(client, &sc).await?;
+    let mut requeue_after = None;
+    let mut status = SparkClusterStatus {
+        secret_rotation_time: secret_rotation_time(&sc, &managed_secrets_hash),
+        managed_secrets_hash: Some(managed_secrets_hash.clone()),
+        applied_non_critical_config: Some(non_critical_config(&sc)),
+        pending_non_critical_changes,
+        ..SparkClusterStatus::default()
+    };
+    let previous_master_drain_start_time = sc
+        .status
+        .as_ref()
+        .and_then(|status| status.master_drain_start_time.clone());
+    let mut all_replicas_ready = true;
+    let mut rolling_update = false;
+    let mut unschedulable_rolegroups = Vec::new();
+    let mut gpus_requested = false;
+    let mut rolegroups_without_gpu_nodes = Vec::new();
+
     for (role_name, group_config) in validated_config.iter() {
         for (rolegroup_name, rolegroup_config) in group_config.iter() {
             let rolegroup = sc.server_rolegroup_ref(role_name, rolegroup_name);
             let rg_service = build_rolegroup_service(&sc, &rolegroup, rolegroup_config)?;
-            let rg_configmap = build_rolegroup_config_map(&sc, &rolegroup, rolegroup_config)?;
+            let rg_configmap = build_rolegroup_config_map(
+                &sc,
+                &default_master_role_ports,
+                &rolegroup,
+                rolegroup_config,
+            )?;
             let rg_statefulset = build_rolegroup_statefulset(
                 &sc,
                 &default_master_role_ports,
                 &rolegroup,
                 rolegroup_config,
+                &managed_secrets_hash,
+                &config_map_hash(&rg_configmap),
             )?;
             client
                 .apply_patch(FIELD_MANAGER_SCOPE, &rg_service, &rg_service)
@@ -145,583 +475,7428 @@ pub async fn reconcile(sc: SparkCluster, ctx: Context<Ctx>) -> Result<Reconciler
                     source: e,
                     rolegroup: rolegroup.clone(),
                 })?;
+            // Still applied, e.g. for scale-downs or so that the cluster autoscaler scales up a
+            // node pool from zero for the pending pods
+            if !verify_node_pool_capacity(client, &rolegroup, &rg_statefulset).await? {
+                unschedulable_rolegroups.push(rolegroup.to_string());
+                requeue_after = Some(NO_MATCHING_NODES_REQUEUE);
+            }
+            let role: SparkRole = serde_yaml::from_str(role_name).unwrap();
+            if sc.node_config(&role, rolegroup_name).gpu_limit > Some(0) {
+                gpus_requested = true;
+                if !gpu_nodes_available(client, &rolegroup, &rg_statefulset).await? {
+                    rolegroups_without_gpu_nodes.push(rolegroup.to_string());
+                }
+            }
+            // Published once per generation of the cluster, not on every reconcile
+            if spec_changed(&sc)
+                && sc
+                    .node_config(&role, rolegroup_name)
+                    .host_network
+                    .unwrap_or(false)
+            {
+                publish_event(
+                    client,
+                    &sc,
+                    EVENT_TYPE_WARNING,
+                    "HostNetwork",
+                    &format!(
+                        "{} uses the host network, which bypasses NetworkPolicies",
+                        rolegroup
+                    ),
+                )
+                .await;
+            }
+            let deferred_statefulset = if role == SparkRole::Master {
+                defer_master_replacement(
+                    client,
+                    &sc,
+                    &rolegroup,
+                    &rg_statefulset,
+                    &default_master_role_ports,
+                    previous_master_drain_start_time.as_ref(),
+                )
+                .await?
+            } else {
+                None
+            };
+            let applied_statefulset = match deferred_statefulset {
+                Some((existing, drain_start_time)) => {
+                    requeue_after = Some(MASTER_DRAIN_REQUEUE);
+                    status.master_drain_start_time = Some(drain_start_time);
+                    existing
+                }
+                None => {
+                    if delete_statefulset_with_changed_claims(client, &rolegroup, &rg_statefulset)
+                        .await?
+                    {
+                        // Applied again once the old StatefulSet is gone, which adopts the pods
+                        // again
+                        requeue_after = Some(RECREATE_STATEFULSET_REQUEUE);
+                        all_replicas_ready = false;
+                        rolling_update = true;
+                        continue;
+                    }
+                    client
+                        .apply_patch(FIELD_MANAGER_SCOPE, &rg_statefulset, &rg_statefulset)
+                        .await
+                        .map_err(|e| ApplyRoleGroupStatefulSet {
+                            source: e,
+                            rolegroup: rolegroup.clone(),
+                        })?
+                }
+            };
+
+            let statefulset_status = applied_statefulset.status.unwrap_or_default();
+            let ready_replicas = statefulset_status.ready_replicas.unwrap_or(0);
+            let replicas = rolegroup_replicas(&sc, &rolegroup)?;
+            all_replicas_ready &= ready_replicas >= replicas;
+            // A changed StatefulSet is only rolled out once its controller observed the change
+            rolling_update |= statefulset_status.observed_generation
+                < applied_statefulset.metadata.generation
+                || statefulset_status.update_revision.is_some()
+                    && statefulset_status.current_revision != statefulset_status.update_revision;
+            match serde_yaml::from_str(role_name).unwrap() {
+                SparkRole::Master => status.master_count += ready_replicas,
+                SparkRole::Worker => status.worker_count += ready_replicas,
+                SparkRole::HistoryServer => status.history_server_count += ready_replicas,
+            }
+        }
+    }
+
+    if let Some(target) = &sc.spec.publish_master_url {
+        // Clients only get the URL once a master is ready, an earlier publication stays in place
+        if status.master_count > 0 {
+            let master_url_config_map =
+                build_master_url_config_map(&sc, target, &default_master_role_ports)?;
             client
-                .apply_patch(FIELD_MANAGER_SCOPE, &rg_statefulset, &rg_statefulset)
+                .apply_patch(
+                    FIELD_MANAGER_SCOPE,
+                    &master_url_config_map,
+                    &master_url_config_map,
+                )
                 .await
-                .map_err(|e| ApplyRoleGroupStatefulSet {
+                .map_err(|e| PublishMasterUrlFailed {
                     source: e,
-                    rolegroup: rolegroup.clone(),
+                    target: target.clone(),
                 })?;
+            status.published_master_url = Some(target.clone());
+        } else {
+            status.published_master_url =
+                published_master_url.filter(|previous| previous == target);
+        }
+    }
+
+    // Unknown while no master is ready or the leader cannot be reached
+    let master_state = if status.master_count > 0 {
+        fetch_leader_state(&sc, &default_master_role_ports).await
+    } else {
+        None
+    };
+    status.total_cores = master_state.as_ref().map(|state| state.cores);
+    status.cores_used = master_state.as_ref().map(|state| state.cores_used);
+    status.total_memory_mb = master_state.as_ref().map(|state| state.memory);
+    status.memory_used_mb = master_state.as_ref().map(|state| state.memory_used);
+    status.active_application_count = master_state
+        .as_ref()
+        .map(|state| state.active_apps.len() as i32);
+    status.completed_application_count = master_state
+        .as_ref()
+        .map(|state| state.completed_apps.len() as i32);
+
+    let version_upgraded_from = version_upgraded_from(&sc, rolling_update, all_replicas_ready);
+    let upgrade_in_progress = version_upgraded_from.is_some();
+    status.version = version_upgraded_from.or_else(|| sc.spec.version.clone());
+    status.phase = Some(if sc.spec.stopped.unwrap_or(false) {
+        SparkClusterPhase::Stopped
+    } else if upgrade_in_progress {
+        SparkClusterPhase::UpgradeInProgress
+    } else if all_replicas_ready {
+        SparkClusterPhase::Running
+    } else {
+        SparkClusterPhase::Pending
+    });
+    let available = status.master_count > 0 && status.worker_count > 0;
+    status.conditions = vec![
+        build_condition(
+            &sc,
+            CONDITION_AVAILABLE,
+            available,
+            if available {
+                "MinimumReplicasAvailable"
+            } else {
+                "MinimumReplicasUnavailable"
+            },
+            &format!(
+                "{} master(s) and {} worker(s) are ready",
+                status.master_count, status.worker_count
+            ),
+        ),
+        build_condition(
+            &sc,
+            CONDITION_PROGRESSING,
+            rolling_update,
+            if rolling_update {
+                "RollingUpdate"
+            } else {
+                "UpdateComplete"
+            },
+            if rolling_update {
+                "Pods are being replaced with an updated revision"
+            } else {
+                "All pods run the current revision"
+            },
+        ),
+        build_condition(
+            &sc,
+            CONDITION_DEGRADED,
+            !all_replicas_ready,
+            if all_replicas_ready {
+                "AllReplicasReady"
+            } else {
+                "ReplicasNotReady"
+            },
+            if all_replicas_ready {
+                "All role groups have their requested number of ready pods"
+            } else {
+                "Not all role groups have their requested number of ready pods"
+            },
+        ),
+        build_condition(
+            &sc,
+            CONDITION_NO_MATCHING_NODES,
+            !unschedulable_rolegroups.is_empty(),
+            if unschedulable_rolegroups.is_empty() {
+                "MatchingNodesFound"
+            } else {
+                "NoMatchingNodes"
+            },
+            &if unschedulable_rolegroups.is_empty() {
+                "The node selectors of all role groups match a node".to_string()
+            } else {
+                format!(
+                    "No node matches the node selector of {}, its pods stay pending",
+                    unschedulable_rolegroups.join(", ")
+                )
+            },
+        ),
+    ];
+    // Only reported while dynamic allocation is enabled
+    if let Some(shuffle_service_missing) = shuffle_service_missing {
+        status.conditions.push(build_condition(
+            &sc,
+            CONDITION_NO_SHUFFLE_SERVICE,
+            shuffle_service_missing,
+            if shuffle_service_missing {
+                "NoShuffleService"
+            } else {
+                "ShuffleServiceRunning"
+            },
+            if shuffle_service_missing {
+                "Dynamic allocation is enabled, but no worker runs the external shuffle service"
+            } else {
+                "Workers run the external shuffle service for dynamic allocation"
+            },
+        ));
+    }
+    // Only reported while a threshold is set, kept while the leader cannot be reached
+    if let Some(threshold_hours) = sc.spec.stale_app_threshold_hours {
+        match &master_state {
+            Some(master_state) => {
+                let stale_applications =
+                    stale_applications(master_state, threshold_hours, Utc::now());
+                status.conditions.push(build_condition(
+                    &sc,
+                    CONDITION_STALE_APPLICATION,
+                    !stale_applications.is_empty(),
+                    if stale_applications.is_empty() {
+                        "NoStaleApplications"
+                    } else {
+                        "StaleApplicationDetected"
+                    },
+                    &if stale_applications.is_empty() {
+                        format!("No application runs longer than {} hours", threshold_hours)
+                    } else {
+                        stale_applications
+                            .iter()
+                            .map(|(id, age)| {
+                                format!("{} runs for {:.1} hours", id, age.as_secs_f64() / 3600.0)
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    },
+                ));
+            }
+            None => status.conditions.extend(
+                sc.status
+                    .iter()
+                    .flat_map(|sc_status| sc_status.conditions.iter())
+                    .find(|previous| previous.type_ == CONDITION_STALE_APPLICATION)
+                    .cloned(),
+            ),
+        }
+    }
+    // Only reported while a rolegroup requests GPUs
+    if gpus_requested {
+        status.conditions.push(build_condition(
+            &sc,
+            CONDITION_NO_GPU_NODES,
+            !rolegroups_without_gpu_nodes.is_empty(),
+            if rolegroups_without_gpu_nodes.is_empty() {
+                "GpuNodesFound"
+            } else {
+                "NoGpuNodes"
+            },
+            &if rolegroups_without_gpu_nodes.is_empty() {
+                format!(
+                    "A matching node offers {} to all role groups requesting GPUs",
+                    RESOURCE_NVIDIA_GPU
+                )
+            } else {
+                format!(
+                    "{} request GPUs, but no matching node offers {}",
+                    rolegroups_without_gpu_nodes.join(", "),
+                    RESOURCE_NVIDIA_GPU
+                )
+            },
+        ));
+    }
+    for condition in &status.conditions {
+        let previous_status = sc
+            .status
+            .iter()
+            .flat_map(|sc_status| sc_status.conditions.iter())
+            .find(|previous| previous.type_ == condition.type_)
+            .map(|previous| previous.status.as_str());
+        if let Some(event_type) = condition_event_type(previous_status, condition) {
+            publish_event(
+                client,
+                &sc,
+                event_type,
+                &condition.reason,
+                &condition.message,
+            )
+            .await;
+        }
+    }
+    client
+        .apply_patch_status(FIELD_MANAGER_SCOPE, &sc, &status)
+        .await
+        .map_err(|e| UpdateStatus {
+            source: e,
+            sc: sc_ref.clone(),
+        })?;
+
+    if !status.pending_non_critical_changes.is_empty() && requeue_after.is_none() {
+        requeue_after = Some(DEFERRED_CHANGES_REQUEUE);
+    }
+    Ok(ReconcilerAction { requeue_after })
+}
+
+/// Whether the cluster definition changed since the last reconcile, i.e. the conditions in the
+/// status were observed for another generation or there are none yet.
+fn spec_changed(sc: &SparkCluster) -> bool {
+    sc.status
+        .iter()
+        .flat_map(|status| status.conditions.first())
+        .all(|condition| condition.observed_generation != sc.metadata.generation)
+}
+
+/// The type of the event to publish for a condition, `None` if it is not worth an event.
+///
+/// Changed conditions are published, `True` problems of the cluster definition (see
+/// [`SPEC_PROBLEM_CONDITIONS`]) also when they have no previous status. Problems, including
+/// stale applications, are warnings.
+///
+/// # Arguments
+/// * `previous_status` - The status of the condition after the last reconcile, if any.
+/// * `condition`       - The current condition.
+///
+fn condition_event_type(
+    previous_status: Option<&str>,
+    condition: &Condition,
+) -> Option<&'static str> {
+    let spec_problem =
+        condition.status == "True" && SPEC_PROBLEM_CONDITIONS.contains(&condition.type_.as_str());
+    if !previous_status.map_or(spec_problem, |previous_status| {
+        previous_status != condition.status
+    }) {
+        return None;
+    }
+    Some(
+        if condition.status == "True"
+            && (condition.type_ == CONDITION_DEGRADED
+                || condition.type_ == CONDITION_STALE_APPLICATION
+                || spec_problem)
+        {
+            EVENT_TYPE_WARNING
+        } else {
+            EVENT_TYPE_NORMAL
+        },
+    )
+}
+
+/// The non-critical settings of a cluster, see [`NonCriticalConfig`].
+fn non_critical_config(sc: &SparkCluster) -> NonCriticalConfig {
+    let rolegroups = sc
+        .spec
+        .masters
+        .iter()
+        .flat_map(|role| role.role_groups.keys())
+        .map(|group| (SparkRole::Master, group))
+        .chain(
+            sc.spec
+                .workers
+                .iter()
+                .flat_map(|role| role.role_groups.keys())
+                .map(|group| (SparkRole::Worker, group)),
+        )
+        .chain(
+            sc.spec
+                .history_servers
+                .iter()
+                .flat_map(|role| role.role_groups.keys())
+                .map(|group| (SparkRole::HistoryServer, group)),
+        );
+    NonCriticalConfig {
+        labels: forwarded_cluster_labels(sc),
+        pod_annotations: sc.spec.pod_annotations.clone(),
+        log4j_config: sc.spec.log4j_config.clone(),
+        log_levels: rolegroups
+            .map(|(role, group)| {
+                (
+                    sc.server_rolegroup_ref(role.to_string(), group)
+                        .object_name(),
+                    sc.node_config(&role, group).log_level,
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Defer the non-critical changes of a cluster whose previous phase is `UpgradeInProgress`, i.e.
+/// replace them with the applied settings in the status. `Pending` clusters get them right away,
+/// since pods that do not become ready because of their logging config need the fix.
+///
+/// Returns the cluster to build the objects from and the categories of the deferred changes.
+/// Clusters without applied settings get all changes right away, so do new rolegroups.
+///
+/// # Arguments
+/// * `sc` - The cluster resource object.
+///
+fn defer_non_critical_changes(mut sc: SparkCluster) -> (SparkCluster, Vec<String>) {
+    let status = sc.status.as_ref();
+    let applied = match status.and_then(|status| status.applied_non_critical_config.clone()) {
+        Some(applied)
+            if matches!(
+                status.and_then(|status| status.phase.as_ref()),
+                Some(SparkClusterPhase::UpgradeInProgress)
+            ) =>
+        {
+            applied
+        }
+        _ => return (sc, Vec::new()),
+    };
+    let desired = non_critical_config(&sc);
+    let mut deferred = Vec::new();
+    if desired.labels != applied.labels {
+        // Only the forwarded labels of the cluster end up on the pods
+        sc.metadata.labels = Some(applied.labels.clone());
+        deferred.push("labels".to_string());
+    }
+    if desired.pod_annotations != applied.pod_annotations {
+        sc.spec.pod_annotations = applied.pod_annotations.clone();
+        deferred.push("podAnnotations".to_string());
+    }
+    let log_levels_changed = desired.log_levels.iter().any(|(rolegroup, log_level)| {
+        applied
+            .log_levels
+            .get(rolegroup)
+            .map_or(false, |applied| applied != log_level)
+    });
+    if desired.log4j_config != applied.log4j_config || log_levels_changed {
+        sc.spec.log4j_config = applied.log4j_config.clone();
+        let cluster = ObjectRef::from_obj(&sc);
+        let applied_log_level = |role: &SparkRole, group: &str| {
+            let rolegroup = RoleGroupRef {
+                cluster: cluster.clone(),
+                role: role.to_string(),
+                role_group: group.to_string(),
+            };
+            applied.log_levels.get(&rolegroup.object_name()).cloned()
+        };
+        restore_log_levels(
+            sc.spec.masters.as_mut(),
+            |config| &mut config.node,
+            |group| applied_log_level(&SparkRole::Master, group),
+        );
+        restore_log_levels(
+            sc.spec.workers.as_mut(),
+            |config| &mut config.node,
+            |group| applied_log_level(&SparkRole::Worker, group),
+        );
+        restore_log_levels(
+            sc.spec.history_servers.as_mut(),
+            |config| &mut config.node,
+            |group| applied_log_level(&SparkRole::HistoryServer, group),
+        );
+        deferred.push("logging".to_string());
+    }
+    (sc, deferred)
+}
+
+/// Set the merged `logLevel` of every rolegroup of a role to its applied one, if any.
+///
+/// # Arguments
+/// * `role`              - The role, if configured.
+/// * `node`              - The node config of a role or rolegroup config.
+/// * `applied_log_level` - The applied `logLevel` of a rolegroup by its name, `None` if new.
+///
+fn restore_log_levels<T: Default>(
+    role: Option<&mut Role<T>>,
+    node: fn(&mut T) -> &mut NodeConfig,
+    applied_log_level: impl Fn(&str) -> Option<Option<String>>,
+) {
+    if let Some(role) = role {
+        let role_log_level = role
+            .config
+            .config
+            .as_mut()
+            .and_then(|config| node(config).log_level.take());
+        for (group, rolegroup) in role.role_groups.iter_mut() {
+            let node_config = node(rolegroup.config.config.get_or_insert_with(T::default));
+            node_config.log_level = applied_log_level(group).unwrap_or_else(|| {
+                node_config
+                    .log_level
+                    .take()
+                    .or_else(|| role_log_level.clone())
+            });
         }
     }
+}
 
+/// Clean up a cluster that is being deleted: delete the published master URL [`ConfigMap`],
+/// which is not garbage collected, and release the cluster by removing
+/// [`MASTER_URL_FINALIZER`].
+///
+/// # Arguments
+/// * `client` - The Kubernetes client.
+/// * `sc`     - The cluster resource object.
+///
+async fn finalize_cluster(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+) -> Result<ReconcilerAction, Error> {
+    let targets = sc
+        .status
+        .iter()
+        .flat_map(|status| status.published_master_url.iter())
+        .chain(sc.spec.publish_master_url.iter())
+        .collect::<BTreeSet<_>>();
+    for target in targets {
+        delete_master_url_config_map(client, target).await?;
+    }
+    update_master_url_finalizer(client, sc, false).await?;
     Ok(ReconcilerAction {
         requeue_after: None,
     })
 }
 
-/// Build the [`NodePort`] service for clients.
-fn build_master_role_service(
+/// Add or remove [`MASTER_URL_FINALIZER`], unless the cluster already is in that state.
+///
+/// # Arguments
+/// * `client`  - The Kubernetes client.
+/// * `sc`      - The cluster resource object.
+/// * `present` - Whether the finalizer should be set.
+///
+async fn update_master_url_finalizer(
+    client: &stackable_operator::client::Client,
     sc: &SparkCluster,
-    service_ports: Vec<ServicePort>,
-) -> Result<Service, Error> {
-    let role_name = SparkRole::Master.to_string();
-    let role_svc_name = sc
-        .server_role_service_name()
-        .ok_or(GlobalServiceNameNotFound {
-            obj_ref: ObjectRef::from_obj(sc),
+    present: bool,
+) -> Result<(), Error> {
+    let mut finalizers = sc.metadata.finalizers.clone().unwrap_or_default();
+    if finalizers
+        .iter()
+        .any(|finalizer| finalizer == MASTER_URL_FINALIZER)
+        == present
+    {
+        return Ok(());
+    }
+    if present {
+        finalizers.push(MASTER_URL_FINALIZER.to_string());
+    } else {
+        finalizers.retain(|finalizer| finalizer != MASTER_URL_FINALIZER);
+    }
+    // The resourceVersion makes the patch fail instead of dropping concurrently added finalizers
+    let patch = serde_json::json!({
+        "metadata": {
+            "finalizers": finalizers,
+            "resourceVersion": sc.metadata.resource_version,
+        }
+    });
+    client
+        .get_namespaced_api::<SparkCluster>(&sc.namespace().unwrap_or_default())
+        .patch(&sc.name(), &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+        .map_err(|e| UpdateFinalizers {
+            source: e,
+            sc: ObjectRef::from_obj(sc),
         })?;
-    Ok(Service {
-        metadata: ObjectMetaBuilder::new()
-            .name_and_namespace(sc)
-            .name(&role_svc_name)
-            .ownerreference_from_resource(sc, None, Some(true))
-            .map_err(|e| ObjectMissingMetadataForOwnerRef {
-                source: e,
-                obj_ref: ObjectRef::from_obj(sc),
-            })?
-            .with_recommended_labels(sc, APP_NAME, version(sc)?, &role_name, "global")
-            .build(),
-        spec: Some(ServiceSpec {
-            ports: Some(service_ports),
-            selector: Some(role_selector_labels(sc, APP_NAME, &role_name)),
-            type_: Some("NodePort".to_string()),
-            ..ServiceSpec::default()
+    Ok(())
+}
+
+/// Publish a Kubernetes `Event` about the cluster, shown by `kubectl describe sparkcluster`.
+/// Events are informational, so failures are only logged.
+///
+/// # Arguments
+/// * `client` - The Kubernetes client
+/// * `sc` - The SparkCluster the event is about
+/// * `event_type` - `Normal` or `Warning`
+/// * `reason` - A CamelCase reason of the event, e.g. `RollingUpdate`
+/// * `message` - A human readable description of the event
+async fn publish_event(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+    event_type: &str,
+    reason: &str,
+    message: &str,
+) {
+    let result = match build_event(sc, event_type, reason, message) {
+        Ok(event) => client
+            .create(&event)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(error) = result {
+        tracing::warn!(%error, reason, "Failed to publish event");
+    }
+}
+
+fn build_event(
+    sc: &SparkCluster,
+    event_type: &str,
+    reason: &str,
+    message: &str,
+) -> Result<Event, Error> {
+    let mut metadata = ObjectMetaBuilder::new()
+        .name_and_namespace(sc)
+        .ownerreference_from_resource(sc, None, Some(false))
+        .map_err(|e| Error::ObjectMissingMetadataForOwnerRef {
+            source: e,
+            obj_ref: ObjectRef::from_obj(sc),
+        })?
+        .build();
+    // Let the API server pick a unique name per event
+    metadata.name = None;
+    metadata.generate_name = Some(format!("{}.", sc.name()));
+    let now = Time(Utc::now());
+
+    Ok(Event {
+        metadata,
+        involved_object: ObjectReference {
+            api_version: Some(SparkCluster::api_version(&()).into_owned()),
+            kind: Some(SparkCluster::kind(&()).into_owned()),
+            name: Some(sc.name()),
+            namespace: sc.namespace(),
+            uid: sc.metadata.uid.clone(),
+            resource_version: sc.metadata.resource_version.clone(),
+            ..ObjectReference::default()
+        },
+        type_: Some(event_type.to_string()),
+        reason: Some(reason.to_string()),
+        message: Some(message.to_string()),
+        count: Some(1),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        source: Some(EventSource {
+            component: Some(EVENT_SOURCE_COMPONENT.to_string()),
+            ..EventSource::default()
         }),
-        status: None,
+        reporting_component: Some(EVENT_SOURCE_COMPONENT.to_string()),
+        ..Event::default()
     })
 }
 
-/// The rolegroup [`ConfigMap`] configures the rolegroup based on the configuration given by the administrator
-fn build_rolegroup_config_map(
+/// Build a status condition of the cluster. The transition time of the current condition
+/// of the same type is kept if the status did not change.
+///
+/// # Arguments
+/// * `sc` - The SparkCluster with the current conditions and generation
+/// * `condition_type` - The type of the condition, e.g. `Available`
+/// * `is_true` - Whether the condition holds
+/// * `reason` - A CamelCase reason for the status
+/// * `message` - A human readable description of the status
+fn build_condition(
     sc: &SparkCluster,
-    rolegroup: &RoleGroupRef<SparkCluster>,
-    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
-) -> Result<ConfigMap, Error> {
-    ConfigMapBuilder::new()
-        .metadata(
-            ObjectMetaBuilder::new()
-                .name_and_namespace(sc)
-                .name(rolegroup.object_name())
-                .ownerreference_from_resource(sc, None, Some(true))
-                .map_err(|e| Error::ObjectMissingMetadataForOwnerRef {
-                    source: e,
-                    obj_ref: ObjectRef::from_obj(sc),
-                })?
-                .with_recommended_labels(
-                    sc,
-                    APP_NAME,
-                    version(sc)?,
-                    &rolegroup.role,
-                    &rolegroup.role_group,
-                )
-                .build(),
-        )
-        .add_data(
-            SPARK_DEFAULTS_CONF,
-            rolegroup_config
-                .get(&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string()))
-                .map(|c| convert_map_to_string(c, " "))
-                .ok_or_else(|| SerializeSparkDefaults {
-                    rolegroup: rolegroup.clone(),
-                })?,
-        )
-        .add_data(
-            SPARK_ENV_SH,
-            rolegroup_config
-                .get(&PropertyNameKind::File(SPARK_ENV_SH.to_string()))
-                .map(|c| convert_map_to_string(c, "="))
-                .ok_or_else(|| SerializeSparkEnv {
-                    rolegroup: rolegroup.clone(),
-                })?,
-        )
-        .add_data(
-            SPARK_METRICS_PROPERTIES,
-            rolegroup_config
-                .get(&PropertyNameKind::File(
-                    SPARK_METRICS_PROPERTIES.to_string(),
-                ))
-                .and(sc.enable_monitoring())
-                .filter(|&monitoring_enabled_flag| monitoring_enabled_flag)
-                .map(|_| "\
-                            *.sink.prometheusServlet.class=org.apache.spark.metrics.sink.PrometheusServlet\n\
-                            *.sink.prometheusServlet.path=/metrics\n\
-                            *.source.jvm.class=org.apache.spark.metrics.source.JvmSource")
-                .unwrap_or_default()
-        )
-        .build()
-        .map_err(|e| Error::BuildRoleGroupConfig {
+    condition_type: &str,
+    is_true: bool,
+    reason: &str,
+    message: &str,
+) -> Condition {
+    let status = if is_true { "True" } else { "False" }.to_string();
+    let last_transition_time = sc
+        .status
+        .iter()
+        .flat_map(|sc_status| sc_status.conditions.iter())
+        .find(|condition| condition.type_ == condition_type && condition.status == status)
+        .map(|condition| condition.last_transition_time.clone())
+        .unwrap_or_else(|| Time(Utc::now()));
+    Condition {
+        last_transition_time,
+        message: message.to_string(),
+        observed_generation: sc.metadata.generation,
+        reason: reason.to_string(),
+        status,
+        type_: condition_type.to_string(),
+    }
+}
+
+/// Check the parts of the cluster definition that cannot be expressed in the CRD schema.
+fn validate_spec(sc: &SparkCluster) -> Result<(), Error> {
+    let mut profile_ids = HashSet::new();
+    for profile in sc
+        .common_config()
+        .and_then(|c| c.resource_profiles.as_ref())
+        .into_iter()
+        .flatten()
+    {
+        if RESERVED_RESOURCE_PROFILE_IDS.contains(&profile.id.as_str()) {
+            return Err(ReservedResourceProfile {
+                id: profile.id.clone(),
+            });
+        }
+        if !profile_ids.insert(&profile.id) {
+            return Err(DuplicateResourceProfile {
+                id: profile.id.clone(),
+            });
+        }
+    }
+
+    if let Some(dpp) = &sc.spec.dynamic_partition_pruning {
+        if !(0.0..=1.0).contains(&dpp.fallback_filter_ratio) {
+            return Err(InvalidFallbackFilterRatio {
+                ratio: dpp.fallback_filter_ratio,
+            });
+        }
+    }
+
+    // Workers cannot connect to anything without a master, so scaling the masters down to 0 is
+    // most likely a typo. A cluster being deleted has to pass to be cleaned up.
+    if master_count(sc) == 0 && sc.metadata.deletion_timestamp.is_none() {
+        return Err(InvalidMasterInstanceCount {
+            sc: ObjectRef::from_obj(sc),
+        });
+    }
+
+    if sc.spec.ha_config.is_some() && master_count(sc) < MINIMUM_MASTER_COUNT_FOR_HA {
+        return Err(MinimumMasterCountForHA {
+            count: master_count(sc),
+            minimum: MINIMUM_MASTER_COUNT_FOR_HA,
+        });
+    }
+
+    if let Some(common_config) = sc.common_config() {
+        if let Some(log_dir) = common_config.log_dir.as_ref().filter(|d| is_s3_url(d)) {
+            if s3_credentials_secret(sc).is_none() && sc.spec.aws_role_arn.is_none() {
+                return Err(InvalidEventLogConfig {
+                    log_dir: log_dir.clone(),
+                });
+            }
+        }
+        if let Some(log_dir) = common_config
+            .log_dir
+            .as_ref()
+            .filter(|d| sc.spec.gcp_service_account.is_some() && is_gcs_url(d))
+        {
+            // The GCS connector needs a bucket, gs:///path does not fall back to a default one
+            let bucket = log_dir.trim_start_matches("gs://").split('/').next();
+            if bucket.map_or(true, str::is_empty) {
+                return Err(InvalidGcsEventLogDir {
+                    log_dir: log_dir.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check whether any [`Node`] matching the node selector of the pods offers `nvidia.com/gpu`,
+/// otherwise pods requesting GPUs stay pending.
+///
+/// # Arguments
+/// * `client`        - The Kubernetes client.
+/// * `rolegroup_ref` - The rolegroup requesting GPUs, used for error reporting.
+/// * `statefulset`   - The rolegroup [`StatefulSet`], whose node selector the nodes must match.
+///
+async fn gpu_nodes_available(
+    client: &stackable_operator::client::Client,
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
+    statefulset: &StatefulSet,
+) -> Result<bool, Error> {
+    let mut list_params = ListParams::default();
+    if let Some(label_selector) = node_label_selector(statefulset) {
+        list_params = list_params.labels(&label_selector);
+    }
+    let nodes = client
+        .get_all_api::<Node>()
+        .list(&list_params)
+        .await
+        .map_err(|e| ListGpuNodes {
             source: e,
-            rolegroup: rolegroup.clone(),
-        })
+            rolegroup: rolegroup_ref.clone(),
+        })?;
+    Ok(nodes.items.iter().any(|node| {
+        node.status
+            .as_ref()
+            .and_then(|status| status.allocatable.as_ref())
+            .and_then(|allocatable| allocatable.get(RESOURCE_NVIDIA_GPU))
+            .map_or(false, |gpus| gpus.0 != "0")
+    }))
 }
 
-/// The rolegroup [`Service`] is a headless service that allows direct access to the instances of a certain rolegroup
+/// Check that the role group does not override the shared secret of `authentication`, since
+/// masters, workers and applications cannot connect to each other otherwise.
 ///
-/// This is mostly useful for internal communication between peers, or for clients that perform client-side load balancing.
-fn build_rolegroup_service(
+/// # Arguments
+/// * `sc`               - The cluster resource object.
+/// * `rolegroup_ref`    - The rolegroup, used for error reporting.
+/// * `rolegroup_config` - The validated configuration for the rolegroup.
+///
+fn validate_authentication(
     sc: &SparkCluster,
-    rolegroup: &RoleGroupRef<SparkCluster>,
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
     rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
-) -> Result<Service, Error> {
-    Ok(Service {
-        metadata: ObjectMetaBuilder::new()
-            .name_and_namespace(sc)
-            .name(&rolegroup.object_name())
-            .ownerreference_from_resource(sc, None, Some(true))
-            .map_err(|e| Error::ObjectMissingMetadataForOwnerRef {
-                source: e,
-                obj_ref: ObjectRef::from_obj(sc),
-            })?
-            .with_recommended_labels(
-                sc,
-                APP_NAME,
-                version(sc)?,
-                &rolegroup.role,
-                &rolegroup.role_group,
-            )
-            .build(),
-        spec: Some(ServiceSpec {
-            cluster_ip: Some("None".to_string()),
-            ports: Some(
-                build_ports(sc, rolegroup, rolegroup_config)?
-                    .iter()
-                    .map(|(name, value)| ServicePort {
-                        name: Some(name.clone()),
-                        port: *value,
-                        protocol: Some("TCP".to_string()),
-                        ..ServicePort::default()
-                    })
-                    .collect(),
-            ),
-            selector: Some(role_group_selector_labels(
-                sc,
-                APP_NAME,
-                &rolegroup.role,
-                &rolegroup.role_group,
-            )),
-            publish_not_ready_addresses: Some(true),
-            ..ServiceSpec::default()
-        }),
-        status: None,
-    })
+) -> Result<(), Error> {
+    if sc.spec.authentication.is_none() {
+        return Ok(());
+    }
+    let expected_secret = format!("${{env:{}}}", SPARK_AUTHENTICATE_SECRET_ENV);
+    let secret = rolegroup_config
+        .get(&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string()))
+        .and_then(|spark_defaults| spark_defaults.get(SPARK_DEFAULTS_AUTHENTICATE_SECRET));
+    if secret != Some(&expected_secret) {
+        return Err(InconsistentAuthenticationSecret {
+            rolegroup: rolegroup_ref.clone(),
+        });
+    }
+    Ok(())
 }
 
-/// Build the [`StatefulSet`]s for the given rolegroup.
+/// Whether no worker runs the external shuffle service required by dynamic allocation, `None`
+/// if dynamic allocation is disabled.
 ///
 /// # Arguments
-/// * `sc`                       - The cluster resource object.
-/// * `default_master_role_ports` - Master role service (and container ports). Used to build the master URLs needed by the worker pods.
-/// * `rolegroup`                 - The rolegroup.
-/// * `rolegroup_config`          - The validated configuration for the rolegroup.
+/// * `sc`               - The cluster resource object.
+/// * `validated_config` - The validated configuration of all rolegroups.
 ///
-fn build_rolegroup_statefulset(
+fn shuffle_service_missing(
     sc: &SparkCluster,
-    default_master_role_ports: &[(String, i32)],
-    rolegroup_ref: &RoleGroupRef<SparkCluster>,
-    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
-) -> Result<StatefulSet, Error> {
-    let sc_version = version(sc)?;
-    let image = format!(
-        "docker.stackable.tech/stackable/spark:{}-stackable0",
-        sc_version
-    );
-    let env = rolegroup_config
-        .get(&PropertyNameKind::Env)
-        .iter()
-        .flat_map(|env_vars| env_vars.iter())
-        .map(|(k, v)| EnvVar {
-            name: k.clone(),
-            value: Some(v.clone()),
-            ..EnvVar::default()
+    validated_config: &ValidatedRoleConfigByPropertyKind,
+) -> Option<bool> {
+    sc.spec
+        .dynamic_allocation
+        .as_ref()
+        .filter(|dynamic_allocation| dynamic_allocation.enabled)
+        .map(|_| {
+            !validated_config
+                .iter()
+                .filter(|(role_name, _groups)| SparkRole::Worker.to_string().eq(*role_name))
+                .flat_map(|(_role_name, groups)| groups.values())
+                .any(runs_shuffle_service)
         })
-        .collect::<Vec<_>>();
-
-    let container_sc = ContainerBuilder::new("spark")
-        .image(image)
-        .args(container_command(rolegroup_ref, default_master_role_ports))
-        .readiness_probe(PROBE.clone())
-        .liveness_probe(PROBE.clone())
-        .add_env_vars(env)
-        .add_container_ports(build_container_ports(sc, rolegroup_ref, rolegroup_config)?)
-        .add_volume_mount("log", spark_log_dir(rolegroup_config))
-        .add_volume_mount("config", spark_conf_dir(rolegroup_config))
-        .build();
-
-    Ok(StatefulSet {
-        metadata: ObjectMetaBuilder::new()
-            .name_and_namespace(sc)
-            .name(&rolegroup_ref.object_name())
-            .ownerreference_from_resource(sc, None, Some(true))
-            .map_err(|e| Error::ObjectMissingMetadataForOwnerRef {
-                source: e,
-                obj_ref: ObjectRef::from_obj(sc),
-            })?
-            .with_recommended_labels(
-                sc,
-                APP_NAME,
-                sc_version,
-                &rolegroup_ref.role,
-                &rolegroup_ref.role_group,
-            )
-            .build(),
-        spec: Some(StatefulSetSpec {
-            pod_management_policy: Some("Parallel".to_string()),
-            replicas: Some(rolegroup_replicas(sc, rolegroup_ref)?),
-            selector: LabelSelector {
-                match_labels: Some(role_group_selector_labels(
-                    sc,
-                    APP_NAME,
-                    &rolegroup_ref.role,
-                    &rolegroup_ref.role_group,
-                )),
-                ..LabelSelector::default()
-            },
-            service_name: rolegroup_ref.object_name(),
-            template: PodBuilder::new()
-                .metadata_builder(|m| {
-                    m.with_recommended_labels(
-                        sc,
-                        APP_NAME,
-                        sc_version,
-                        &rolegroup_ref.role,
-                        &rolegroup_ref.role_group,
-                    )
-                })
-                .add_container(container_sc)
-                .add_volume(Volume {
-                    name: "config".to_string(),
-                    config_map: Some(ConfigMapVolumeSource {
-                        name: Some(rolegroup_ref.object_name()),
-                        ..ConfigMapVolumeSource::default()
-                    }),
-                    ..Volume::default()
-                })
-                .build_template(),
-            volume_claim_templates: Some(vec![PersistentVolumeClaim {
-                metadata: ObjectMeta {
-                    name: Some("log".to_string()),
-                    ..ObjectMeta::default()
-                },
-                spec: Some(PersistentVolumeClaimSpec {
-                    access_modes: Some(vec!["ReadWriteOnce".to_string()]),
-                    resources: Some(ResourceRequirements {
-                        requests: Some({
-                            let mut map = BTreeMap::new();
-                            map.insert("storage".to_string(), Quantity("1Gi".to_string()));
-                            map
-                        }),
-                        ..ResourceRequirements::default()
-                    }),
-                    ..PersistentVolumeClaimSpec::default()
-                }),
-                ..PersistentVolumeClaim::default()
-            }]),
-            ..StatefulSetSpec::default()
-        }),
-        status: None,
-    })
 }
 
-pub fn error_policy(_error: &Error, _ctx: Context<Ctx>) -> ReconcilerAction {
-    ReconcilerAction {
-        requeue_after: Some(Duration::from_secs(5)),
-    }
+/// Whether `spark.shuffle.service.enabled` is set for the rolegroup, i.e. whether its workers
+/// run the external shuffle service.
+fn runs_shuffle_service(
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+) -> bool {
+    rolegroup_config
+        .get(&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string()))
+        .and_then(|spark_defaults| spark_defaults.get(SPARK_DEFAULTS_SHUFFLE_SERVICE_ENABLED))
+        .map_or(false, |enabled| enabled == "true")
 }
 
-/// TODO: this is pure boilerplate code that should be part of product-config.
-fn build_spark_role_properties(
-    resource: &SparkCluster,
-) -> HashMap<
-    String,
-    (
-        Vec<PropertyNameKind>,
-        Role<impl Configuration<Configurable = SparkCluster>>,
-    ),
-> {
-    let mut result = HashMap::new();
-    let pnk: Vec<PropertyNameKind> = vec![
-        PropertyNameKind::File(SPARK_ENV_SH.to_string()),
-        PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string()),
-        PropertyNameKind::File(SPARK_METRICS_PROPERTIES.to_string()),
-        PropertyNameKind::Env,
-    ];
-    if let Some(masters) = &resource.spec.masters {
-        result.insert(
-            SparkRole::Master.to_string(),
-            (pnk.clone(), masters.clone().erase()),
-        );
-    }
-    if let Some(workers) = &resource.spec.workers {
-        result.insert(
-            SparkRole::Worker.to_string(),
-            (pnk.clone(), workers.clone().erase()),
-        );
-    }
-    if let Some(history_servers) = &resource.spec.history_servers {
-        result.insert(
-            SparkRole::HistoryServer.to_string(),
-            (pnk, history_servers.clone().erase()),
+/// The annotations of the Vault agent injector, empty without a `vault_role`. The injector
+/// writes each secret to the file name of its mount path in the directory of the mount path.
+///
+/// # Arguments
+/// * `node_config` - The merged node config of the rolegroup.
+///
+fn vault_annotations(node_config: &NodeConfig) -> BTreeMap<String, String> {
+    let mut annotations = BTreeMap::new();
+    let vault_role = match &node_config.vault_role {
+        Some(vault_role) => vault_role,
+        None => return annotations,
+    };
+    annotations.insert(
+        VAULT_AGENT_INJECT_ANNOTATION.to_string(),
+        "true".to_string(),
+    );
+    annotations.insert(VAULT_ROLE_ANNOTATION.to_string(), vault_role.clone());
+    for secret in node_config.vault_secrets.iter().flatten() {
+        let mount_path = Path::new(&secret.mount_path);
+        let name = match mount_path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        annotations.insert(
+            format!("{}{}", VAULT_AGENT_INJECT_SECRET_ANNOTATION_PREFIX, name),
+            secret.path.clone(),
         );
+        if let Some(directory) = mount_path.parent().and_then(|directory| directory.to_str()) {
+            annotations.insert(
+                format!("{}{}", VAULT_SECRET_VOLUME_PATH_ANNOTATION_PREFIX, name),
+                directory.to_string(),
+            );
+        }
     }
-    result
+    annotations
 }
 
-/// Build [`ContainerPort`]s for the given rolegroup.
+/// Check that at least one [`Node`] matches the node selector of the rolegroup pods.
+///
+/// Rolegroups without a node selector can be scheduled on any node.
 ///
 /// # Arguments
-/// * `_sc`              - The cluster resource object.
-/// * `rolegroup`        - The rolegroup for which to extract the pods.
-/// * `rolegroup_config` - The validated configuration for the rolegroup.
+/// * `client`         - The Kubernetes client.
+/// * `rolegroup_ref`  - The rolegroup, used for error reporting.
+/// * `statefulset`    - The rolegroup [`StatefulSet`] carrying the pod node selector.
 ///
-fn build_container_ports(
-    _sc: &SparkCluster,
-    rolegroup: &RoleGroupRef<SparkCluster>,
-    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
-) -> Result<Vec<ContainerPort>, Error> {
-    Ok(build_ports(_sc, rolegroup, rolegroup_config)?
-        .iter()
-        .map(|(name, value)| ContainerPort {
-            name: Some(name.clone()),
-            container_port: *value,
-            protocol: Some("TCP".to_string()),
-            ..ContainerPort::default()
-        })
-        .collect())
+async fn verify_node_pool_capacity(
+    client: &stackable_operator::client::Client,
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
+    statefulset: &StatefulSet,
+) -> Result<bool, Error> {
+    let label_selector = match node_label_selector(statefulset) {
+        Some(label_selector) => label_selector,
+        None => return Ok(true),
+    };
+    let nodes = client
+        .get_all_api::<Node>()
+        .list(&ListParams::default().labels(&label_selector).limit(1))
+        .await
+        .map_err(|e| ListNodes {
+            source: e,
+            rolegroup: rolegroup_ref.clone(),
+        })?;
+    Ok(!nodes.items.is_empty())
 }
 
-/// Extract all named ports from the given validated configuration.
+/// The volume claim templates of a [`StatefulSet`] are immutable, so a StatefulSet whose
+/// templates differ from `statefulset` is deleted, orphaning its pods. Returns whether the
+/// StatefulSet is still being deleted, in that case it must not be applied yet.
+///
+/// The recreated StatefulSet adopts the orphaned pods and replaces them one at a time.
 ///
 /// # Arguments
-/// * `_sc`              - The cluster resource object.
-/// * `rolegroup`        - The rolegroup for which to extract the pods.
-/// * `rolegroup_config` - The validated configuration for the rolegroup.
+/// * `client`        - The Kubernetes client.
+/// * `rolegroup_ref` - The rolegroup, used for error reporting.
+/// * `statefulset`   - The rolegroup [`StatefulSet`] about to be applied.
 ///
-fn build_ports(
-    _sc: &SparkCluster,
-    rolegroup: &RoleGroupRef<SparkCluster>,
-    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
-) -> Result<Vec<(String, i32)>, Error> {
-    Ok(match serde_yaml::from_str(&rolegroup.role).unwrap() {
-        SparkRole::Master => vec![
-            (
-                PORT_NAME_SPARK.to_string(),
-                rolegroup_config
-                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
-                    .and_then(|c| c.get(SPARK_ENV_MASTER_PORT))
-                    .unwrap_or(&String::from("7077"))
-                    .parse::<i32>()
-                    .map_err(|e| Error::InvalidPort {
-                        source: e,
-                        rolegroup_ref: rolegroup.clone(),
-                    })?,
-            ),
-            (
-                String::from(PORT_NAME_WEB),
-                rolegroup_config
-                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
-                    .and_then(|c| c.get(SPARK_ENV_MASTER_WEBUI_PORT))
-                    .unwrap_or(&String::from("8080"))
-                    .parse::<i32>()
-                    .map_err(|e| Error::InvalidPort {
-                        source: e,
-                        rolegroup_ref: rolegroup.clone(),
-                    })?,
-            ),
-        ],
-        SparkRole::Worker => vec![
-            (
-                PORT_NAME_SPARK.to_string(),
-                rolegroup_config
-                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
-                    .and_then(|c| c.get(SPARK_ENV_WORKER_PORT))
-                    .unwrap_or(&String::from("7077"))
-                    .parse::<i32>()
-                    .map_err(|e| Error::InvalidPort {
-                        source: e,
-                        rolegroup_ref: rolegroup.clone(),
-                    })?,
-            ),
-            (
-                String::from(PORT_NAME_WEB),
-                rolegroup_config
-                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
-                    .and_then(|c| c.get(SPARK_ENV_WORKER_WEBUI_PORT))
-                    .unwrap_or(&String::from("8080"))
-                    .parse::<i32>()
-                    .map_err(|e| Error::InvalidPort {
-                        source: e,
-                        rolegroup_ref: rolegroup.clone(),
-                    })?,
-            ),
-        ],
-        SparkRole::HistoryServer => vec![(
-            String::from(PORT_NAME_WEB),
-            rolegroup_config
-                .get(&PropertyNameKind::File(String::from(SPARK_DEFAULTS_CONF)))
-                .and_then(|c| c.get(SPARK_DEFAULTS_HISTORY_WEBUI_PORT))
-                .unwrap_or(&String::from("8080"))
-                .parse::<i32>()
-                .map_err(|e| Error::InvalidPort {
-                    source: e,
-                    rolegroup_ref: rolegroup.clone(),
-                })?,
-        )],
-    })
+async fn delete_statefulset_with_changed_claims(
+    client: &stackable_operator::client::Client,
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
+    statefulset: &StatefulSet,
+) -> Result<bool, Error> {
+    let api = client.get_namespaced_api::<StatefulSet>(
+        statefulset
+            .metadata
+            .namespace
+            .as_deref()
+            .unwrap_or_default(),
+    );
+    let name = statefulset.metadata.name.as_deref().unwrap_or_default();
+    let existing = match api.get(name).await {
+        Ok(existing) => existing,
+        Err(kube::Error::Api(response)) if response.code == 404 => return Ok(false),
+        Err(e) => {
+            return Err(RecreateRoleGroupStatefulSet {
+                source: e,
+                rolegroup: rolegroup_ref.clone(),
+            })
+        }
+    };
+    if existing.metadata.deletion_timestamp.is_some() {
+        return Ok(true);
+    }
+    if !volume_claim_templates_changed(&existing, statefulset) {
+        return Ok(false);
+    }
+    api.delete(
+        name,
+        &DeleteParams {
+            propagation_policy: Some(PropagationPolicy::Orphan),
+            ..DeleteParams::default()
+        },
+    )
+    .await
+    .map_err(|e| RecreateRoleGroupStatefulSet {
+        source: e,
+        rolegroup: rolegroup_ref.clone(),
+    })?;
+    Ok(true)
 }
 
-/// Unroll a map into a String using a given assignment character (for writing config maps)
-///
-/// # Arguments
-/// * `map`        - Map containing option_name:option_value pairs
-/// * `assignment` - Used character to assign option_value to option_name (e.g. "=", " ", ":" ...)
-///
-fn convert_map_to_string(map: &BTreeMap<String, String>, assignment: &str) -> String {
-    let mut data = String::new();
-    for (key, value) in map {
-        data.push_str(format!("{}{}{}\n", key, assignment, value).as_str());
-    }
-    data
+/// Whether the names of the volume claim templates differ. The templates of the operator do
+/// not change otherwise, and the API server adds defaults to the applied ones.
+fn volume_claim_templates_changed(existing: &StatefulSet, statefulset: &StatefulSet) -> bool {
+    let claim_names = |statefulset: &StatefulSet| {
+        statefulset
+            .spec
+            .iter()
+            .flat_map(|spec| spec.volume_claim_templates.iter().flatten())
+            .map(|claim| claim.metadata.name.clone())
+            .collect::<Vec<_>>()
+    };
+    claim_names(existing) != claim_names(statefulset)
 }
 
-/// Build the connection string for the start-worker.sh script
+/// Replacing a master kills the applications it runs, so a master [`StatefulSet`] that would
+/// replace pods is not applied while the elected master reports running applications, for at
+/// most `drainTimeoutSeconds`. Returns the existing StatefulSet and the start of the drain if
+/// the replacement is deferred.
+///
+/// The replacement proceeds right away if no master can be reached, and with a warning event
+/// once the timeout passed.
 ///
 /// # Arguments
-/// * `rolegroup_ref`             - The worker's RoleGroupRef.
+/// * `client`                    - The Kubernetes client.
+/// * `sc`                        - The cluster resource object.
+/// * `rolegroup_ref`             - The master rolegroup.
+/// * `statefulset`               - The rolegroup [`StatefulSet`] about to be applied.
 /// * `default_master_role_ports` - The ports used to create the master's ClusterIP service.
+/// * `drain_start_time`          - Since when replacements of the last reconciles were deferred.
 ///
-fn build_master_service_url(
+async fn defer_master_replacement(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
     rolegroup_ref: &RoleGroupRef<SparkCluster>,
+    statefulset: &StatefulSet,
     default_master_role_ports: &[(String, i32)],
-) -> String {
-    let default_master_port = default_master_role_ports
-        .iter()
-        .filter_map(|(name, value)| match name.as_ref() {
-            PORT_NAME_SPARK => Some(*value),
-            _ => None,
-        })
-        .take(1)
-        .next()
-        .unwrap_or(7077);
-
-    format!(
-        "spark://{}:{}",
-        RoleGroupRef {
-            cluster: rolegroup_ref.cluster.clone(),
-            role: SparkRole::Master.to_string(),
-            role_group: "default".to_string(),
+    drain_start_time: Option<&Time>,
+) -> Result<Option<(StatefulSet, Time)>, Error> {
+    let api = client.get_namespaced_api::<StatefulSet>(
+        statefulset
+            .metadata
+            .namespace
+            .as_deref()
+            .unwrap_or_default(),
+    );
+    let name = statefulset.metadata.name.as_deref().unwrap_or_default();
+    let existing = match api.get(name).await {
+        Ok(existing) => existing,
+        Err(kube::Error::Api(response)) if response.code == 404 => return Ok(None),
+        Err(e) => {
+            return Err(GetRoleGroupStatefulSet {
+                source: e,
+                rolegroup: rolegroup_ref.clone(),
+            })
         }
-        .object_name(),
-        default_master_port
+    };
+    if !master_pods_replaced(&existing, statefulset) {
+        return Ok(None);
+    }
+    let active_apps = fetch_leader_state(sc, default_master_role_ports)
+        .await
+        .map(|state| state.active_apps.len())
+        .unwrap_or(0);
+    if active_apps == 0 {
+        return Ok(None);
+    }
+    let drain_start_time = drain_start_time
+        .cloned()
+        .unwrap_or_else(|| Time(Utc::now()));
+    let drain_timeout = sc
+        .node_config(&SparkRole::Master, &rolegroup_ref.role_group)
+        .drain_timeout_seconds
+        .unwrap_or(DEFAULT_MASTER_DRAIN_TIMEOUT_SECONDS);
+    if Utc::now()
+        .signed_duration_since(drain_start_time.0)
+        .num_seconds()
+        < drain_timeout as i64
+    {
+        return Ok(Some((existing, drain_start_time)));
+    }
+    publish_event(
+        client,
+        sc,
+        EVENT_TYPE_WARNING,
+        "MasterDrainTimeout",
+        &format!(
+            "replacing the masters of {} after {}s with {} applications still running",
+            rolegroup_ref, drain_timeout, active_apps
+        ),
     )
+    .await;
+    Ok(None)
 }
 
-/// Extract the SPARK_CONF_DIR path from the validated rolegroup configuration.
+/// Whether applying `statefulset` over `existing` replaces pods: it has fewer replicas or a
+/// different pod template. A StatefulSet without a template hash, i.e. one applied by an older
+/// operator version, counts as changed.
+fn master_pods_replaced(existing: &StatefulSet, statefulset: &StatefulSet) -> bool {
+    let replicas = |statefulset: &StatefulSet| {
+        statefulset
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.replicas)
+            .unwrap_or(1)
+    };
+    let template_hash = |statefulset: &StatefulSet| {
+        statefulset
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(POD_TEMPLATE_HASH_ANNOTATION))
+            .cloned()
+    };
+    replicas(statefulset) < replicas(existing)
+        || template_hash(existing).is_none()
+        || template_hash(existing) != template_hash(statefulset)
+}
+
+/// The label selector of the nodes matching the node selector of the pods, `None` if the pods
+/// have no node selector.
+fn node_label_selector(statefulset: &StatefulSet) -> Option<String> {
+    statefulset
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.template.spec.as_ref())
+        .and_then(|pod_spec| pod_spec.node_selector.as_ref())
+        .filter(|node_selector| !node_selector.is_empty())
+        .map(|node_selector| {
+            node_selector
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+}
+
+/// Hash all managed `Secret`s of the cluster, see [`secrets_hash`].
 ///
-/// # Arguments
-/// * `rolegroup_config` - Validated config for a rolegroup.
+/// Managed secrets are labeled with [`MANAGED_SECRET_LABEL`]`=true` and the cluster instance,
+/// and live in the namespace of the cluster.
+async fn managed_secrets_hash(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+) -> Result<String, Error> {
+    let list_params = ListParams::default().labels(&format!(
+        "{}=true,{}={}",
+        MANAGED_SECRET_LABEL,
+        APP_INSTANCE_LABEL,
+        sc.name()
+    ));
+    let secrets = client
+        .get_namespaced_api::<Secret>(&sc.namespace().unwrap_or_default())
+        .list(&list_params)
+        .await
+        .map_err(|e| ListManagedSecrets {
+            source: e,
+            sc: ObjectRef::from_obj(sc),
+        })?;
+    Ok(secrets_hash(&secrets.items))
+}
+
+/// Hash the names and data of `Secret`s, independent of their order.
 ///
-fn spark_conf_dir(
-    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
-) -> String {
-    rolegroup_config
-        .get(&PropertyNameKind::Env)
-        .iter()
-        .flat_map(|env_vars| env_vars.iter())
-        .filter_map(|(k, v)| match k.as_ref() {
-            SPARK_CONF_DIR => Some(v.clone()),
-            _ => None,
-        })
-        .take(1)
-        .next()
-        .unwrap_or_else(|| "/stackable/config".to_string())
+/// The hash is added to the pod template, so rotating such a secret restarts the pods. Changes
+/// of the metadata only, like labels, do not.
+fn secrets_hash(secrets: &[Secret]) -> String {
+    let mut secrets = secrets.iter().collect::<Vec<_>>();
+    secrets.sort_by(|a, b| a.metadata.name.cmp(&b.metadata.name));
+    sha256_digest(secrets.into_iter().flat_map(|secret| {
+        let mut parts = vec![secret
+            .metadata
+            .name
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes()];
+        for (key, value) in secret.data.iter().flatten() {
+            parts.push(key.as_bytes());
+            parts.push(&value.0);
+        }
+        parts
+    }))
 }
 
-/// Extract the log path from the validated rolegroup configuration.
+/// The time the managed `Secret`s of the cluster were rotated last, i.e. now if their hash
+/// differs from the one of the last reconcile. The first reconcile is not a rotation.
 ///
 /// # Arguments
-/// * `rolegroup_config` - Validated config for a rolegroup.
+/// * `sc`                   - The cluster resource object with the status of the last reconcile.
+/// * `managed_secrets_hash` - The current hash of the managed `Secret`s.
 ///
-fn spark_log_dir(rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>) -> String {
-    rolegroup_config
-        .get(&PropertyNameKind::File(String::from(SPARK_DEFAULTS_CONF)))
-        .iter()
-        .flat_map(|vars| vars.iter())
-        .filter_map(|(k, v)| match k.as_ref() {
-            SPARK_DEFAULTS_EVENT_LOG_DIR => Some(v.clone()),
-            _ => None,
-        })
-        .take(1)
-        .next()
-        .unwrap_or_else(|| DEFAULT_LOG_DIR.to_string())
+fn secret_rotation_time(sc: &SparkCluster, managed_secrets_hash: &str) -> Option<Time> {
+    let status = sc.status.as_ref()?;
+    match &status.managed_secrets_hash {
+        Some(previous) if previous != managed_secrets_hash => Some(Time(Utc::now())),
+        _ => status.secret_rotation_time.clone(),
+    }
 }
 
-fn version(sc: &SparkCluster) -> Result<&str, Error> {
-    sc.spec.version.as_deref().ok_or(ObjectHasNoVersion {
-        obj_ref: ObjectRef::from_obj(sc),
-    })
+/// Hash the content of a [`ConfigMap`].
+///
+/// The hash is added to the pod template, so configuration changes restart the pods
+/// (Spark only reads its configuration at startup). The data is sorted by key, so the hash
+/// only changes with the content.
+fn config_map_hash(config_map: &ConfigMap) -> String {
+    sha256_digest(
+        config_map
+            .data
+            .iter()
+            .flatten()
+            .flat_map(|(key, value)| vec![key.as_bytes(), value.as_bytes()]),
+    )
 }
 
-fn container_command(
-    rolegroup_ref: &RoleGroupRef<SparkCluster>,
-    default_master_role_ports: &[(String, i32)],
-) -> Vec<String> {
-    match serde_yaml::from_str(&rolegroup_ref.role).unwrap() {
-        SparkRole::Master => vec!["sbin/start-master.sh".to_string()],
-        SparkRole::HistoryServer => vec!["sbin/start-history-server.sh".to_string()],
-        SparkRole::Worker => vec![
-            "sbin/start-slave.sh".to_string(),
-            build_master_service_url(rolegroup_ref, default_master_role_ports),
-        ],
+/// Hash a pod template, see [`POD_TEMPLATE_HASH_ANNOTATION`].
+///
+/// The API server adds defaults to the template of an applied [`StatefulSet`], so the hash of
+/// the template generated by the operator is compared instead. Serialized maps are sorted by
+/// key, so the hash only changes with the content.
+fn pod_template_hash(pod_template: &PodTemplateSpec) -> String {
+    // Serializing Kubernetes objects to JSON cannot fail
+    let json = serde_json::to_vec(pod_template).unwrap_or_default();
+    sha256_digest(vec![json.as_slice()])
+}
+
+/// The hex encoded SHA-256 digest of `parts`. Unlike the [`std::hash::Hasher`]s it is stable
+/// across Rust releases, so an operator upgrade does not restart all pods. Every part is
+/// terminated by a 0 byte, so that moving bytes between adjacent parts changes the digest.
+fn sha256_digest<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+        hasher.update([0]);
     }
+    format!("{:x}", hasher.finalize())
 }
 
-fn rolegroup_replicas(
+/// Build the [`NodePort`] service for clients.
+fn build_master_role_service(
     sc: &SparkCluster,
-    rolegroup_ref: &RoleGroupRef<SparkCluster>,
-) -> Result<i32, Error> {
-    let replicas = match serde_yaml::from_str(&rolegroup_ref.role).unwrap() {
-        SparkRole::Worker => sc
-            .spec
-            .workers
-            .as_ref()
-            .ok_or(Error::MissingRoleGroup {
-                obj_ref: rolegroup_ref.clone(),
-            })?
-            .role_groups
-            .get(&rolegroup_ref.role_group)
-            .and_then(|rg| rg.replicas)
-            .map(i32::from)
-            .unwrap_or(0),
-        SparkRole::HistoryServer => sc
-            .spec
-            .history_servers
-            .as_ref()
-            .ok_or(Error::MissingRoleGroup {
-                obj_ref: rolegroup_ref.clone(),
-            })?
-            .role_groups
-            .get(&rolegroup_ref.role_group)
-            .and_then(|rg| rg.replicas)
-            .map(i32::from)
-            .unwrap_or(0),
-        SparkRole::Master => sc
-            .spec
-            .masters
-            .as_ref()
-            .ok_or(Error::MissingRoleGroup {
-                obj_ref: rolegroup_ref.clone(),
+    service_ports: Vec<ServicePort>,
+) -> Result<Service, Error> {
+    let role_name = SparkRole::Master.to_string();
+    let role_svc_name = sc
+        .server_role_service_name()
+        .ok_or(GlobalServiceNameNotFound {
+            obj_ref: ObjectRef::from_obj(sc),
+        })?;
+    Ok(Service {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(&role_svc_name)
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
             })?
-            .role_groups
-            .get(&rolegroup_ref.role_group)
-            .and_then(|rg| rg.replicas)
-            .map(i32::from)
-            .unwrap_or(0),
-    };
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, &role_name, "global")
+            .build(),
+        spec: Some(ServiceSpec {
+            ports: Some(service_ports),
+            selector: Some(role_selector_labels(sc, APP_NAME, &role_name)),
+            type_: Some("NodePort".to_string()),
+            ..ServiceSpec::default()
+        }),
+        status: None,
+    })
+}
 
-    if sc.spec.stopped.unwrap_or(false) {
-        Ok(0)
-    } else {
+/// Apply the [`Ingress`] of the master web UI, or delete it if `masterUiIngress` is not set
+/// (anymore).
+///
+/// # Arguments
+/// * `client` - The Kubernetes client.
+/// * `sc`     - The cluster resource object.
+///
+async fn reconcile_master_ingress(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+) -> Result<(), Error> {
+    let name = format!("{}-{}-ui", sc.name(), SparkRole::Master);
+    let ingress_config = match &sc.spec.master_ui_ingress {
+        Some(ingress_config) => ingress_config,
+        None => {
+            return delete_ingress(client, sc, &name)
+                .await
+                .map_err(|e| DeleteMasterIngress {
+                    source: e,
+                    sc: ObjectRef::from_obj(sc),
+                })
+        }
+    };
+    let service_name = sc
+        .server_role_service_name()
+        .ok_or(GlobalServiceNameNotFound {
+            obj_ref: ObjectRef::from_obj(sc),
+        })?;
+    let ingress = build_ui_ingress(
+        sc,
+        &SparkRole::Master,
+        &name,
+        ingress_config,
+        &service_name,
+        false,
+    )?;
+    client
+        .apply_patch(FIELD_MANAGER_SCOPE, &ingress, &ingress)
+        .await
+        .map_err(|e| ApplyMasterIngress {
+            source: e,
+            sc: ObjectRef::from_obj(sc),
+        })?;
+    Ok(())
+}
+
+/// Apply the [`Ingress`] of the history server web UI, or delete it if `historyServerIngress`
+/// is not set or the cluster has no history server (anymore).
+///
+/// # Arguments
+/// * `client`        - The Kubernetes client.
+/// * `sc`            - The cluster resource object.
+/// * `reverse_proxy` - Whether `spark.ui.reverseProxy` is enabled, `None` without history server.
+///
+async fn reconcile_history_server_ingress(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+    reverse_proxy: Option<bool>,
+) -> Result<(), Error> {
+    let name = format!("{}-history-ui", sc.name());
+    let (ingress_config, reverse_proxy) = match (&sc.spec.history_server_ingress, reverse_proxy) {
+        (Some(ingress_config), Some(reverse_proxy)) => (ingress_config, reverse_proxy),
+        _ => {
+            return delete_ingress(client, sc, &name).await.map_err(|e| {
+                DeleteHistoryServerIngress {
+                    source: e,
+                    sc: ObjectRef::from_obj(sc),
+                }
+            })
+        }
+    };
+    let service_name = sc
+        .history_server_role_service_name()
+        .ok_or(GlobalServiceNameNotFound {
+            obj_ref: ObjectRef::from_obj(sc),
+        })?;
+    let ingress = build_ui_ingress(
+        sc,
+        &SparkRole::HistoryServer,
+        &name,
+        ingress_config,
+        &service_name,
+        reverse_proxy,
+    )?;
+    client
+        .apply_patch(FIELD_MANAGER_SCOPE, &ingress, &ingress)
+        .await
+        .map_err(|e| ApplyHistoryServerIngress {
+            source: e,
+            sc: ObjectRef::from_obj(sc),
+        })?;
+    Ok(())
+}
+
+/// Build an [`Ingress`] routing the host of `ingress_config` to the web UI port of a role
+/// `Service`.
+///
+/// # Arguments
+/// * `sc`             - The cluster resource object.
+/// * `role`           - The role of the web UI.
+/// * `name`           - The name of the `Ingress`.
+/// * `ingress_config` - The host, TLS and class of the `Ingress`.
+/// * `service_name`   - The `Service` of the role.
+/// * `rewrite_path`   - Strip the path prefix via ingress-nginx, for UIs behind
+///                      `spark.ui.reverseProxy` that expect to be served from `/`.
+///
+fn build_ui_ingress(
+    sc: &SparkCluster,
+    role: &SparkRole,
+    name: &str,
+    ingress_config: &IngressConfig,
+    service_name: &str,
+    rewrite_path: bool,
+) -> Result<Ingress, Error> {
+    let path_prefix = ingress_config
+        .path_prefix
+        .as_deref()
+        .unwrap_or("/")
+        .trim_end_matches('/');
+    let mut annotations = BTreeMap::new();
+    let (path, path_type) = if rewrite_path && !path_prefix.is_empty() {
+        annotations.insert(
+            ANNOTATION_NGINX_REWRITE_TARGET.to_string(),
+            "/$2".to_string(),
+        );
+        (
+            format!("{}(/|$)(.*)", path_prefix),
+            "ImplementationSpecific",
+        )
+    } else {
+        let path = if path_prefix.is_empty() {
+            "/"
+        } else {
+            path_prefix
+        };
+        (path.to_string(), "Prefix")
+    };
+
+    Ok(Ingress {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(name)
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, &role.to_string(), "global")
+            .with_annotations(annotations)
+            .build(),
+        spec: Some(IngressSpec {
+            ingress_class_name: ingress_config.ingress_class_name.clone(),
+            rules: Some(vec![IngressRule {
+                host: Some(ingress_config.host.clone()),
+                http: Some(HTTPIngressRuleValue {
+                    paths: vec![HTTPIngressPath {
+                        path: Some(path),
+                        path_type: Some(path_type.to_string()),
+                        backend: IngressBackend {
+                            service: Some(IngressServiceBackend {
+                                name: service_name.to_string(),
+                                port: Some(ServiceBackendPort {
+                                    name: Some(PORT_NAME_WEB.to_string()),
+                                    ..ServiceBackendPort::default()
+                                }),
+                            }),
+                            ..IngressBackend::default()
+                        },
+                    }],
+                }),
+            }]),
+            tls: ingress_config.tls_secret.as_ref().map(|tls_secret| {
+                vec![IngressTLS {
+                    hosts: Some(vec![ingress_config.host.clone()]),
+                    secret_name: Some(tls_secret.clone()),
+                }]
+            }),
+            ..IngressSpec::default()
+        }),
+        status: None,
+    })
+}
+
+/// Delete an [`Ingress`] of the cluster, if it exists.
+async fn delete_ingress(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+    name: &str,
+) -> Result<(), stackable_operator::error::Error> {
+    let ingress = Ingress {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(name)
+            .build(),
+        ..Ingress::default()
+    };
+    match client.delete(&ingress).await {
+        Ok(_) => Ok(()),
+        Err(stackable_operator::error::Error::KubeError {
+            source: kube::Error::Api(response),
+            ..
+        }) if response.code == 404 => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Apply the [`NetworkPolicy`] of the Spark pods if `enableNetworkPolicy` is set, or delete it
+/// otherwise.
+///
+/// # Arguments
+/// * `client`             - The Kubernetes client.
+/// * `sc`                 - The cluster resource object.
+/// * `operator_namespace` - The namespace of the operator pods, if known.
+///
+async fn reconcile_network_policy(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+    operator_namespace: Option<&str>,
+) -> Result<(), Error> {
+    if !sc.spec.enable_network_policy.unwrap_or(false) {
+        let network_policy = NetworkPolicy {
+            metadata: ObjectMetaBuilder::new()
+                .name_and_namespace(sc)
+                .name(sc.name())
+                .build(),
+            ..NetworkPolicy::default()
+        };
+        return match client.delete(&network_policy).await {
+            Ok(_) => Ok(()),
+            Err(stackable_operator::error::Error::KubeError {
+                source: kube::Error::Api(response),
+                ..
+            }) if response.code == 404 => Ok(()),
+            Err(e) => Err(DeleteNetworkPolicy {
+                source: e,
+                sc: ObjectRef::from_obj(sc),
+            }),
+        };
+    }
+
+    let network_policy = build_network_policy(sc, operator_namespace)?;
+    client
+        .apply_patch(FIELD_MANAGER_SCOPE, &network_policy, &network_policy)
+        .await
+        .map_err(|e| ApplyNetworkPolicy {
+            source: e,
+            sc: ObjectRef::from_obj(sc),
+        })?;
+    Ok(())
+}
+
+/// Build the [`NetworkPolicy`] allowing ingress to the Spark pods only
+/// * from the other pods of the cluster,
+/// * to the master port from pods labeled `spark.stackable.tech/client=true`,
+/// * to the web UI ports from the pods of the ingress controller namespace and from the operator
+///   pods in `operator_namespace`, if the operator runs in the Kubernetes cluster.
+///
+/// Egress is not restricted.
+fn build_network_policy(
+    sc: &SparkCluster,
+    operator_namespace: Option<&str>,
+) -> Result<NetworkPolicy, Error> {
+    let cluster_labels = vec![
+        (APP_NAME_LABEL.to_string(), APP_NAME.to_string()),
+        (APP_INSTANCE_LABEL.to_string(), sc.name()),
+    ]
+    .into_iter()
+    .collect::<BTreeMap<_, _>>();
+    let named_port = |name: &str| NetworkPolicyPort {
+        port: Some(IntOrString::String(name.to_string())),
+        protocol: Some("TCP".to_string()),
+        ..NetworkPolicyPort::default()
+    };
+    let ingress_controller_namespace = sc
+        .spec
+        .ingress_controller_namespace
+        .clone()
+        .unwrap_or_else(|| DEFAULT_INGRESS_CONTROLLER_NAMESPACE.to_string());
+    let namespace_selector = |namespace: &str| LabelSelector {
+        match_labels: Some(
+            vec![(LABEL_NAMESPACE_NAME.to_string(), namespace.to_string())]
+                .into_iter()
+                .collect(),
+        ),
+        ..LabelSelector::default()
+    };
+    let mut web_ui_peers = vec![NetworkPolicyPeer {
+        namespace_selector: Some(namespace_selector(&ingress_controller_namespace)),
+        ..NetworkPolicyPeer::default()
+    }];
+    // The operator reads the state of the masters from their web UI
+    if let Some(operator_namespace) = operator_namespace {
+        web_ui_peers.push(NetworkPolicyPeer {
+            namespace_selector: Some(namespace_selector(operator_namespace)),
+            pod_selector: Some(LabelSelector {
+                match_labels: Some(
+                    vec![(APP_NAME_LABEL.to_string(), OPERATOR_APP_NAME.to_string())]
+                        .into_iter()
+                        .collect(),
+                ),
+                ..LabelSelector::default()
+            }),
+            ..NetworkPolicyPeer::default()
+        });
+    }
+
+    Ok(NetworkPolicy {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(sc.name())
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, "global", "global")
+            .build(),
+        spec: Some(NetworkPolicySpec {
+            pod_selector: LabelSelector {
+                match_labels: Some(cluster_labels.clone()),
+                ..LabelSelector::default()
+            },
+            ingress: Some(vec![
+                NetworkPolicyIngressRule {
+                    from: Some(vec![NetworkPolicyPeer {
+                        pod_selector: Some(LabelSelector {
+                            match_labels: Some(cluster_labels),
+                            ..LabelSelector::default()
+                        }),
+                        ..NetworkPolicyPeer::default()
+                    }]),
+                    ports: None,
+                },
+                NetworkPolicyIngressRule {
+                    from: Some(vec![NetworkPolicyPeer {
+                        pod_selector: Some(LabelSelector {
+                            match_labels: Some(
+                                vec![(SPARK_CLIENT_LABEL.to_string(), "true".to_string())]
+                                    .into_iter()
+                                    .collect(),
+                            ),
+                            ..LabelSelector::default()
+                        }),
+                        ..NetworkPolicyPeer::default()
+                    }]),
+                    ports: Some(vec![
+                        named_port(PORT_NAME_SPARK),
+                        named_port(PORT_NAME_REST),
+                    ]),
+                },
+                NetworkPolicyIngressRule {
+                    from: Some(web_ui_peers),
+                    ports: Some(vec![named_port(PORT_NAME_WEB)]),
+                },
+            ]),
+            // Only ingress is restricted, egress stays open
+            policy_types: Some(vec!["Ingress".to_string()]),
+            ..NetworkPolicySpec::default()
+        }),
+    })
+}
+
+/// Build the [`ServiceAccount`] managed for clusters that do not bring their own.
+///
+/// It is owned by the cluster and deleted along with it. A `ServiceAccount` given in
+/// `serviceAccountName` is only annotated, or created without owner if it does not exist yet,
+/// and `None` is returned if it needs no annotations.
+fn build_service_account(sc: &SparkCluster) -> Result<Option<ServiceAccount>, Error> {
+    let name = sc.service_account_name().ok_or(GlobalServiceNameNotFound {
+        obj_ref: ObjectRef::from_obj(sc),
+    })?;
+    let mut annotations = BTreeMap::new();
+    if let Some(aws_role_arn) = &sc.spec.aws_role_arn {
+        annotations.insert(EKS_ROLE_ARN_ANNOTATION.to_string(), aws_role_arn.clone());
+    }
+    if let Some(gcp_service_account) = &sc.spec.gcp_service_account {
+        annotations.insert(
+            GKE_SERVICE_ACCOUNT_ANNOTATION.to_string(),
+            gcp_service_account.clone(),
+        );
+    }
+
+    let mut metadata = ObjectMetaBuilder::new();
+    metadata.name_and_namespace(sc).name(name);
+    if sc.spec.service_account_name.is_none() {
+        metadata
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, "global", "global");
+    } else if annotations.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ServiceAccount {
+        metadata: metadata.with_annotations(annotations).build(),
+        ..ServiceAccount::default()
+    }))
+}
+
+/// Build the event log [`PersistentVolumeClaim`] shared by all pods, `None` unless
+/// `eventLogVolume` is set.
+///
+/// The claim has no owner reference, so the event logs survive the deletion of the cluster
+/// and can still be read by a new history server.
+fn build_event_log_volume_claim(sc: &SparkCluster) -> Result<Option<PersistentVolumeClaim>, Error> {
+    let event_log_volume = match &sc.spec.event_log_volume {
+        Some(event_log_volume) => event_log_volume,
+        None => return Ok(None),
+    };
+    let name = sc
+        .event_log_volume_claim_name()
+        .ok_or(GlobalServiceNameNotFound {
+            obj_ref: ObjectRef::from_obj(sc),
+        })?;
+    let mut requests = BTreeMap::new();
+    requests.insert(
+        "storage".to_string(),
+        Quantity(
+            event_log_volume
+                .storage_size
+                .clone()
+                .unwrap_or_else(|| DEFAULT_EVENT_LOG_VOLUME_SIZE.to_string()),
+        ),
+    );
+
+    Ok(Some(PersistentVolumeClaim {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(name)
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, "global", "global")
+            .build(),
+        spec: Some(PersistentVolumeClaimSpec {
+            access_modes: Some(vec![event_log_volume
+                .access_mode
+                .clone()
+                .unwrap_or_else(|| DEFAULT_EVENT_LOG_VOLUME_ACCESS_MODE.to_string())]),
+            storage_class_name: event_log_volume.storage_class.clone(),
+            resources: Some(ResourceRequirements {
+                requests: Some(requests),
+                ..ResourceRequirements::default()
+            }),
+            ..PersistentVolumeClaimSpec::default()
+        }),
+        ..PersistentVolumeClaim::default()
+    }))
+}
+
+/// Build the [`PodDisruptionBudget`] that keeps at least one master available during voluntary
+/// disruptions like node drains.
+///
+/// A single master cannot be protected without blocking the drain, so `minAvailable` drops to 0
+/// unless there is more than one master, which is always the case with `haConfig`. `None`
+/// without masters.
+fn build_master_pod_disruption_budget(
+    sc: &SparkCluster,
+) -> Result<Option<PodDisruptionBudget>, Error> {
+    let role_name = SparkRole::Master.to_string();
+    let min_available = match master_count(sc) {
+        0 => return Ok(None),
+        1 => 0,
+        _ => 1,
+    };
+
+    Ok(Some(PodDisruptionBudget {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(format!("{}-{}-pdb", sc.name(), role_name))
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, &role_name, "global")
+            .build(),
+        spec: Some(PodDisruptionBudgetSpec {
+            min_available: Some(IntOrString::Int(min_available)),
+            selector: Some(LabelSelector {
+                match_labels: Some(role_selector_labels(sc, APP_NAME, &role_name)),
+                ..LabelSelector::default()
+            }),
+            ..PodDisruptionBudgetSpec::default()
+        }),
+        status: None,
+    }))
+}
+
+/// Delete the [`PodDisruptionBudget`] of a role that has no pods (anymore), if it exists.
+///
+/// # Arguments
+/// * `client`    - The Kubernetes client.
+/// * `sc`        - The cluster resource object.
+/// * `role_name` - The role of the budget.
+///
+async fn delete_pod_disruption_budget(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+    role_name: &str,
+) -> Result<(), stackable_operator::error::Error> {
+    let pdb = PodDisruptionBudget {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(format!("{}-{}-pdb", sc.name(), role_name))
+            .build(),
+        ..PodDisruptionBudget::default()
+    };
+    match client.delete(&pdb).await {
+        Ok(_) => Ok(()),
+        Err(stackable_operator::error::Error::KubeError {
+            source: kube::Error::Api(response),
+            ..
+        }) if response.code == 404 => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Build the [`PodDisruptionBudget`] that limits how many workers are evicted at the same time
+/// during voluntary disruptions like node drains, `workerMaxUnavailable` or 1.
+///
+/// A single worker cannot be protected without blocking the drain, so it gets `minAvailable: 0`.
+/// `None` without workers or worker role groups.
+fn build_worker_pod_disruption_budget(
+    sc: &SparkCluster,
+) -> Result<Option<PodDisruptionBudget>, Error> {
+    let role_name = SparkRole::Worker.to_string();
+    let (min_available, max_unavailable) = match worker_count(sc) {
+        0 => return Ok(None),
+        1 => (Some(IntOrString::Int(0)), None),
+        _ => {
+            let max_unavailable = sc
+                .spec
+                .worker_max_unavailable
+                .unwrap_or(DEFAULT_WORKER_MAX_UNAVAILABLE);
+            (None, Some(IntOrString::Int(max_unavailable)))
+        }
+    };
+
+    Ok(Some(PodDisruptionBudget {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(format!("{}-{}-pdb", sc.name(), role_name))
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, &role_name, "global")
+            .build(),
+        spec: Some(PodDisruptionBudgetSpec {
+            min_available,
+            max_unavailable,
+            selector: Some(LabelSelector {
+                match_labels: Some(role_selector_labels(sc, APP_NAME, &role_name)),
+                ..LabelSelector::default()
+            }),
+        }),
+        status: None,
+    }))
+}
+
+/// Apply the role-level history server [`Service`], or delete it if the cluster has no
+/// history server (anymore).
+///
+/// # Arguments
+/// * `client`               - The Kubernetes client.
+/// * `sc`                   - The cluster resource object.
+/// * `history_server_ports` - The ports of the history server, `None` without history server.
+///
+async fn reconcile_history_server_service(
+    client: &stackable_operator::client::Client,
+    sc: &SparkCluster,
+    history_server_ports: Option<Vec<(String, i32)>>,
+) -> Result<(), Error> {
+    let role_name = SparkRole::HistoryServer.to_string();
+    let service_name = sc
+        .history_server_role_service_name()
+        .ok_or(GlobalServiceNameNotFound {
+            obj_ref: ObjectRef::from_obj(sc),
+        })?;
+
+    let history_server_ports = match history_server_ports {
+        Some(history_server_ports) => history_server_ports,
+        None => {
+            let service = Service {
+                metadata: ObjectMetaBuilder::new()
+                    .name_and_namespace(sc)
+                    .name(&service_name)
+                    .build(),
+                ..Service::default()
+            };
+            return match client.delete(&service).await {
+                Ok(_) => Ok(()),
+                Err(stackable_operator::error::Error::KubeError {
+                    source: kube::Error::Api(response),
+                    ..
+                }) if response.code == 404 => Ok(()),
+                Err(e) => Err(DeleteHistoryServerService {
+                    source: e,
+                    sc: ObjectRef::from_obj(sc),
+                }),
+            };
+        }
+    };
+
+    let service = Service {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(&service_name)
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(sc, APP_NAME, version(sc)?, &role_name, "global")
+            .build(),
+        spec: Some(ServiceSpec {
+            ports: Some(
+                history_server_ports
+                    .iter()
+                    .map(|(name, value)| ServicePort {
+                        name: Some(name.clone()),
+                        port: *value,
+                        protocol: Some("TCP".to_string()),
+                        ..ServicePort::default()
+                    })
+                    .collect(),
+            ),
+            selector: Some(role_selector_labels(sc, APP_NAME, &role_name)),
+            type_: Some(
+                sc.spec
+                    .history_server_service_type
+                    .clone()
+                    .unwrap_or_default()
+                    .as_str()
+                    .to_string(),
+            ),
+            ..ServiceSpec::default()
+        }),
+        status: None,
+    };
+    client
+        .apply_patch(FIELD_MANAGER_SCOPE, &service, &service)
+        .await
+        .map_err(|e| ApplyHistoryServerService {
+            source: e,
+            sc: ObjectRef::from_obj(sc),
+        })?;
+    Ok(())
+}
+
+/// Build the [`ConfigMap`] that publishes the master URL for clients in other namespaces.
+///
+/// The `ConfigMap` is not owned by the cluster because owner references
+/// cannot point across namespaces.
+///
+/// # Arguments
+/// * `sc`                        - The cluster resource object.
+/// * `target`                    - The `<namespace>/<name>` of the `ConfigMap` to write.
+/// * `default_master_role_ports` - Master role service ports. Used to build the master URL.
+///
+fn build_master_url_config_map(
+    sc: &SparkCluster,
+    target: &str,
+    default_master_role_ports: &[(String, i32)],
+) -> Result<ConfigMap, Error> {
+    let (namespace, name) =
+        parse_master_url_target(target).ok_or_else(|| InvalidPublishMasterUrlTarget {
+            target: target.to_string(),
+        })?;
+    let master_service_fqdn = sc
+        .server_role_service_fqdn()
+        .ok_or(GlobalServiceNameNotFound {
+            obj_ref: ObjectRef::from_obj(sc),
+        })?;
+
+    ConfigMapBuilder::new()
+        .metadata(
+            ObjectMetaBuilder::new()
+                .name(name)
+                .namespace(namespace)
+                .with_recommended_labels(
+                    sc,
+                    APP_NAME,
+                    version(sc)?,
+                    &SparkRole::Master.to_string(),
+                    "global",
+                )
+                .build(),
+        )
+        .add_data(
+            MASTER_URL_CONFIG_MAP_KEY,
+            build_ha_master_url(sc, default_master_role_ports).unwrap_or_else(|| {
+                format!(
+                    "spark://{}:{}",
+                    master_service_fqdn,
+                    master_port(default_master_role_ports)
+                )
+            }),
+        )
+        .build()
+        .map_err(|e| PublishMasterUrlFailed {
+            source: e,
+            target: target.to_string(),
+        })
+}
+
+/// Delete a published master URL [`ConfigMap`], if it exists.
+///
+/// # Arguments
+/// * `client` - The Kubernetes client.
+/// * `target` - The `<namespace>/<name>` of the `ConfigMap`, nothing was published to malformed
+///              targets.
+///
+async fn delete_master_url_config_map(
+    client: &stackable_operator::client::Client,
+    target: &str,
+) -> Result<(), Error> {
+    let (namespace, name) = match parse_master_url_target(target) {
+        Some(namespace_and_name) => namespace_and_name,
+        None => return Ok(()),
+    };
+    let config_map = ConfigMap {
+        metadata: ObjectMetaBuilder::new()
+            .name(name)
+            .namespace(namespace)
+            .build(),
+        ..ConfigMap::default()
+    };
+    match client.delete(&config_map).await {
+        Ok(_) => Ok(()),
+        Err(stackable_operator::error::Error::KubeError {
+            source: kube::Error::Api(response),
+            ..
+        }) if response.code == 404 => Ok(()),
+        Err(e) => Err(DeletePublishedMasterUrl {
+            source: e,
+            target: target.to_string(),
+        }),
+    }
+}
+
+/// Split a `publishMasterUrl` target into namespace and name, `None` if it is malformed.
+fn parse_master_url_target(target: &str) -> Option<(&str, &str)> {
+    target
+        .split_once('/')
+        .filter(|(namespace, name)| !namespace.is_empty() && !name.is_empty())
+}
+
+/// The rolegroup [`ConfigMap`] configures the rolegroup based on the configuration given by the administrator
+///
+/// # Arguments
+/// * `sc`                        - The cluster resource object.
+/// * `default_master_role_ports` - Master role service ports. Used to build `spark.master`.
+/// * `rolegroup`                 - The rolegroup.
+/// * `rolegroup_config`          - The validated configuration for the rolegroup.
+///
+fn build_rolegroup_config_map(
+    sc: &SparkCluster,
+    default_master_role_ports: &[(String, i32)],
+    rolegroup: &RoleGroupRef<SparkCluster>,
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+) -> Result<ConfigMap, Error> {
+    let fair_scheduler_xml = sc
+        .spec
+        .scheduler_mode
+        .as_ref()
+        .and_then(|scheduler_mode| scheduler_mode.fair_scheduler_xml());
+    let mut spark_defaults = rolegroup_config
+        .get(&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string()))
+        .cloned()
+        .ok_or_else(|| SerializeSparkDefaults {
+            rolegroup: rolegroup.clone(),
+        })?;
+    // Applications submitted from within the pods connect to this master by default,
+    // unless spark.master is overridden via configOverrides
+    spark_defaults
+        .entry(SPARK_DEFAULTS_MASTER.to_string())
+        .or_insert_with(|| build_master_service_url(sc, rolegroup, default_master_role_ports));
+    if fair_scheduler_xml.is_some() {
+        // The pools are mounted next to spark-defaults.conf
+        spark_defaults.insert(
+            SPARK_DEFAULTS_SCHEDULER_ALLOCATION_FILE.to_string(),
+            format!(
+                "{}/{}",
+                spark_conf_dir(rolegroup_config),
+                FAIR_SCHEDULER_XML
+            ),
+        );
+    }
+
+    let role: SparkRole = serde_yaml::from_str(&rolegroup.role).unwrap();
+    let node_config = sc.node_config(&role, &rolegroup.role_group);
+    // The executor settings of the rolegroup are laid over the common spark-defaults.conf
+    for (property, value) in vec![
+        (
+            SPARK_DEFAULTS_EXECUTOR_CORES,
+            node_config.executor_cores.map(|cores| cores.to_string()),
+        ),
+        (
+            SPARK_DEFAULTS_EXECUTOR_MEMORY,
+            node_config.executor_memory.clone(),
+        ),
+        (
+            SPARK_EXECUTOR_RESOURCE_GPU_AMOUNT,
+            worker_gpus(&role, &node_config).map(|gpus| gpus.to_string()),
+        ),
+    ] {
+        if let Some(value) = value {
+            if spark_defaults.contains_key(property) {
+                return Err(ConflictingExecutorConfig {
+                    rolegroup: rolegroup.clone(),
+                    property: property.to_string(),
+                });
+            }
+            spark_defaults.insert(property.to_string(), value);
+        }
+    }
+    let log4j_properties = build_log4j_properties(sc, &node_config);
+    let mut spark_env = rolegroup_config
+        .get(&PropertyNameKind::File(SPARK_ENV_SH.to_string()))
+        .cloned()
+        .ok_or_else(|| SerializeSparkEnv {
+            rolegroup: rolegroup.clone(),
+        })?;
+    if let Some(gpus) = worker_gpus(&role, &node_config) {
+        // Appended here from the merged node config, since the options computed for a role
+        // group would replace the jvmOpts of the role
+        let gpu_opts = format!(
+            "-D{}={} -D{}={}/{}",
+            SPARK_WORKER_RESOURCE_GPU_AMOUNT,
+            gpus,
+            SPARK_WORKER_RESOURCE_GPU_DISCOVERY_SCRIPT,
+            GPU_DISCOVERY_SCRIPT_DIR,
+            GPU_DISCOVERY_SCRIPT
+        );
+        let worker_opts = spark_env
+            .entry(SPARK_ENV_WORKER_OPTS.to_string())
+            .or_default();
+        *worker_opts = if worker_opts.is_empty() {
+            gpu_opts
+        } else {
+            format!("{} {}", worker_opts, gpu_opts)
+        };
+    }
+
+    let mut config_map = ConfigMapBuilder::new();
+    config_map
+        .metadata(
+            ObjectMetaBuilder::new()
+                .name_and_namespace(sc)
+                .name(rolegroup.object_name())
+                .ownerreference_from_resource(sc, None, Some(true))
+                .map_err(|e| Error::ObjectMissingMetadataForOwnerRef {
+                    source: e,
+                    obj_ref: ObjectRef::from_obj(sc),
+                })?
+                .with_recommended_labels(
+                    sc,
+                    APP_NAME,
+                    version(sc)?,
+                    &rolegroup.role,
+                    &rolegroup.role_group,
+                )
+                .build(),
+        )
+        .add_data(
+            SPARK_DEFAULTS_CONF,
+            convert_map_to_string(&spark_defaults, " "),
+        )
+        .add_data(SPARK_ENV_SH, convert_map_to_env_exports(&spark_env))
+        .add_data(
+            SPARK_METRICS_PROPERTIES,
+            rolegroup_config
+                .get(&PropertyNameKind::File(
+                    SPARK_METRICS_PROPERTIES.to_string(),
+                ))
+                .and(sc.enable_monitoring())
+                .filter(|&monitoring_enabled_flag| monitoring_enabled_flag)
+                .map(|_| "\
+                            *.sink.prometheusServlet.class=org.apache.spark.metrics.sink.PrometheusServlet\n\
+                            *.sink.prometheusServlet.path=/metrics\n\
+                            *.source.jvm.class=org.apache.spark.metrics.source.JvmSource")
+                .unwrap_or_default()
+        );
+    if let Some(fair_scheduler_xml) = fair_scheduler_xml {
+        config_map.add_data(FAIR_SCHEDULER_XML, fair_scheduler_xml);
+    }
+    if let Some(hive_site_xml) = sc.spec.hive_site_xml() {
+        config_map.add_data(HIVE_SITE_XML, hive_site_xml);
+    }
+    if let Some(log4j_properties) = log4j_properties {
+        config_map.add_data(LOG4J_PROPERTIES, log4j_properties);
+    }
+    if jmx_exporter_enabled(sc) {
+        config_map.add_data(JMX_EXPORTER_CONFIG_MAP_KEY, JMX_EXPORTER_CONFIG_CONTENT);
+    }
+    if worker_gpus(&role, &node_config).is_some() {
+        config_map.add_data(GPU_DISCOVERY_SCRIPT, GPU_DISCOVERY_SCRIPT_CONTENT);
+    }
+    config_map.build().map_err(|e| Error::BuildRoleGroupConfig {
+        source: e,
+        rolegroup: rolegroup.clone(),
+    })
+}
+
+/// The rolegroup [`Service`] is a headless service that allows direct access to the instances of a certain rolegroup
+///
+/// This is mostly useful for internal communication between peers, or for clients that perform client-side load balancing.
+fn build_rolegroup_service(
+    sc: &SparkCluster,
+    rolegroup: &RoleGroupRef<SparkCluster>,
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+) -> Result<Service, Error> {
+    Ok(Service {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(&rolegroup.object_name())
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| Error::ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(
+                sc,
+                APP_NAME,
+                version(sc)?,
+                &rolegroup.role,
+                &rolegroup.role_group,
+            )
+            .build(),
+        spec: Some(ServiceSpec {
+            cluster_ip: Some("None".to_string()),
+            ports: Some(
+                build_ports(sc, rolegroup, rolegroup_config)?
+                    .iter()
+                    .map(|(name, value)| ServicePort {
+                        name: Some(name.clone()),
+                        port: *value,
+                        protocol: Some("TCP".to_string()),
+                        ..ServicePort::default()
+                    })
+                    .collect(),
+            ),
+            selector: Some(role_group_selector_labels(
+                sc,
+                APP_NAME,
+                &rolegroup.role,
+                &rolegroup.role_group,
+            )),
+            publish_not_ready_addresses: Some(true),
+            ..ServiceSpec::default()
+        }),
+        status: None,
+    })
+}
+
+/// Build the [`StatefulSet`]s for the given rolegroup.
+///
+/// # Arguments
+/// * `sc`                       - The cluster resource object.
+/// * `default_master_role_ports` - Master role service (and container ports). Used to build the master URLs needed by the worker pods.
+/// * `rolegroup`                 - The rolegroup.
+/// * `rolegroup_config`          - The validated configuration for the rolegroup.
+/// * `managed_secrets_hash`      - Hash of the managed secrets, changes when a secret is rotated.
+/// * `config_hash`               - Hash of the rolegroup [`ConfigMap`], changes with the configuration.
+///
+fn build_rolegroup_statefulset(
+    sc: &SparkCluster,
+    default_master_role_ports: &[(String, i32)],
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+    managed_secrets_hash: &str,
+    config_hash: &str,
+) -> Result<StatefulSet, Error> {
+    let sc_version = version(sc)?;
+    let role: SparkRole = serde_yaml::from_str(&rolegroup_ref.role).unwrap();
+    let node_config = sc.node_config(&role, &rolegroup_ref.role_group);
+    let image = format!(
+        "docker.stackable.tech/stackable/spark:{}-stackable0",
+        sc_version
+    );
+    let mut env = rolegroup_config
+        .get(&PropertyNameKind::Env)
+        .iter()
+        .flat_map(|env_vars| env_vars.iter())
+        .map(|(k, v)| EnvVar {
+            name: k.clone(),
+            value: Some(v.clone()),
+            ..EnvVar::default()
+        })
+        .collect::<Vec<_>>();
+    env.extend(node_config.env.iter().flatten().cloned());
+    if let Some(authentication) = &sc.spec.authentication {
+        env.push(EnvVar {
+            name: SPARK_AUTHENTICATE_SECRET_ENV.to_string(),
+            value_from: Some(EnvVarSource {
+                secret_key_ref: Some(SecretKeySelector {
+                    name: Some(authentication.secret_ref.clone()),
+                    key: SPARK_DEFAULTS_AUTHENTICATE_SECRET.to_string(),
+                    ..SecretKeySelector::default()
+                }),
+                ..EnvVarSource::default()
+            }),
+            ..EnvVar::default()
+        });
+    }
+    if let Some(credentials_secret) = s3_credentials_secret(sc) {
+        for (name, key) in [
+            (AWS_ACCESS_KEY_ID, S3_ACCESS_KEY),
+            (AWS_SECRET_ACCESS_KEY, S3_SECRET_KEY),
+        ] {
+            env.push(EnvVar {
+                name: name.to_string(),
+                value_from: Some(EnvVarSource {
+                    secret_key_ref: Some(SecretKeySelector {
+                        name: Some(credentials_secret.to_string()),
+                        key: key.to_string(),
+                        ..SecretKeySelector::default()
+                    }),
+                    ..EnvVarSource::default()
+                }),
+                ..EnvVar::default()
+            });
+        }
+    }
+
+    if let Some(aws_region) = &sc.spec.aws_region {
+        env.push(EnvVar {
+            name: AWS_REGION.to_string(),
+            value: Some(aws_region.clone()),
+            ..EnvVar::default()
+        });
+    }
+
+    let mut daemon_java_opts = Vec::new();
+    if jmx_exporter_enabled(sc) {
+        daemon_java_opts.push(format!(
+            "-javaagent:{}={}:{}/{}",
+            JMX_EXPORTER_JAR, JMX_EXPORTER_PORT, JMX_EXPORTER_CONFIG_DIR, JMX_EXPORTER_CONFIG
+        ));
+    }
+    if build_log4j_properties(sc, &node_config).is_some() {
+        daemon_java_opts.push(format!(
+            "-Dlog4j.configuration=file:{}/{}",
+            spark_conf_dir(rolegroup_config),
+            LOG4J_PROPERTIES
+        ));
+    }
+    if !daemon_java_opts.is_empty() {
+        env.push(EnvVar {
+            name: SPARK_DAEMON_JAVA_OPTS.to_string(),
+            value: Some(daemon_java_opts.join(" ")),
+            ..EnvVar::default()
+        });
+    }
+
+    let mut container_builder = ContainerBuilder::new("spark");
+    container_builder
+        .image(image)
+        .args(container_command(
+            sc,
+            rolegroup_ref,
+            default_master_role_ports,
+        ))
+        .readiness_probe(build_probe(
+            node_config.readiness_probe.as_ref(),
+            default_readiness_check(&role).as_ref(),
+        ))
+        .add_env_vars(env)
+        .add_container_ports(build_container_ports(
+            sc,
+            rolegroup_ref,
+            rolegroup_config,
+            node_config.host_network.unwrap_or(false),
+        )?)
+        .add_volume_mount("config", spark_conf_dir(rolegroup_config));
+    let log_dir = spark_log_dir(rolegroup_config);
+    if let Some(log_dir) = &log_dir {
+        container_builder.add_volume_mount("log", log_dir);
+    }
+    if jmx_exporter_enabled(sc) {
+        container_builder.add_volume_mount("jmx-exporter-config", JMX_EXPORTER_CONFIG_DIR);
+    }
+    if worker_gpus(&role, &node_config).is_some() {
+        container_builder.add_volume_mount("gpu-discovery", GPU_DISCOVERY_SCRIPT_DIR);
+    }
+    for (i, secret_volume) in sc.spec.secret_volumes.iter().flatten().enumerate() {
+        container_builder.add_volume_mount(secret_volume_name(i), &secret_volume.mount_path);
+    }
+    let mut container_sc = container_builder.build();
+    container_sc.resources = build_resources(&node_config);
+    // Without a configured probe the pods keep the probe of earlier versions, so that they are
+    // not restarted by an operator upgrade
+    container_sc.liveness_probe = Some(match &node_config.liveness_probe {
+        Some(probe_config) => {
+            build_probe(Some(probe_config), default_liveness_check(&role).as_ref())
+        }
+        None => PROBE.clone(),
+    });
+    container_sc.env_from = node_config.env_from.clone();
+    container_sc.security_context = node_config.container_security_context.clone();
+    container_sc.lifecycle = node_config
+        .pre_stop_command
+        .clone()
+        .or_else(|| default_pre_stop_command(&role))
+        .map(|command| Lifecycle {
+            pre_stop: Some(Handler {
+                exec: Some(ExecAction {
+                    command: Some(command),
+                }),
+                ..Handler::default()
+            }),
+            ..Lifecycle::default()
+        });
+    if sc.spec.hdfs_config_map.is_some() {
+        // Only the file is mounted, the rest of the configuration directory stays in place
+        container_sc
+            .volume_mounts
+            .get_or_insert_with(Vec::new)
+            .push(VolumeMount {
+                name: "hdfs-config".to_string(),
+                mount_path: format!("{}/{}", spark_conf_dir(rolegroup_config), HDFS_CORE_SITE),
+                sub_path: Some(HDFS_CORE_SITE.to_string()),
+                ..VolumeMount::default()
+            });
+    }
+
+    if sc.spec.hive_config_map_ref.is_some() {
+        container_sc
+            .volume_mounts
+            .get_or_insert_with(Vec::new)
+            .push(VolumeMount {
+                name: "hive-config".to_string(),
+                mount_path: format!("{}/{}", spark_conf_dir(rolegroup_config), HIVE_SITE_XML),
+                sub_path: Some(HIVE_SITE_XML.to_string()),
+                ..VolumeMount::default()
+            });
+    }
+
+    let mut pod_annotations = sc
+        .spec
+        .pod_annotations
+        .iter()
+        .flatten()
+        .filter(|(key, _)| {
+            !DENIED_POD_ANNOTATIONS.contains(&key.as_str())
+                && !key.starts_with(OPERATOR_ANNOTATION_PREFIX)
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect::<BTreeMap<_, _>>();
+    pod_annotations.insert(
+        MANAGED_SECRETS_HASH_ANNOTATION.to_string(),
+        managed_secrets_hash.to_string(),
+    );
+    pod_annotations.insert(CONFIG_HASH_ANNOTATION.to_string(), config_hash.to_string());
+    pod_annotations.extend(vault_annotations(&node_config));
+    if role == SparkRole::Worker && runs_shuffle_service(rolegroup_config) {
+        pod_annotations.insert(SHUFFLE_SERVICE_ANNOTATION.to_string(), "true".to_string());
+    }
+
+    let mut pod_builder = PodBuilder::new();
+    pod_builder
+        .metadata_builder(|m| {
+            m.with_labels(forwarded_cluster_labels(sc))
+                .with_recommended_labels(
+                    sc,
+                    APP_NAME,
+                    sc_version,
+                    &rolegroup_ref.role,
+                    &rolegroup_ref.role_group,
+                )
+                .with_annotations(pod_annotations.clone())
+        })
+        .add_container(container_sc)
+        .add_volume(Volume {
+            name: "config".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(rolegroup_ref.object_name()),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    // The Spark container stays the first one, sidecars are appended after it.
+    for sidecar in node_config.sidecar_containers.iter().flatten() {
+        pod_builder.add_container(sidecar.clone());
+    }
+    if jmx_exporter_enabled(sc) {
+        pod_builder.add_volume(Volume {
+            name: "jmx-exporter-config".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(rolegroup_ref.object_name()),
+                items: Some(vec![KeyToPath {
+                    key: JMX_EXPORTER_CONFIG_MAP_KEY.to_string(),
+                    path: JMX_EXPORTER_CONFIG.to_string(),
+                    ..KeyToPath::default()
+                }]),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    }
+    if worker_gpus(&role, &node_config).is_some() {
+        pod_builder.add_volume(Volume {
+            name: "gpu-discovery".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(rolegroup_ref.object_name()),
+                items: Some(vec![KeyToPath {
+                    key: GPU_DISCOVERY_SCRIPT.to_string(),
+                    path: GPU_DISCOVERY_SCRIPT.to_string(),
+                    ..KeyToPath::default()
+                }]),
+                // Spark runs the discovery script directly
+                default_mode: Some(0o755),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    }
+    if let Some(hdfs_config_map) = &sc.spec.hdfs_config_map {
+        pod_builder.add_volume(Volume {
+            name: "hdfs-config".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(hdfs_config_map.clone()),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    }
+    if let Some(hive_config_map) = &sc.spec.hive_config_map_ref {
+        pod_builder.add_volume(Volume {
+            name: "hive-config".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(hive_config_map.clone()),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    }
+    // Without a shared event log volume every pod gets its own one via the volume claim template
+    if log_dir.is_some() && sc.spec.event_log_volume.is_some() {
+        pod_builder.add_volume(Volume {
+            name: "log".to_string(),
+            persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                claim_name: sc.event_log_volume_claim_name().unwrap_or_default(),
+                ..PersistentVolumeClaimVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    }
+    for (i, secret_volume) in sc.spec.secret_volumes.iter().flatten().enumerate() {
+        pod_builder.add_volume(Volume {
+            name: secret_volume_name(i),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(secret_volume.secret_name.clone()),
+                ..SecretVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    }
+    let mut pod_template = pod_builder.build_template();
+    if let Some(pod_spec) = pod_template.spec.as_mut() {
+        pod_spec.node_selector = pod_node_selector(&node_config);
+        pod_spec.affinity = build_affinity(sc, &role);
+        pod_spec.topology_spread_constraints =
+            node_config
+                .topology_spread_constraints
+                .clone()
+                .map(|constraints| {
+                    constraints
+                        .into_iter()
+                        .map(|mut constraint| {
+                            constraint
+                                .label_selector
+                                .get_or_insert_with(|| LabelSelector {
+                                    match_labels: Some(role_selector_labels(
+                                        sc,
+                                        APP_NAME,
+                                        &rolegroup_ref.role,
+                                    )),
+                                    ..LabelSelector::default()
+                                });
+                            constraint
+                        })
+                        .collect()
+                });
+        pod_spec.service_account_name = sc.service_account_name();
+        pod_spec.priority_class_name = node_config
+            .priority_class_name
+            .clone()
+            .or_else(|| sc.spec.priority_class_name.clone());
+        pod_spec.init_containers = node_config.init_containers.clone();
+        pod_spec.runtime_class_name = node_config.runtime_class_name.clone();
+        if node_config.host_network.unwrap_or(false) {
+            pod_spec.host_network = Some(true);
+            // Keeps resolving cluster Services like the master Service
+            pod_spec.dns_policy = Some("ClusterFirstWithHostNet".to_string());
+        }
+        if let Some(dns_policy) = &node_config.dns_policy {
+            pod_spec.dns_policy = Some(dns_policy.clone());
+        }
+        pod_spec.dns_config = node_config.dns_config.clone();
+        // Unset by default, the image decides about the user
+        pod_spec.security_context = node_config.pod_security_context.clone();
+        // History servers are stateless and keep the Kubernetes default
+        pod_spec.termination_grace_period_seconds =
+            node_config.termination_grace_period_seconds.or(match role {
+                SparkRole::Master => Some(DEFAULT_MASTER_TERMINATION_GRACE_PERIOD_SECONDS),
+                SparkRole::Worker => Some(DEFAULT_WORKER_TERMINATION_GRACE_PERIOD_SECONDS),
+                SparkRole::HistoryServer => None,
+            });
+        pod_spec.image_pull_secrets = sc.spec.image_pull_secrets.as_ref().map(|secrets| {
+            secrets
+                .iter()
+                .map(|secret| LocalObjectReference {
+                    name: Some(secret.clone()),
+                })
+                .collect()
+        });
+    }
+    if let Some(overrides) = &node_config.pod_template_override {
+        pod_template =
+            override_pod_template(pod_template, overrides).map_err(|e| PodTemplateOverride {
+                source: e,
+                rolegroup: rolegroup_ref.clone(),
+            })?;
+    }
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        POD_TEMPLATE_HASH_ANNOTATION.to_string(),
+        pod_template_hash(&pod_template),
+    );
+
+    Ok(StatefulSet {
+        metadata: ObjectMetaBuilder::new()
+            .name_and_namespace(sc)
+            .name(&rolegroup_ref.object_name())
+            .ownerreference_from_resource(sc, None, Some(true))
+            .map_err(|e| Error::ObjectMissingMetadataForOwnerRef {
+                source: e,
+                obj_ref: ObjectRef::from_obj(sc),
+            })?
+            .with_recommended_labels(
+                sc,
+                APP_NAME,
+                sc_version,
+                &rolegroup_ref.role,
+                &rolegroup_ref.role_group,
+            )
+            .with_annotations(annotations)
+            .build(),
+        spec: Some(StatefulSetSpec {
+            pod_management_policy: Some("Parallel".to_string()),
+            replicas: Some(rolegroup_replicas(sc, rolegroup_ref)?),
+            selector: LabelSelector {
+                match_labels: Some(role_group_selector_labels(
+                    sc,
+                    APP_NAME,
+                    &rolegroup_ref.role,
+                    &rolegroup_ref.role_group,
+                )),
+                ..LabelSelector::default()
+            },
+            service_name: rolegroup_ref.object_name(),
+            template: pod_template,
+            // Replace outdated pods one at a time, waiting for each new pod to become ready
+            update_strategy: Some(StatefulSetUpdateStrategy {
+                type_: Some("RollingUpdate".to_string()),
+                ..StatefulSetUpdateStrategy::default()
+            }),
+            volume_claim_templates: if log_dir.is_none() || sc.spec.event_log_volume.is_some() {
+                None
+            } else {
+                Some(vec![PersistentVolumeClaim {
+                    metadata: ObjectMeta {
+                        name: Some("log".to_string()),
+                        ..ObjectMeta::default()
+                    },
+                    spec: Some(PersistentVolumeClaimSpec {
+                        access_modes: Some(vec!["ReadWriteOnce".to_string()]),
+                        resources: Some(ResourceRequirements {
+                            requests: Some({
+                                let mut map = BTreeMap::new();
+                                map.insert("storage".to_string(), Quantity("1Gi".to_string()));
+                                map
+                            }),
+                            ..ResourceRequirements::default()
+                        }),
+                        ..PersistentVolumeClaimSpec::default()
+                    }),
+                    ..PersistentVolumeClaim::default()
+                }])
+            },
+            ..StatefulSetSpec::default()
+        }),
+        status: None,
+    })
+}
+
+/// Build the [`ResourceRequirements`] of the Spark container, `None` if no resources are set.
+///
+/// # Arguments
+/// * `node_config` - The merged node config of the rolegroup.
+///
+fn build_resources(node_config: &NodeConfig) -> Option<ResourceRequirements> {
+    let quantities = |cpu: &Option<String>, memory: &Option<String>| {
+        let mut quantities = BTreeMap::new();
+        if let Some(cpu) = cpu {
+            quantities.insert("cpu".to_string(), Quantity(cpu.clone()));
+        }
+        if let Some(memory) = memory {
+            quantities.insert("memory".to_string(), Quantity(memory.clone()));
+        }
+        Some(quantities).filter(|quantities| !quantities.is_empty())
+    };
+    let requests = quantities(&node_config.cpu_request, &node_config.memory_request);
+    let mut limits = quantities(&node_config.cpu_limit, &node_config.memory_limit);
+    // Extended resources are only set as limit, Kubernetes uses it as request as well
+    if let Some(gpus) = node_config.gpu_limit.filter(|gpus| *gpus > 0) {
+        limits
+            .get_or_insert_with(BTreeMap::new)
+            .insert(RESOURCE_NVIDIA_GPU.to_string(), Quantity(gpus.to_string()));
+    }
+    if requests.is_none() && limits.is_none() {
+        return None;
+    }
+    Some(ResourceRequirements { limits, requests })
+}
+
+/// Build a container probe against the web UI port.
+///
+/// # Arguments
+/// * `probe_config`  - The probe configured for the rolegroup, if any.
+/// * `default_check` - The check used if none is configured, falls back to [`PROBE`].
+///
+fn build_probe(probe_config: Option<&ProbeConfig>, default_check: Option<&ProbeCheck>) -> Probe {
+    let mut probe = PROBE.clone();
+    let check = probe_config
+        .and_then(|probe_config| probe_config.check.as_ref())
+        .or(default_check);
+    match check {
+        None => {}
+        Some(ProbeCheck::HttpGet { path }) => {
+            probe.http_get = Some(HTTPGetAction {
+                path: Some(path.clone()),
+                port: IntOrString::String(String::from(PORT_NAME_WEB)),
+                ..HTTPGetAction::default()
+            });
+        }
+        Some(ProbeCheck::TcpSocket) => {
+            probe.http_get = None;
+            probe.tcp_socket = Some(TCPSocketAction {
+                port: IntOrString::String(String::from(PORT_NAME_WEB)),
+                ..TCPSocketAction::default()
+            });
+        }
+    }
+
+    if let Some(probe_config) = probe_config {
+        if let Some(initial_delay_seconds) = probe_config.initial_delay_seconds {
+            probe.initial_delay_seconds = Some(initial_delay_seconds);
+        }
+        if let Some(period_seconds) = probe_config.period_seconds {
+            probe.period_seconds = Some(period_seconds);
+        }
+        if let Some(timeout_seconds) = probe_config.timeout_seconds {
+            probe.timeout_seconds = Some(timeout_seconds);
+        }
+        if let Some(failure_threshold) = probe_config.failure_threshold {
+            probe.failure_threshold = Some(failure_threshold);
+        }
+    }
+    probe
+}
+
+/// The check of a configured liveness probe without `check`, the master serves its state as
+/// JSON on `/json`.
+fn default_liveness_check(role: &SparkRole) -> Option<ProbeCheck> {
+    match role {
+        SparkRole::Master => Some(ProbeCheck::HttpGet {
+            path: "/json".to_string(),
+        }),
+        SparkRole::Worker | SparkRole::HistoryServer => None,
+    }
+}
+
+/// The default readiness check of a role.
+///
+/// The worker web UI only comes up after the worker registered with the master. It is checked
+/// via the named web UI port, which is 8081 unless `SPARK_WORKER_WEBUI_PORT` is changed.
+fn default_readiness_check(role: &SparkRole) -> Option<ProbeCheck> {
+    match role {
+        SparkRole::Master => Some(ProbeCheck::HttpGet {
+            path: "/json".to_string(),
+        }),
+        SparkRole::Worker => Some(ProbeCheck::TcpSocket),
+        SparkRole::HistoryServer => None,
+    }
+}
+
+/// The labels of the cluster object that are forwarded to its pods.
+///
+/// Labels in the Kubernetes reserved namespaces (`kubernetes.io`, `k8s.io`) are not forwarded,
+/// the recommended `app.kubernetes.io` labels are set by the operator itself.
+fn forwarded_cluster_labels(sc: &SparkCluster) -> BTreeMap<String, String> {
+    sc.labels()
+        .iter()
+        .filter(|(key, _)| {
+            let prefix = key.split_once('/').map(|(prefix, _)| prefix).unwrap_or("");
+            !RESERVED_LABEL_DOMAINS
+                .iter()
+                .any(|domain| prefix == *domain || prefix.ends_with(&format!(".{}", domain)))
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// The name of the pod volume of the `index`th entry of `secretVolumes`.
+///
+/// `Secret` names may contain dots, which are not allowed in volume names.
+fn secret_volume_name(index: usize) -> String {
+    format!("secret-{}", index)
+}
+
+/// Build the affinity of the pods of a role: the `requiredNodeAffinity` of the cluster and, for
+/// workers, the anti-affinity of [`worker_anti_affinity`]. `None` if neither applies.
+fn build_affinity(sc: &SparkCluster, role: &SparkRole) -> Option<Affinity> {
+    let node_affinity = sc
+        .spec
+        .required_node_affinity
+        .clone()
+        .filter(|requirements| !requirements.is_empty())
+        .map(|requirements| NodeAffinity {
+            required_during_scheduling_ignored_during_execution: Some(NodeSelector {
+                node_selector_terms: vec![NodeSelectorTerm {
+                    match_expressions: Some(requirements),
+                    ..NodeSelectorTerm::default()
+                }],
+            }),
+            ..NodeAffinity::default()
+        });
+    let pod_anti_affinity = match role {
+        SparkRole::Worker => worker_anti_affinity(sc),
+        SparkRole::Master | SparkRole::HistoryServer => None,
+    };
+    if node_affinity.is_none() && pod_anti_affinity.is_none() {
+        return None;
+    }
+    Some(Affinity {
+        node_affinity,
+        pod_anti_affinity,
+        ..Affinity::default()
+    })
+}
+
+/// Build the preferred anti-affinity that spreads all workers of the cluster across the
+/// topology domains (nodes by default), `None` if disabled via `workerSpread`.
+fn worker_anti_affinity(sc: &SparkCluster) -> Option<PodAntiAffinity> {
+    let worker_spread = sc.spec.worker_spread.clone().unwrap_or_default();
+    if !worker_spread.enabled.unwrap_or(true) {
+        return None;
+    }
+    Some(PodAntiAffinity {
+        preferred_during_scheduling_ignored_during_execution: Some(vec![WeightedPodAffinityTerm {
+            weight: worker_spread.weight.unwrap_or(100),
+            pod_affinity_term: PodAffinityTerm {
+                label_selector: Some(LabelSelector {
+                    match_labels: Some(role_selector_labels(
+                        sc,
+                        APP_NAME,
+                        &SparkRole::Worker.to_string(),
+                    )),
+                    ..LabelSelector::default()
+                }),
+                topology_key: worker_spread
+                    .topology_key
+                    .unwrap_or_else(|| LABEL_HOSTNAME.to_string()),
+                ..PodAffinityTerm::default()
+            },
+        }]),
+        ..PodAntiAffinity::default()
+    })
+}
+
+/// Build the node selector of the rolegroup pods from the [`NodeConfig`].
+///
+/// The instance type label of the node pool selector takes precedence over the same label
+/// in the node selector.
+///
+/// # Arguments
+/// * `node_config` - The merged node config of the rolegroup.
+///
+fn pod_node_selector(node_config: &NodeConfig) -> Option<BTreeMap<String, String>> {
+    let mut node_selector = node_config.node_selector.clone().unwrap_or_default();
+    if let Some(node_pool_selector) = &node_config.node_pool_selector {
+        let (key, value) = node_pool_selector.node_label();
+        node_selector.insert(key, value);
+    }
+    Some(node_selector).filter(|node_selector| !node_selector.is_empty())
+}
+
+/// Consecutive reconcile failures caused by an unreachable API server, see [`error_policy`].
+#[derive(Debug, Default)]
+struct ApiAvailability {
+    failures: AtomicU32,
+}
+
+impl ApiAvailability {
+    /// Whether the API server has been unreachable often enough to back off.
+    fn backoff(&self) -> bool {
+        self.failures.load(Ordering::SeqCst) >= API_UNAVAILABLE_THRESHOLD
+    }
+
+    /// Count a reconcile that failed because the API server is unreachable. Returns its requeue
+    /// delay and whether the backoff mode was entered, which is reported once instead of every
+    /// failure.
+    fn record_unavailable(&self, error_requeue: Duration) -> (Duration, bool) {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        (
+            api_unavailable_requeue_delay(error_requeue, failures),
+            failures == API_UNAVAILABLE_THRESHOLD,
+        )
+    }
+
+    /// Count any other reconcile result, successful or not, the API server answered it.
+    /// Returns whether the backoff mode was left.
+    fn record_available(&self) -> bool {
+        let left_backoff = self.failures.swap(0, Ordering::SeqCst) >= API_UNAVAILABLE_THRESHOLD;
+        if left_backoff {
+            tracing::info!("Kubernetes API server is available again");
+        }
+        left_backoff
+    }
+}
+
+/// Requeue failed reconciles after the configured requeue delay, doubled per consecutive
+/// failure of the cluster up to [`MAX_ERROR_REQUEUE`].
+///
+/// While the API server is unreachable every reconcile fails the same way. After
+/// [`API_UNAVAILABLE_THRESHOLD`] consecutive failures the requeue delay is doubled per failure
+/// (up to [`MAX_API_UNAVAILABLE_REQUEUE`]) and a single `APIServerUnavailable` warning is logged.
+/// Any other failure ends the backoff mode.
+pub fn error_policy(error: &Error, ctx: Context<Ctx>) -> ReconcilerAction {
+    let error_requeue = ctx.get_ref().error_requeue;
+    if !error.is_api_unavailable() {
+        ctx.get_ref().api_availability.record_available();
+        let failures = match error {
+            Error::Reconcile { failures, .. } => *failures,
+            _ => 1,
+        };
+        return ReconcilerAction {
+            requeue_after: Some(error_requeue_delay(error_requeue, failures)),
+        };
+    }
+
+    let (requeue_after, entered_backoff) = ctx
+        .get_ref()
+        .api_availability
+        .record_unavailable(error_requeue);
+    if entered_backoff {
+        tracing::warn!(
+            reason = "APIServerUnavailable",
+            failures = API_UNAVAILABLE_THRESHOLD,
+            "Kubernetes API server is unavailable, backing off"
+        );
+    }
+    ReconcilerAction {
+        requeue_after: Some(requeue_after),
+    }
+}
+
+/// The requeue delay of a cluster after `failures` consecutive failed reconciles.
+fn error_requeue_delay(error_requeue: Duration, failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    error_requeue
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_ERROR_REQUEUE)
+}
+
+/// The requeue delay after `failures` consecutive reconciles failed because the API server is
+/// unreachable.
+fn api_unavailable_requeue_delay(error_requeue: Duration, failures: u32) -> Duration {
+    if failures < API_UNAVAILABLE_THRESHOLD {
+        return error_requeue;
+    }
+    let exponent = (failures - API_UNAVAILABLE_THRESHOLD + 1).min(16);
+    error_requeue
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_API_UNAVAILABLE_REQUEUE)
+}
+
+/// TODO: this is pure boilerplate code that should be part of product-config.
+fn build_spark_role_properties(
+    resource: &SparkCluster,
+) -> HashMap<
+    String,
+    (
+        Vec<PropertyNameKind>,
+        Role<impl Configuration<Configurable = SparkCluster>>,
+    ),
+> {
+    let mut result = HashMap::new();
+    let pnk: Vec<PropertyNameKind> = vec![
+        PropertyNameKind::File(SPARK_ENV_SH.to_string()),
+        PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string()),
+        PropertyNameKind::File(SPARK_METRICS_PROPERTIES.to_string()),
+        PropertyNameKind::Env,
+    ];
+    if let Some(masters) = &resource.spec.masters {
+        result.insert(
+            SparkRole::Master.to_string(),
+            (pnk.clone(), masters.clone().erase()),
+        );
+    }
+    if let Some(workers) = &resource.spec.workers {
+        result.insert(
+            SparkRole::Worker.to_string(),
+            (pnk.clone(), workers.clone().erase()),
+        );
+    }
+    if let Some(history_servers) = &resource.spec.history_servers {
+        result.insert(
+            SparkRole::HistoryServer.to_string(),
+            (pnk, history_servers.clone().erase()),
+        );
+    }
+    result
+}
+
+/// Build [`ContainerPort`]s for the given rolegroup.
+///
+/// # Arguments
+/// * `sc`               - The cluster resource object.
+/// * `rolegroup`        - The rolegroup for which to extract the pods.
+/// * `rolegroup_config` - The validated configuration for the rolegroup.
+/// * `host_network`     - Whether the pods use the host network, the ports are host ports then.
+///
+fn build_container_ports(
+    sc: &SparkCluster,
+    rolegroup: &RoleGroupRef<SparkCluster>,
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+    host_network: bool,
+) -> Result<Vec<ContainerPort>, Error> {
+    let mut ports = build_ports(sc, rolegroup, rolegroup_config)?;
+    if jmx_exporter_enabled(sc) {
+        ports.push((PORT_NAME_JMX.to_string(), JMX_EXPORTER_PORT));
+    }
+    Ok(ports
+        .iter()
+        .map(|(name, value)| ContainerPort {
+            name: Some(name.clone()),
+            container_port: *value,
+            host_port: Some(*value).filter(|_| host_network),
+            protocol: Some("TCP".to_string()),
+            ..ContainerPort::default()
+        })
+        .collect())
+}
+
+/// Build the `log4j.properties` of a rolegroup, `None` to keep the logging defaults of Spark.
+///
+/// A `log4jConfig` of the cluster is used as is, otherwise the Spark console logging is
+/// configured with the log level of the rolegroup.
+///
+/// # Arguments
+/// * `sc`          - The cluster resource object.
+/// * `node_config` - The merged node config of the rolegroup.
+///
+fn build_log4j_properties(sc: &SparkCluster, node_config: &NodeConfig) -> Option<String> {
+    if let Some(log4j_config) = &sc.spec.log4j_config {
+        return Some(log4j_config.clone());
+    }
+    let log_level = node_config.log_level.as_deref()?;
+    Some(format!(
+        "\
+        log4j.rootCategory={}, console\n\
+        log4j.appender.console=org.apache.log4j.ConsoleAppender\n\
+        log4j.appender.console.target=System.err\n\
+        log4j.appender.console.layout=org.apache.log4j.PatternLayout\n\
+        log4j.appender.console.layout.ConversionPattern=%d{{yy/MM/dd HH:mm:ss}} %p %c{{1}}: %m%n\n",
+        log_level.to_uppercase()
+    ))
+}
+
+/// The number of GPUs a worker offers to its executors, `None` for other roles and workers
+/// without GPUs.
+///
+/// # Arguments
+/// * `role`        - The role of the rolegroup.
+/// * `node_config` - The merged node config of the rolegroup.
+///
+fn worker_gpus(role: &SparkRole, node_config: &NodeConfig) -> Option<i64> {
+    node_config
+        .gpu_limit
+        .filter(|gpus| *gpus > 0 && *role == SparkRole::Worker)
+}
+
+/// Whether the Prometheus JMX exporter agent runs in the Spark daemons.
+fn jmx_exporter_enabled(sc: &SparkCluster) -> bool {
+    sc.spec.jmx_exporter_enabled.unwrap_or(false)
+}
+
+/// Extract all named ports from the given validated configuration.
+///
+/// # Arguments
+/// * `_sc`              - The cluster resource object.
+/// * `rolegroup`        - The rolegroup for which to extract the pods.
+/// * `rolegroup_config` - The validated configuration for the rolegroup.
+///
+fn build_ports(
+    _sc: &SparkCluster,
+    rolegroup: &RoleGroupRef<SparkCluster>,
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+) -> Result<Vec<(String, i32)>, Error> {
+    Ok(match serde_yaml::from_str(&rolegroup.role).unwrap() {
+        SparkRole::Master => vec![
+            (
+                PORT_NAME_SPARK.to_string(),
+                rolegroup_config
+                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
+                    .and_then(|c| c.get(SPARK_ENV_MASTER_PORT))
+                    .unwrap_or(&String::from("7077"))
+                    .parse::<i32>()
+                    .map_err(|e| Error::InvalidPort {
+                        source: e,
+                        rolegroup_ref: rolegroup.clone(),
+                    })?,
+            ),
+            (
+                String::from(PORT_NAME_WEB),
+                rolegroup_config
+                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
+                    .and_then(|c| c.get(SPARK_ENV_MASTER_WEBUI_PORT))
+                    .unwrap_or(&String::from("8080"))
+                    .parse::<i32>()
+                    .map_err(|e| Error::InvalidPort {
+                        source: e,
+                        rolegroup_ref: rolegroup.clone(),
+                    })?,
+            ),
+            (
+                String::from(PORT_NAME_REST),
+                rolegroup_config
+                    .get(&PropertyNameKind::File(String::from(SPARK_DEFAULTS_CONF)))
+                    .and_then(|c| c.get(SPARK_DEFAULTS_MASTER_REST_PORT))
+                    .unwrap_or(&String::from(DEFAULT_MASTER_REST_PORT))
+                    .parse::<i32>()
+                    .map_err(|e| Error::InvalidPort {
+                        source: e,
+                        rolegroup_ref: rolegroup.clone(),
+                    })?,
+            ),
+        ],
+        SparkRole::Worker => vec![
+            (
+                PORT_NAME_SPARK.to_string(),
+                rolegroup_config
+                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
+                    .and_then(|c| c.get(SPARK_ENV_WORKER_PORT))
+                    .unwrap_or(&String::from("7077"))
+                    .parse::<i32>()
+                    .map_err(|e| Error::InvalidPort {
+                        source: e,
+                        rolegroup_ref: rolegroup.clone(),
+                    })?,
+            ),
+            (
+                String::from(PORT_NAME_WEB),
+                rolegroup_config
+                    .get(&PropertyNameKind::File(String::from(SPARK_ENV_SH)))
+                    .and_then(|c| c.get(SPARK_ENV_WORKER_WEBUI_PORT))
+                    .unwrap_or(&String::from("8080"))
+                    .parse::<i32>()
+                    .map_err(|e| Error::InvalidPort {
+                        source: e,
+                        rolegroup_ref: rolegroup.clone(),
+                    })?,
+            ),
+        ],
+        SparkRole::HistoryServer => vec![(
+            String::from(PORT_NAME_WEB),
+            rolegroup_config
+                .get(&PropertyNameKind::File(String::from(SPARK_DEFAULTS_CONF)))
+                .and_then(|c| c.get(SPARK_DEFAULTS_HISTORY_WEBUI_PORT))
+                .unwrap_or(&String::from("8080"))
+                .parse::<i32>()
+                .map_err(|e| Error::InvalidPort {
+                    source: e,
+                    rolegroup_ref: rolegroup.clone(),
+                })?,
+        )],
+    })
+}
+
+/// Unroll a map into a String using a given assignment character (for writing config maps)
+///
+/// # Arguments
+/// * `map`        - Map containing option_name:option_value pairs
+/// * `assignment` - Used character to assign option_value to option_name (e.g. "=", " ", ":" ...)
+///
+fn convert_map_to_string(map: &BTreeMap<String, String>, assignment: &str) -> String {
+    let mut data = String::new();
+    for (key, value) in map {
+        data.push_str(format!("{}{}{}\n", key, assignment, value).as_str());
+    }
+    data
+}
+
+/// Unroll a map into `export KEY="VALUE"` lines (for writing spark-env.sh)
+///
+/// Values are double quoted, so characters with a special meaning inside double quotes are
+/// escaped to keep them literal.
+///
+/// # Arguments
+/// * `map` - Map containing variable_name:variable_value pairs
+///
+fn convert_map_to_env_exports(map: &BTreeMap<String, String>) -> String {
+    let mut data = String::new();
+    for (key, value) in map {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | '$' | '`') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        data.push_str(format!("export {}=\"{}\"\n", key, escaped).as_str());
+    }
+    data
+}
+
+/// Deep-merge the `pod_template_override` of a role group into the generated pod template, see
+/// [`NodeConfig::pod_template_override`]. The labels of the generated template are kept, since the
+/// StatefulSet selects its pods by them.
+///
+/// # Arguments
+/// * `template`  - The pod template generated by the operator.
+/// * `overrides` - The pod template override of the role group.
+///
+fn override_pod_template(
+    template: PodTemplateSpec,
+    overrides: &PodTemplateSpec,
+) -> Result<PodTemplateSpec, serde_json::Error> {
+    let labels = template
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.labels.clone());
+    let mut merged = serde_json::to_value(template)?;
+    merge_json(&mut merged, serde_json::to_value(overrides)?);
+    let mut merged: PodTemplateSpec = serde_json::from_value(merged)?;
+    merged
+        .metadata
+        .get_or_insert_with(ObjectMeta::default)
+        .labels = labels;
+    Ok(merged)
+}
+
+/// Merge objects field by field and lists of named objects entry by entry, all other values of
+/// `overrides` replace the ones of `base`.
+fn merge_json(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    use serde_json::Value;
+
+    let named = |values: &[Value]| values.iter().all(|value| value.get("name").is_some());
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge_json(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (Value::Array(base), Value::Array(overrides))
+            if named(base.as_slice()) && named(&overrides) =>
+        {
+            for value in overrides {
+                match base
+                    .iter_mut()
+                    .find(|existing| existing.get("name") == value.get("name"))
+                {
+                    Some(existing) => merge_json(existing, value),
+                    None => base.push(value),
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// The version the cluster is being upgraded from, `None` unless an upgrade is in progress.
+///
+/// A changed version changes the image of all StatefulSets, which then replace their pods one
+/// at a time like for any other update. The previous version is kept until all pods are ready.
+fn version_upgraded_from(
+    sc: &SparkCluster,
+    rolling_update: bool,
+    all_replicas_ready: bool,
+) -> Option<String> {
+    sc.status
+        .as_ref()
+        .and_then(|status| status.version.clone())
+        .filter(|previous_version| {
+            Some(previous_version) != sc.spec.version.as_ref()
+                && (rolling_update || !all_replicas_ready)
+        })
+}
+
+/// Build the master URL for the start-worker.sh script and `spark.master`
+///
+/// # Arguments
+/// * `sc`                        - The cluster resource object.
+/// * `rolegroup_ref`             - The worker's RoleGroupRef.
+/// * `default_master_role_ports` - The ports used to create the master's ClusterIP service.
+///
+fn build_master_service_url(
+    sc: &SparkCluster,
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
+    default_master_role_ports: &[(String, i32)],
+) -> String {
+    if let Some(url) = build_ha_master_url(sc, default_master_role_ports) {
+        return url;
+    }
+    format!(
+        "spark://{}:{}",
+        RoleGroupRef {
+            cluster: rolegroup_ref.cluster.clone(),
+            role: SparkRole::Master.to_string(),
+            role_group: "default".to_string(),
+        }
+        .object_name(),
+        master_port(default_master_role_ports)
+    )
+}
+
+/// Build the master URL listing every master pod, so that workers and applications fail over to
+/// the master elected via ZooKeeper. `None` without `haConfig`.
+///
+/// # Arguments
+/// * `sc`                        - The cluster resource object.
+/// * `default_master_role_ports` - The ports used to create the master's ClusterIP service.
+///
+fn build_ha_master_url(
+    sc: &SparkCluster,
+    default_master_role_ports: &[(String, i32)],
+) -> Option<String> {
+    sc.spec.ha_config.as_ref()?;
+    let namespace = sc.namespace()?;
+    let port = master_port(default_master_role_ports);
+    // Sorted by role group, so that the URL and thus the config hash are stable
+    let role_groups = sc
+        .spec
+        .masters
+        .iter()
+        .flat_map(|role| role.role_groups.iter())
+        .collect::<BTreeMap<_, _>>();
+    let mut masters = Vec::new();
+    for (role_group, rg) in role_groups {
+        let service_name = RoleGroupRef {
+            cluster: ObjectRef::from_obj(sc),
+            role: SparkRole::Master.to_string(),
+            role_group: role_group.clone(),
+        }
+        .object_name();
+        for i in 0..rg.replicas.unwrap_or(0) {
+            masters.push(format!(
+                "{}-{}.{}.{}.svc.cluster.local:{}",
+                service_name, i, service_name, namespace, port
+            ));
+        }
+    }
+    Some(format!("spark://{}", masters.join(",")))
+}
+
+/// Read the state of the elected master. All master pods are asked at the same time, so an
+/// unreachable master delays the reconcile by one request timeout at most. Standby masters
+/// report no workers.
+///
+/// # Arguments
+/// * `sc`                        - The cluster resource object.
+/// * `default_master_role_ports` - The ports used to create the master's ClusterIP service.
+///
+async fn fetch_leader_state(
+    sc: &SparkCluster,
+    default_master_role_ports: &[(String, i32)],
+) -> Option<MasterState> {
+    let timeout = Duration::from_secs(
+        sc.spec
+            .master_api_timeout_seconds
+            .unwrap_or(DEFAULT_MASTER_API_TIMEOUT_SECONDS),
+    );
+    let requests = master_web_ui_urls(sc, default_master_role_ports)
+        .into_iter()
+        .map(|url| {
+            Box::pin(async move {
+                match master_state::fetch(&url, timeout).await {
+                    Some(state) if state.status == MASTER_STATUS_ALIVE => Ok(state),
+                    _ => Err(()),
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    if requests.is_empty() {
+        return None;
+    }
+    future::select_ok(requests)
+        .await
+        .ok()
+        .map(|(state, _pending)| state)
+}
+
+/// The applications of the master running longer than `threshold_hours` at `now`, with their
+/// age.
+///
+/// # Arguments
+/// * `master_state`    - The state of the elected master.
+/// * `threshold_hours` - The age above which applications are considered stale.
+/// * `now`             - The current time.
+///
+fn stale_applications(
+    master_state: &MasterState,
+    threshold_hours: f64,
+    now: DateTime<Utc>,
+) -> Vec<(String, Duration)> {
+    master_state
+        .active_apps
+        .iter()
+        .filter_map(|application| {
+            let age_millis = now.timestamp_millis() - application.start_time;
+            if age_millis <= 0 || age_millis as f64 <= threshold_hours * 3_600_000.0 {
+                return None;
+            }
+            Some((
+                application.id.clone(),
+                Duration::from_millis(age_millis as u64),
+            ))
+        })
+        .collect()
+}
+
+/// The web UI URLs of all master pods, sorted by role group.
+///
+/// # Arguments
+/// * `sc`                        - The cluster resource object.
+/// * `default_master_role_ports` - The ports used to create the master's ClusterIP service.
+///
+fn master_web_ui_urls(
+    sc: &SparkCluster,
+    default_master_role_ports: &[(String, i32)],
+) -> Vec<String> {
+    let namespace = match sc.namespace() {
+        Some(namespace) => namespace,
+        None => return Vec::new(),
+    };
+    let port = default_master_role_ports
+        .iter()
+        .find(|(name, _)| name == PORT_NAME_WEB)
+        .map_or(8080, |(_, port)| *port);
+    let role_groups = sc
+        .spec
+        .masters
+        .iter()
+        .flat_map(|role| role.role_groups.iter())
+        .collect::<BTreeMap<_, _>>();
+    let mut urls = Vec::new();
+    for (role_group, rg) in role_groups {
+        let service_name = RoleGroupRef {
+            cluster: ObjectRef::from_obj(sc),
+            role: SparkRole::Master.to_string(),
+            role_group: role_group.clone(),
+        }
+        .object_name();
+        for i in 0..rg.replicas.unwrap_or(0) {
+            urls.push(format!(
+                "http://{}-{}.{}.{}.svc.cluster.local:{}",
+                service_name, i, service_name, namespace, port
+            ));
+        }
+    }
+    urls
+}
+
+/// The number of master pods over all role groups.
+fn master_count(sc: &SparkCluster) -> u32 {
+    sc.spec
+        .masters
+        .iter()
+        .flat_map(|role| role.role_groups.values())
+        .map(|rg| u32::from(rg.replicas.unwrap_or(0)))
+        .sum()
+}
+
+/// The number of worker pods over all role groups.
+fn worker_count(sc: &SparkCluster) -> u32 {
+    sc.spec
+        .workers
+        .iter()
+        .flat_map(|role| role.role_groups.values())
+        .map(|rg| u32::from(rg.replicas.unwrap_or(0)))
+        .sum()
+}
+
+/// Extract the spark port from the master role ports, falling back to the Spark default.
+///
+/// # Arguments
+/// * `default_master_role_ports` - The ports used to create the master's ClusterIP service.
+///
+fn master_port(default_master_role_ports: &[(String, i32)]) -> i32 {
+    default_master_role_ports
+        .iter()
+        .filter_map(|(name, value)| match name.as_ref() {
+            PORT_NAME_SPARK => Some(*value),
+            _ => None,
+        })
+        .take(1)
+        .next()
+        .unwrap_or(7077)
+}
+
+/// Extract the SPARK_CONF_DIR path from the validated rolegroup configuration.
+///
+/// # Arguments
+/// * `rolegroup_config` - Validated config for a rolegroup.
+///
+fn spark_conf_dir(
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+) -> String {
+    rolegroup_config
+        .get(&PropertyNameKind::Env)
+        .iter()
+        .flat_map(|env_vars| env_vars.iter())
+        .filter_map(|(k, v)| match k.as_ref() {
+            SPARK_CONF_DIR => Some(v.clone()),
+            _ => None,
+        })
+        .take(1)
+        .next()
+        .unwrap_or_else(|| "/stackable/config".to_string())
+}
+
+/// Extract the log path from the validated rolegroup configuration, i.e. the event log
+/// directory (`spark.eventLog.dir`, or `spark.history.fs.logDirectory` of history servers).
+/// A `file:` URL like the default `file:///tmp/spark-events` is reduced to its path, other
+/// URLs like `s3a://` are not stored in a volume and return `None`.
+///
+/// # Arguments
+/// * `rolegroup_config` - Validated config for a rolegroup.
+///
+fn spark_log_dir(
+    rolegroup_config: &HashMap<PropertyNameKind, BTreeMap<String, String>>,
+) -> Option<String> {
+    let spark_defaults =
+        rolegroup_config.get(&PropertyNameKind::File(String::from(SPARK_DEFAULTS_CONF)));
+    let log_dir = spark_defaults
+        .and_then(|vars| {
+            vars.get(SPARK_DEFAULTS_EVENT_LOG_DIR)
+                .or_else(|| vars.get(SPARK_DEFAULTS_HISTORY_FS_LOG_DIRECTORY))
+        })
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_LOG_DIR);
+    let local_path = log_dir
+        .strip_prefix("file://")
+        .or_else(|| log_dir.strip_prefix("file:"))
+        .unwrap_or(log_dir);
+    if local_path.contains("://") {
+        return None;
+    }
+    Some(local_path.to_string())
+}
+
+/// The `Secret` with the S3 credentials for the event logs, if configured.
+fn s3_credentials_secret(sc: &SparkCluster) -> Option<&str> {
+    sc.common_config()?
+        .s3
+        .as_ref()?
+        .credentials_secret
+        .as_deref()
+}
+
+fn version(sc: &SparkCluster) -> Result<&str, Error> {
+    sc.spec.version.as_deref().ok_or(ObjectHasNoVersion {
+        obj_ref: ObjectRef::from_obj(sc),
+    })
+}
+
+fn container_command(
+    sc: &SparkCluster,
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
+    default_master_role_ports: &[(String, i32)],
+) -> Vec<String> {
+    match serde_yaml::from_str(&rolegroup_ref.role).unwrap() {
+        SparkRole::Master => vec!["sbin/start-master.sh".to_string()],
+        SparkRole::HistoryServer => vec!["sbin/start-history-server.sh".to_string()],
+        SparkRole::Worker => vec![
+            "sbin/start-slave.sh".to_string(),
+            build_master_service_url(sc, rolegroup_ref, default_master_role_ports),
+        ],
+    }
+}
+
+/// Workers deregister from the master before they are stopped, instead of the master noticing
+/// their absence only after `spark.worker.timeout`.
+fn default_pre_stop_command(role: &SparkRole) -> Option<Vec<String>> {
+    match role {
+        SparkRole::Worker => Some(vec!["sbin/stop-slave.sh".to_string()]),
+        SparkRole::Master | SparkRole::HistoryServer => None,
+    }
+}
+
+fn rolegroup_replicas(
+    sc: &SparkCluster,
+    rolegroup_ref: &RoleGroupRef<SparkCluster>,
+) -> Result<i32, Error> {
+    let replicas = match serde_yaml::from_str(&rolegroup_ref.role).unwrap() {
+        SparkRole::Worker => sc
+            .spec
+            .workers
+            .as_ref()
+            .ok_or(Error::MissingRoleGroup {
+                obj_ref: rolegroup_ref.clone(),
+            })?
+            .role_groups
+            .get(&rolegroup_ref.role_group)
+            .and_then(|rg| rg.replicas)
+            .map(i32::from)
+            .unwrap_or(0),
+        SparkRole::HistoryServer => sc
+            .spec
+            .history_servers
+            .as_ref()
+            .ok_or(Error::MissingRoleGroup {
+                obj_ref: rolegroup_ref.clone(),
+            })?
+            .role_groups
+            .get(&rolegroup_ref.role_group)
+            .and_then(|rg| rg.replicas)
+            .map(i32::from)
+            .unwrap_or(0),
+        SparkRole::Master => sc
+            .spec
+            .masters
+            .as_ref()
+            .ok_or(Error::MissingRoleGroup {
+                obj_ref: rolegroup_ref.clone(),
+            })?
+            .role_groups
+            .get(&rolegroup_ref.role_group)
+            .and_then(|rg| rg.replicas)
+            .map(i32::from)
+            .unwrap_or(0),
+    };
+
+    if sc.spec.stopped.unwrap_or(false) {
+        Ok(0)
+    } else {
         Ok(replicas)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stackable_operator::k8s_openapi::ByteString;
+
+    /// The rolegroup configs like computed by `reconcile_cluster`.
+    fn validated_config(sc: &SparkCluster) -> ValidatedRoleConfigByPropertyKind {
+        let product_config = ProductConfigManager::from_yaml_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../deploy/config-spec/properties.yaml"
+        ))
+        .unwrap();
+        validate_all_roles_and_groups_config(
+            version(sc).unwrap(),
+            &transform_all_roles_to_config(sc, build_spark_role_properties(sc)),
+            &product_config,
+            false,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn worker_gpus_keep_the_jvm_opts_of_the_role() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  jvmOpts: -XX:+UseG1GC
+                roleGroups:
+                  gpu:
+                    replicas: 1
+                    config:
+                      gpuLimit: 2
+            ",
+        )
+        .unwrap();
+        let rolegroup = sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "gpu");
+        let config_map = build_rolegroup_config_map(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &rolegroup,
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["gpu"],
+        )
+        .unwrap();
+        let data = config_map.data.unwrap_or_default();
+        assert!(data[SPARK_ENV_SH].contains(
+            "export SPARK_WORKER_OPTS=\"-XX:+UseG1GC \
+            -Dspark.worker.resource.gpu.amount=2 \
+            -Dspark.worker.resource.gpu.discoveryScript=/stackable/gpu/getGpusResources.sh\""
+        ));
+        assert!(data[SPARK_DEFAULTS_CONF].contains("spark.executor.resource.gpu.amount 2\n"));
+        assert_eq!(data[GPU_DISCOVERY_SCRIPT], GPU_DISCOVERY_SCRIPT_CONTENT);
+    }
+
+    #[test]
+    fn workers_without_gpus_get_no_gpu_options() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  gpuLimit: 2
+                roleGroups:
+                  cpu:
+                    replicas: 1
+                    config:
+                      gpuLimit: 0
+            ",
+        )
+        .unwrap();
+        let rolegroup = sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "cpu");
+        let config_map = build_rolegroup_config_map(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &rolegroup,
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["cpu"],
+        )
+        .unwrap();
+        let data = config_map.data.unwrap_or_default();
+        assert!(!data[SPARK_ENV_SH].contains("gpu"));
+        assert!(!data[SPARK_DEFAULTS_CONF].contains("gpu"));
+        assert!(!data.contains_key(GPU_DISCOVERY_SCRIPT));
+    }
+
+    #[test]
+    fn master_url_is_published_to_target_namespace() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              publishMasterUrl: spark-jobs/simple-spark-master
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let config_map = build_master_url_config_map(
+            &sc,
+            "spark-jobs/simple-spark-master",
+            &[(PORT_NAME_SPARK.to_string(), 7078)],
+        )
+        .unwrap();
+        assert_eq!(config_map.metadata.namespace.as_deref(), Some("spark-jobs"));
+        assert_eq!(
+            config_map.metadata.name.as_deref(),
+            Some("simple-spark-master")
+        );
+        // Owner references cannot point to another namespace
+        assert_eq!(config_map.metadata.owner_references, None);
+        assert_eq!(
+            config_map
+                .data
+                .unwrap_or_default()
+                .get(MASTER_URL_CONFIG_MAP_KEY)
+                .map(String::as_str),
+            Some("spark://simple.default.svc.cluster.local:7078")
+        );
+    }
+
+    #[test]
+    fn malformed_master_url_targets_are_rejected() {
+        assert_eq!(
+            parse_master_url_target("spark-jobs/url"),
+            Some(("spark-jobs", "url"))
+        );
+        assert_eq!(parse_master_url_target("url"), None);
+        assert_eq!(parse_master_url_target("/url"), None);
+        assert_eq!(parse_master_url_target("spark-jobs/"), None);
+    }
+
+    #[test]
+    fn config_map_hash_is_a_stable_digest_of_the_data() {
+        let config_map = ConfigMapBuilder::new()
+            .metadata(
+                ObjectMetaBuilder::new()
+                    .name("simple-master-default")
+                    .build(),
+            )
+            .add_data(SPARK_DEFAULTS_CONF, "spark.master spark://simple:7077\n")
+            .build()
+            .unwrap();
+        assert_eq!(
+            config_map_hash(&config_map),
+            "6f194b98403e5877446548ad50f9a65b5754e1db7fefa6145a12de6d32bb7de2"
+        );
+    }
+
+    #[test]
+    fn config_map_hash_changes_with_the_data() {
+        let config_map = |spark_defaults: &str| {
+            ConfigMapBuilder::new()
+                .metadata(
+                    ObjectMetaBuilder::new()
+                        .name("simple-master-default")
+                        .build(),
+                )
+                .add_data(SPARK_DEFAULTS_CONF, spark_defaults)
+                .build()
+                .unwrap()
+        };
+        assert_ne!(
+            config_map_hash(&config_map("spark.master spark://simple:7077\n")),
+            config_map_hash(&config_map("spark.master spark://simple:7078\n"))
+        );
+    }
+
+    #[test]
+    fn secrets_hash_changes_with_the_data() {
+        // The data is base64 encoded, "secret" and "rotated"
+        let secret = |password: &str| -> Secret {
+            serde_yaml::from_str(&format!(
+                "
+                apiVersion: v1
+                kind: Secret
+                metadata:
+                  name: spark-s3-credentials
+                  resourceVersion: \"1\"
+                data:
+                  password: {}
+                ",
+                password
+            ))
+            .unwrap()
+        };
+        assert_eq!(
+            secrets_hash(&[secret("c2VjcmV0")]),
+            secrets_hash(&[secret("c2VjcmV0")])
+        );
+        assert_ne!(
+            secrets_hash(&[secret("c2VjcmV0")]),
+            secrets_hash(&[secret("cm90YXRlZA==")])
+        );
+    }
+
+    #[test]
+    fn secrets_hash_ignores_the_order() {
+        let secret = |name: &str| Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..ObjectMeta::default()
+            },
+            data: Some(
+                vec![("key".to_string(), ByteString(b"value".to_vec()))]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Secret::default()
+        };
+        assert_eq!(
+            secrets_hash(&[secret("a"), secret("b")]),
+            secrets_hash(&[secret("b"), secret("a")])
+        );
+    }
+
+    #[test]
+    fn secret_rotation_is_detected_by_a_changed_hash() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+            status:
+              managedSecretsHash: abc
+              secretRotationTime: 2021-11-01T10:00:00Z
+            ",
+        )
+        .unwrap();
+        let previous_rotation = sc.status.as_ref().unwrap().secret_rotation_time.clone();
+        assert_eq!(secret_rotation_time(&sc, "abc"), previous_rotation);
+        assert_ne!(secret_rotation_time(&sc, "def"), previous_rotation);
+    }
+
+    #[test]
+    fn first_reconcile_is_no_secret_rotation() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+            ",
+        )
+        .unwrap();
+        assert_eq!(secret_rotation_time(&sc, "abc"), None);
+    }
+
+    #[test]
+    fn missing_shuffle_service_is_only_reported_with_dynamic_allocation() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert_eq!(shuffle_service_missing(&sc, &validated_config(&sc)), None);
+
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              dynamicAllocation:
+                enabled: true
+                minExecutors: 1
+                maxExecutors: 4
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  no-shuffle:
+                    replicas: 1
+                    configOverrides:
+                      spark-defaults.conf:
+                        spark.shuffle.service.enabled: \"false\"
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            shuffle_service_missing(&sc, &validated_config(&sc)),
+            Some(false)
+        );
+
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              dynamicAllocation:
+                enabled: true
+                minExecutors: 1
+                maxExecutors: 4
+              workers:
+                configOverrides:
+                  spark-defaults.conf:
+                    spark.shuffle.service.enabled: \"false\"
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            shuffle_service_missing(&sc, &validated_config(&sc)),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn toggling_the_event_log_volume_changes_the_volume_claim_templates() {
+        let statefulset = |sc: &SparkCluster| {
+            build_rolegroup_statefulset(
+                sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+                &validated_config(sc)[&SparkRole::Worker.to_string()]["default"],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+        };
+        let per_pod: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let shared: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              eventLogVolume:
+                storageClass: nfs
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let remote: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              config:
+                logDir: s3a://spark-logs/events
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(!volume_claim_templates_changed(
+            &statefulset(&per_pod),
+            &statefulset(&per_pod)
+        ));
+        assert!(volume_claim_templates_changed(
+            &statefulset(&per_pod),
+            &statefulset(&shared)
+        ));
+        assert!(volume_claim_templates_changed(
+            &statefulset(&shared),
+            &statefulset(&per_pod)
+        ));
+        assert!(volume_claim_templates_changed(
+            &statefulset(&per_pod),
+            &statefulset(&remote)
+        ));
+        assert!(!volume_claim_templates_changed(
+            &statefulset(&shared),
+            &statefulset(&remote)
+        ));
+    }
+
+    #[test]
+    fn security_contexts_are_unset_by_default() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  hardened:
+                    replicas: 1
+                    config:
+                      podSecurityContext:
+                        runAsNonRoot: true
+                        runAsUser: 1000
+            ",
+        )
+        .unwrap();
+        let pod_spec = |rolegroup: &str| {
+            build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), rolegroup),
+                &validated_config(&sc)[&SparkRole::Worker.to_string()][rolegroup],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap()
+        };
+        let default = pod_spec("default");
+        assert_eq!(default.security_context, None);
+        assert_eq!(default.containers[0].security_context, None);
+        assert_eq!(
+            pod_spec("hardened")
+                .security_context
+                .and_then(|security_context| security_context.run_as_user),
+            Some(1000)
+        );
+    }
+
+    #[test]
+    fn liveness_probes_default_to_the_web_ui_probe() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  probed:
+                    replicas: 1
+                    config:
+                      livenessProbe:
+                        failureThreshold: 5
+            ",
+        )
+        .unwrap();
+        let container = |rolegroup: &str| {
+            build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(SparkRole::Master.to_string(), rolegroup),
+                &validated_config(&sc)[&SparkRole::Master.to_string()][rolegroup],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap()
+            .containers
+            .remove(0)
+        };
+        assert_eq!(container("default").liveness_probe, Some(PROBE.clone()));
+        let liveness_probe = container("probed").liveness_probe.unwrap();
+        assert_eq!(liveness_probe.failure_threshold, Some(5));
+        assert_eq!(
+            liveness_probe.http_get,
+            Some(HTTPGetAction {
+                path: Some("/json".to_string()),
+                port: IntOrString::String(PORT_NAME_WEB.to_string()),
+                ..HTTPGetAction::default()
+            })
+        );
+    }
+
+    #[test]
+    fn worker_readiness_is_checked_on_the_web_ui_port() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let container = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap()
+        .containers
+        .remove(0);
+        assert_eq!(
+            container
+                .readiness_probe
+                .and_then(|probe| probe.tcp_socket)
+                .map(|tcp_socket| tcp_socket.port),
+            Some(IntOrString::String(PORT_NAME_WEB.to_string()))
+        );
+        assert_eq!(
+            container
+                .ports
+                .unwrap_or_default()
+                .iter()
+                .find(|port| port.name.as_deref() == Some(PORT_NAME_WEB))
+                .map(|port| port.container_port),
+            Some(8081)
+        );
+    }
+
+    #[test]
+    fn master_pod_disruption_budget_follows_the_master_count() {
+        let min_available = |replicas: u16| {
+            let sc: SparkCluster = serde_yaml::from_str(&format!(
+                "
+                apiVersion: spark.stackable.tech/v1alpha1
+                kind: SparkCluster
+                metadata:
+                  name: simple
+                  namespace: default
+                  uid: 1a2b3c
+                spec:
+                  version: 3.0.1
+                  masters:
+                    roleGroups:
+                      default:
+                        replicas: {}
+                ",
+                replicas
+            ))
+            .unwrap();
+            build_master_pod_disruption_budget(&sc)
+                .unwrap()
+                .map(|pdb| pdb.spec.unwrap().min_available)
+        };
+        assert_eq!(min_available(0), None);
+        assert_eq!(min_available(1), Some(Some(IntOrString::Int(0))));
+        assert_eq!(min_available(3), Some(Some(IntOrString::Int(1))));
+    }
+
+    #[test]
+    fn worker_pod_disruption_budget_follows_the_worker_count() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+            ",
+        )
+        .unwrap();
+        assert_eq!(build_worker_pod_disruption_budget(&sc).unwrap(), None);
+
+        let spec = |replicas: u16| {
+            let sc: SparkCluster = serde_yaml::from_str(&format!(
+                "
+                apiVersion: spark.stackable.tech/v1alpha1
+                kind: SparkCluster
+                metadata:
+                  name: simple
+                  namespace: default
+                  uid: 1a2b3c
+                spec:
+                  version: 3.0.1
+                  workerMaxUnavailable: 2
+                  workers:
+                    roleGroups:
+                      default:
+                        replicas: {}
+                ",
+                replicas
+            ))
+            .unwrap();
+            build_worker_pod_disruption_budget(&sc)
+                .unwrap()
+                .map(|pdb| pdb.spec.unwrap())
+                .map(|spec| (spec.min_available, spec.max_unavailable))
+        };
+        assert_eq!(spec(0), None);
+        assert_eq!(spec(1), Some((Some(IntOrString::Int(0)), None)));
+        assert_eq!(spec(4), Some((None, Some(IntOrString::Int(2)))));
+    }
+
+    #[test]
+    fn master_state_is_read_from_every_master_pod() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 2
+                  zone-b:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            master_web_ui_urls(&sc, &[(PORT_NAME_WEB.to_string(), 8081)]),
+            vec![
+                "http://simple-master-default-0.simple-master-default.default.svc.cluster.local:8081",
+                "http://simple-master-default-1.simple-master-default.default.svc.cluster.local:8081",
+                "http://simple-master-zone-b-0.simple-master-zone-b.default.svc.cluster.local:8081",
+            ]
+        );
+    }
+
+    #[test]
+    fn spec_changes_are_detected_with_the_observed_generation() {
+        let mut sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              generation: 2
+            spec:
+              version: 3.0.1
+            ",
+        )
+        .unwrap();
+        assert!(spec_changed(&sc));
+        let mut observed = condition(CONDITION_AVAILABLE, "True");
+        observed.observed_generation = Some(1);
+        sc.status = Some(SparkClusterStatus {
+            conditions: vec![observed],
+            ..SparkClusterStatus::default()
+        });
+        assert!(spec_changed(&sc));
+        sc.status.as_mut().unwrap().conditions[0].observed_generation = Some(2);
+        assert!(!spec_changed(&sc));
+    }
+
+    #[test]
+    fn non_critical_changes_are_deferred_while_the_cluster_is_upgrading() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              labels:
+                team: analytics
+            spec:
+              version: 3.0.1
+              podAnnotations:
+                prometheus.io/scrape: 'true'
+              masters:
+                config:
+                  logLevel: debug
+                roleGroups:
+                  default:
+                    replicas: 1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  large:
+                    replicas: 1
+                    config:
+                      logLevel: warn
+            status:
+              phase: UpgradeInProgress
+              appliedNonCriticalConfig:
+                labels:
+                  team: platform
+                logLevels:
+                  simple-master-default: info
+                  simple-worker-default: null
+            ",
+        )
+        .unwrap();
+        let (deferred_sc, deferred) = defer_non_critical_changes(sc);
+        assert_eq!(deferred, vec!["labels", "podAnnotations", "logging"]);
+        assert_eq!(
+            forwarded_cluster_labels(&deferred_sc).get("team"),
+            Some(&"platform".to_string())
+        );
+        assert_eq!(deferred_sc.spec.pod_annotations, None);
+        assert_eq!(
+            deferred_sc
+                .node_config(&SparkRole::Master, "default")
+                .log_level
+                .as_deref(),
+            Some("info")
+        );
+        assert_eq!(
+            deferred_sc
+                .node_config(&SparkRole::Worker, "default")
+                .log_level,
+            None
+        );
+        // New rolegroups have nothing to keep
+        assert_eq!(
+            deferred_sc
+                .node_config(&SparkRole::Worker, "large")
+                .log_level
+                .as_deref(),
+            Some("warn")
+        );
+    }
+
+    #[test]
+    fn deferred_changes_are_applied_once_the_cluster_is_running() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              labels:
+                team: analytics
+            spec:
+              version: 3.0.1
+              log4jConfig: log4j.rootCategory=WARN, console
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      logLevel: debug
+            status:
+              phase: Running
+              appliedNonCriticalConfig:
+                labels:
+                  team: platform
+                logLevels:
+                  simple-master-default: info
+            ",
+        )
+        .unwrap();
+        let (applied_sc, deferred) = defer_non_critical_changes(sc.clone());
+        assert!(deferred.is_empty());
+        assert_eq!(applied_sc, sc);
+        let applied = non_critical_config(&applied_sc);
+        assert_eq!(applied.labels.get("team"), Some(&"analytics".to_string()));
+        assert_eq!(
+            applied.log4j_config.as_deref(),
+            Some("log4j.rootCategory=WARN, console")
+        );
+        assert_eq!(
+            applied.log_levels["simple-master-default"].as_deref(),
+            Some("debug")
+        );
+    }
+
+    #[test]
+    fn unchanged_non_critical_config_is_not_deferred() {
+        let mut sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.2
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        sc.status = Some(SparkClusterStatus {
+            phase: Some(SparkClusterPhase::UpgradeInProgress),
+            applied_non_critical_config: Some(non_critical_config(&sc)),
+            ..SparkClusterStatus::default()
+        });
+        let (_, deferred) = defer_non_critical_changes(sc);
+        assert!(deferred.is_empty());
+    }
+
+    fn condition(condition_type: &str, status: &str) -> Condition {
+        Condition {
+            last_transition_time: Time(Utc::now()),
+            message: String::new(),
+            observed_generation: None,
+            reason: "Reason".to_string(),
+            status: status.to_string(),
+            type_: condition_type.to_string(),
+        }
+    }
+
+    #[test]
+    fn no_matching_nodes_is_a_warning_right_away() {
+        let no_matching_nodes = condition(CONDITION_NO_MATCHING_NODES, "True");
+        assert_eq!(
+            condition_event_type(None, &no_matching_nodes),
+            Some(EVENT_TYPE_WARNING)
+        );
+        assert_eq!(
+            condition_event_type(Some("False"), &no_matching_nodes),
+            Some(EVENT_TYPE_WARNING)
+        );
+        assert_eq!(condition_event_type(Some("True"), &no_matching_nodes), None);
+        assert_eq!(
+            condition_event_type(
+                Some("True"),
+                &condition(CONDITION_NO_MATCHING_NODES, "False")
+            ),
+            Some(EVENT_TYPE_NORMAL)
+        );
+    }
+
+    #[test]
+    fn new_clusters_are_not_reported_as_degraded() {
+        let degraded = condition(CONDITION_DEGRADED, "True");
+        assert_eq!(condition_event_type(None, &degraded), None);
+        assert_eq!(
+            condition_event_type(Some("False"), &degraded),
+            Some(EVENT_TYPE_WARNING)
+        );
+    }
+
+    #[test]
+    fn api_unavailable_requeue_backs_off_after_threshold() {
+        let error_requeue = Duration::from_secs(5);
+        let delays = (1..=5)
+            .map(|failures| api_unavailable_requeue_delay(error_requeue, failures))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_secs(5),
+                Duration::from_secs(5),
+                Duration::from_secs(10),
+                Duration::from_secs(20),
+                Duration::from_secs(40),
+            ]
+        );
+    }
+
+    #[test]
+    fn api_unavailable_requeue_is_capped() {
+        assert_eq!(
+            api_unavailable_requeue_delay(Duration::from_secs(5), 50),
+            MAX_API_UNAVAILABLE_REQUEUE
+        );
+    }
+
+    #[test]
+    fn resource_profiles_are_added_to_the_spark_defaults() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              config:
+                resourceProfiles:
+                  - id: large
+                    executorCores: 4
+                    executorMemory: 8g
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let spark_defaults = &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"]
+            [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert_eq!(
+            spark_defaults.get("spark.resourceProfiles.large.executor.cores"),
+            Some(&"4".to_string())
+        );
+        assert_eq!(
+            spark_defaults.get("spark.resourceProfiles.large.executor.memory"),
+            Some(&"8g".to_string())
+        );
+    }
+
+    #[test]
+    fn resource_profile_ids_must_be_unique_and_not_reserved() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              config:
+                resourceProfiles:
+                  - id: large
+                    executorCores: 4
+                    executorMemory: 8g
+                  - id: large
+                    executorCores: 8
+                    executorMemory: 16g
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            validate_spec(&sc),
+            Err(Error::DuplicateResourceProfile { id }) if id == "large"
+        ));
+
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              config:
+                resourceProfiles:
+                  - id: default
+                    executorCores: 4
+                    executorMemory: 8g
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            validate_spec(&sc),
+            Err(Error::ReservedResourceProfile { id }) if id == "default"
+        ));
+    }
+
+    #[test]
+    fn kryo_serializer_registers_the_configured_classes() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              config:
+                serializer:
+                  kryoWithClasses:
+                    - org.example.Event
+                    - org.example.Session
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let spark_defaults = &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"]
+            [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert_eq!(
+            spark_defaults.get(SPARK_DEFAULTS_SERIALIZER),
+            Some(&KRYO_SERIALIZER_CLASS.to_string())
+        );
+        assert_eq!(
+            spark_defaults.get(SPARK_DEFAULTS_KRYO_CLASSES_TO_REGISTER),
+            Some(&"org.example.Event,org.example.Session".to_string())
+        );
+    }
+
+    #[test]
+    fn java_serializer_keeps_the_spark_default() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              config:
+                serializer: java
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let spark_defaults = &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"]
+            [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert!(!spark_defaults.contains_key(SPARK_DEFAULTS_SERIALIZER));
+        assert!(!spark_defaults.contains_key(SPARK_DEFAULTS_KRYO_CLASSES_TO_REGISTER));
+    }
+
+    #[test]
+    fn node_pool_selector_of_the_rolegroup_takes_precedence() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  nodePoolSelector:
+                    cloudProvider: gke
+                    instanceType: n2
+                roleGroups:
+                  default:
+                    replicas: 1
+                  memory:
+                    replicas: 1
+                    config:
+                      nodePoolSelector:
+                        cloudProvider:
+                          custom: example.com/pool
+                        instanceType: highmem
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            pod_node_selector(&sc.node_config(&SparkRole::Worker, "default")),
+            Some(
+                vec![(LABEL_GKE_MACHINE_FAMILY.to_string(), "n2".to_string())]
+                    .into_iter()
+                    .collect()
+            )
+        );
+        assert_eq!(
+            pod_node_selector(&sc.node_config(&SparkRole::Worker, "memory")),
+            Some(
+                vec![("example.com/pool".to_string(), "highmem".to_string())]
+                    .into_iter()
+                    .collect()
+            )
+        );
+        assert_eq!(
+            pod_node_selector(&sc.node_config(&SparkRole::Master, "default")),
+            None
+        );
+    }
+
+    #[test]
+    fn statefulsets_replace_one_pod_at_a_time() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 3
+            ",
+        )
+        .unwrap();
+        let update_strategy = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .update_strategy
+        .unwrap();
+        assert_eq!(update_strategy.type_.as_deref(), Some("RollingUpdate"));
+        // No partition, every pod is replaced
+        assert_eq!(update_strategy.rolling_update, None);
+    }
+
+    #[test]
+    fn container_resources_are_merged_with_the_role() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  cpuRequest: 500m
+                  cpuLimit: '2'
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      memoryRequest: 4Gi
+                      memoryLimit: 8Gi
+            ",
+        )
+        .unwrap();
+        let resources = build_resources(&sc.node_config(&SparkRole::Worker, "default")).unwrap();
+        let quantities = |quantities: Option<BTreeMap<String, Quantity>>| {
+            quantities
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, quantity)| (name, quantity.0))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            quantities(resources.requests),
+            vec![
+                ("cpu".to_string(), "500m".to_string()),
+                ("memory".to_string(), "4Gi".to_string())
+            ]
+        );
+        assert_eq!(
+            quantities(resources.limits),
+            vec![
+                ("cpu".to_string(), "2".to_string()),
+                ("memory".to_string(), "8Gi".to_string())
+            ]
+        );
+        assert_eq!(
+            build_resources(&sc.node_config(&SparkRole::Master, "default")),
+            None
+        );
+    }
+
+    #[test]
+    fn image_pull_secrets_are_referenced_by_the_pods() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              imagePullSecrets:
+                - registry-credentials
+                - mirror-credentials
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Master.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        assert_eq!(
+            pod_spec
+                .image_pull_secrets
+                .unwrap()
+                .into_iter()
+                .map(|secret| secret.name.unwrap())
+                .collect::<Vec<_>>(),
+            vec!["registry-credentials", "mirror-credentials"]
+        );
+    }
+
+    #[test]
+    fn service_account_is_only_managed_if_none_is_given() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let service_account = build_service_account(&sc).unwrap().unwrap();
+        assert_eq!(
+            service_account.metadata.name.as_deref(),
+            Some("simple-spark")
+        );
+        assert_eq!(
+            service_account.metadata.owner_references.unwrap()[0].uid,
+            "1a2b3c"
+        );
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Master.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        assert_eq!(
+            pod_spec.service_account_name.as_deref(),
+            Some("simple-spark")
+        );
+
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              serviceAccountName: spark-driver
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(build_service_account(&sc).unwrap().is_none());
+        assert_eq!(sc.service_account_name().as_deref(), Some("spark-driver"));
+    }
+
+    #[test]
+    fn cluster_labels_are_forwarded_except_reserved_ones() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+              labels:
+                team: analytics
+                example.com/cost-center: '42'
+                app.kubernetes.io/name: spark
+                kubernetes.io/metadata.name: simple
+                node.k8s.io/pool: spark
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            forwarded_cluster_labels(&sc)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![
+                ("example.com/cost-center".to_string(), "42".to_string()),
+                ("team".to_string(), "analytics".to_string())
+            ]
+        );
+        let pod_labels = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Master.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .metadata
+        .unwrap()
+        .labels
+        .unwrap();
+        assert_eq!(pod_labels.get("team"), Some(&"analytics".to_string()));
+        assert_eq!(
+            pod_labels.get("app.kubernetes.io/component"),
+            Some(&SparkRole::Master.to_string())
+        );
+    }
+
+    #[test]
+    fn pod_annotations_cannot_replace_the_operator_annotations() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              podAnnotations:
+                prometheus.io/scrape: 'true'
+                kubectl.kubernetes.io/last-applied-configuration: '{}'
+                spark.stackable.tech/config-hash: forged
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let pod_annotations = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Master.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .metadata
+        .unwrap()
+        .annotations
+        .unwrap();
+        assert_eq!(
+            pod_annotations.get("prometheus.io/scrape"),
+            Some(&"true".to_string())
+        );
+        assert!(!pod_annotations.contains_key("kubectl.kubernetes.io/last-applied-configuration"));
+        assert_eq!(
+            pod_annotations.get(CONFIG_HASH_ANNOTATION),
+            Some(&"config".to_string())
+        );
+    }
+
+    #[test]
+    fn spark_defaults_point_to_the_master_unless_overridden() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  external:
+                    replicas: 1
+                    configOverrides:
+                      spark-defaults.conf:
+                        spark.master: spark://external-master:7077
+            ",
+        )
+        .unwrap();
+        let spark_defaults = |rolegroup: &str| {
+            build_rolegroup_config_map(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7078)],
+                &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), rolegroup),
+                &validated_config(&sc)[&SparkRole::Worker.to_string()][rolegroup],
+            )
+            .unwrap()
+            .data
+            .unwrap()
+            .remove(SPARK_DEFAULTS_CONF)
+            .unwrap()
+        };
+        assert!(
+            spark_defaults("default").contains("spark.master spark://simple-master-default:7078\n")
+        );
+        assert!(spark_defaults("external").contains("spark.master spark://external-master:7077\n"));
+    }
+
+    #[test]
+    fn env_exports_keep_special_characters_literal() {
+        let map = vec![
+            ("SPARK_NO_DAEMONIZE".to_string(), "true".to_string()),
+            (
+                "SPARK_WORKER_OPTS".to_string(),
+                "-Dprompt=\"$HOME\" -Dcmd=`id` -Dpath=C:\\spark".to_string(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            convert_map_to_env_exports(&map),
+            "export SPARK_NO_DAEMONIZE=\"true\"\n\
+            export SPARK_WORKER_OPTS=\"-Dprompt=\\\"\\$HOME\\\" -Dcmd=\\`id\\` -Dpath=C:\\\\spark\"\n"
+        );
+    }
+
+    #[test]
+    fn log4j_properties_follow_the_log_level_of_the_rolegroup() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  logLevel: warn
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let rolegroup = sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default");
+        let config_map = build_rolegroup_config_map(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &rolegroup,
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"],
+        )
+        .unwrap();
+        assert!(config_map.data.unwrap()[LOG4J_PROPERTIES]
+            .starts_with("log4j.rootCategory=WARN, console\n"));
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &rolegroup,
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        assert!(pod_spec.containers[0]
+            .env
+            .iter()
+            .flatten()
+            .find(|env| env.name == SPARK_DAEMON_JAVA_OPTS)
+            .and_then(|env| env.value.as_deref())
+            .unwrap()
+            .contains("-Dlog4j.configuration=file:"));
+        assert_eq!(
+            build_log4j_properties(&sc, &sc.node_config(&SparkRole::Master, "default")),
+            None
+        );
+    }
+
+    #[test]
+    fn custom_log4j_config_is_used_as_is() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              log4jConfig: log4j.rootCategory=INFO, file
+              workers:
+                config:
+                  logLevel: warn
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            build_log4j_properties(&sc, &sc.node_config(&SparkRole::Worker, "default")).as_deref(),
+            Some("log4j.rootCategory=INFO, file")
+        );
+    }
+
+    #[test]
+    fn env_from_secrets_is_added_to_the_spark_container() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  envFrom:
+                    - secretRef:
+                        name: spark-credentials
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      env:
+                        - name: DB_PASSWORD
+                          valueFrom:
+                            secretKeyRef:
+                              name: database
+                              key: password
+            ",
+        )
+        .unwrap();
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        let container = &pod_spec.containers[0];
+        assert_eq!(
+            container.env_from.as_ref().unwrap()[0]
+                .secret_ref
+                .as_ref()
+                .and_then(|secret| secret.name.as_deref()),
+            Some("spark-credentials")
+        );
+        let db_password = container
+            .env
+            .iter()
+            .flatten()
+            .find(|env| env.name == "DB_PASSWORD")
+            .unwrap();
+        assert_eq!(
+            db_password
+                .value_from
+                .as_ref()
+                .and_then(|value_from| value_from.secret_key_ref.as_ref())
+                .map(|secret_key| secret_key.key.as_str()),
+            Some("password")
+        );
+    }
+
+    #[test]
+    fn secret_volumes_are_mounted_under_generated_names() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              secretVolumes:
+                - secretName: spark.example.com-tls
+                  mountPath: /stackable/tls
+                - secretName: spark-keytab
+                  mountPath: /stackable/kerberos
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Master.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        let tls_volume = pod_spec
+            .volumes
+            .iter()
+            .flatten()
+            .find(|volume| volume.name == "secret-0")
+            .unwrap();
+        assert_eq!(
+            tls_volume
+                .secret
+                .as_ref()
+                .and_then(|secret| secret.secret_name.as_deref()),
+            Some("spark.example.com-tls")
+        );
+        let mounts = pod_spec.containers[0]
+            .volume_mounts
+            .iter()
+            .flatten()
+            .filter(|mount| mount.name.starts_with("secret-"))
+            .map(|mount| (mount.name.as_str(), mount.mount_path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            mounts,
+            vec![
+                ("secret-0", "/stackable/tls"),
+                ("secret-1", "/stackable/kerberos")
+            ]
+        );
+    }
+
+    #[test]
+    fn node_pool_label_takes_precedence_over_the_node_selector() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  nodeSelector:
+                    disktype: ssd
+                    node.kubernetes.io/instance-type: m5.large
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      nodePoolSelector:
+                        cloudProvider: eks
+                        instanceType: r5.xlarge
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            pod_node_selector(&sc.node_config(&SparkRole::Worker, "default"))
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![
+                ("disktype".to_string(), "ssd".to_string()),
+                (LABEL_INSTANCE_TYPE.to_string(), "r5.xlarge".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn workers_are_spread_across_nodes_unless_disabled() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              workerSpread:
+                weight: 50
+            ",
+        )
+        .unwrap();
+        let term = &worker_anti_affinity(&sc)
+            .unwrap()
+            .preferred_during_scheduling_ignored_during_execution
+            .unwrap()[0];
+        assert_eq!(term.weight, 50);
+        assert_eq!(term.pod_affinity_term.topology_key, LABEL_HOSTNAME);
+        let match_labels = term
+            .pod_affinity_term
+            .label_selector
+            .as_ref()
+            .and_then(|selector| selector.match_labels.as_ref())
+            .unwrap();
+        assert_eq!(
+            match_labels.get("app.kubernetes.io/component"),
+            Some(&SparkRole::Worker.to_string())
+        );
+        assert!(build_affinity(&sc, &SparkRole::Master).is_none());
+
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              workerSpread:
+                enabled: false
+            ",
+        )
+        .unwrap();
+        assert!(build_affinity(&sc, &SparkRole::Worker).is_none());
+    }
+
+    #[test]
+    fn topology_spread_constraints_select_the_role_by_default() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  topologySpreadConstraints:
+                    - maxSkew: 1
+                      topologyKey: topology.kubernetes.io/zone
+                      whenUnsatisfiable: ScheduleAnyway
+                    - maxSkew: 2
+                      topologyKey: topology.kubernetes.io/zone
+                      whenUnsatisfiable: DoNotSchedule
+                      labelSelector:
+                        matchLabels:
+                          tier: spark
+                roleGroups:
+                  default:
+                    replicas: 3
+            ",
+        )
+        .unwrap();
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        let match_labels = pod_spec
+            .topology_spread_constraints
+            .unwrap()
+            .into_iter()
+            .map(|constraint| constraint.label_selector.unwrap().match_labels.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            match_labels[0],
+            role_selector_labels(&sc, APP_NAME, &SparkRole::Worker.to_string())
+        );
+        assert_eq!(
+            match_labels[1].clone().into_iter().collect::<Vec<_>>(),
+            vec![("tier".to_string(), "spark".to_string())]
+        );
+    }
+
+    #[test]
+    fn priority_class_of_the_rolegroup_overrides_the_cluster() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              priorityClassName: spark
+              masters:
+                config:
+                  priorityClassName: system-cluster-critical
+                roleGroups:
+                  default:
+                    replicas: 1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let priority_class_name = |role: SparkRole| {
+            build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(role.to_string(), "default"),
+                &validated_config(&sc)[&role.to_string()]["default"],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap()
+            .priority_class_name
+        };
+        assert_eq!(
+            priority_class_name(SparkRole::Master).as_deref(),
+            Some("system-cluster-critical")
+        );
+        assert_eq!(
+            priority_class_name(SparkRole::Worker).as_deref(),
+            Some("spark")
+        );
+    }
+
+    #[test]
+    fn init_containers_run_before_the_spark_container() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  initContainers:
+                    - name: fix-permissions
+                      image: busybox
+                      command: [chown, -R, '1000:1000', /stackable/data]
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        let init_containers = pod_spec.init_containers.unwrap();
+        assert_eq!(init_containers.len(), 1);
+        assert_eq!(init_containers[0].name, "fix-permissions");
+        assert_eq!(pod_spec.containers.len(), 1);
+    }
+
+    #[test]
+    fn sidecars_are_added_after_the_spark_container() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      sidecarContainers:
+                        - name: log-shipper
+                          image: fluent/fluent-bit
+            ",
+        )
+        .unwrap();
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Master.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        assert_eq!(
+            pod_spec
+                .containers
+                .iter()
+                .map(|container| container.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["spark", "log-shipper"]
+        );
+    }
+
+    #[test]
+    fn first_failure_is_requeued_after_the_configured_delay() {
+        assert_eq!(
+            error_requeue_delay(Duration::from_secs(12), 1),
+            Duration::from_secs(12)
+        );
+        assert_eq!(
+            error_requeue_delay(DEFAULT_ERROR_REQUEUE, 1),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn repeated_failures_double_the_requeue_delay_up_to_the_maximum() {
+        let delays = (1..=4)
+            .map(|failures| error_requeue_delay(Duration::from_secs(5), failures))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_secs(5),
+                Duration::from_secs(10),
+                Duration::from_secs(20),
+                Duration::from_secs(40),
+            ]
+        );
+        assert_eq!(
+            error_requeue_delay(Duration::from_secs(5), 10),
+            MAX_ERROR_REQUEUE
+        );
+        // The exponent is capped, so many failures do not overflow
+        assert_eq!(
+            error_requeue_delay(Duration::from_secs(5), u32::MAX),
+            MAX_ERROR_REQUEUE
+        );
+    }
+
+    #[test]
+    fn jvm_opts_are_written_to_the_daemon_opts_of_each_role() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              masters:
+                config:
+                  jvmOpts: -XX:+UseG1GC
+                roleGroups:
+                  default:
+                    replicas: 1
+                  large:
+                    replicas: 1
+                    config:
+                      jvmOpts: -Xss4m
+              historyServers:
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      jvmOpts: -XX:+UseParallelGC
+            ",
+        )
+        .unwrap();
+        let validated_config = validated_config(&sc);
+        let spark_env = |role: SparkRole, rolegroup: &str| {
+            validated_config[&role.to_string()][rolegroup]
+                [&PropertyNameKind::File(SPARK_ENV_SH.to_string())]
+                .clone()
+        };
+        assert_eq!(
+            spark_env(SparkRole::Master, "default").get(SPARK_ENV_MASTER_OPTS),
+            Some(&"-XX:+UseG1GC".to_string())
+        );
+        assert_eq!(
+            spark_env(SparkRole::Master, "large").get(SPARK_ENV_MASTER_OPTS),
+            Some(&"-Xss4m".to_string())
+        );
+        assert_eq!(
+            spark_env(SparkRole::HistoryServer, "default").get(SPARK_ENV_HISTORY_OPTS),
+            Some(&"-XX:+UseParallelGC".to_string())
+        );
+    }
+
+    #[test]
+    fn file_log_dirs_are_mounted_at_their_path() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              config:
+                logDir: file:///stackable/data/events
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+              historyServers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let validated_config = validated_config(&sc);
+        for role in [SparkRole::Worker, SparkRole::HistoryServer].iter() {
+            let rolegroup_config = &validated_config[&role.to_string()]["default"];
+            assert_eq!(
+                spark_log_dir(rolegroup_config).as_deref(),
+                Some("/stackable/data/events")
+            );
+            let pod_spec = build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(role.to_string(), "default"),
+                rolegroup_config,
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap();
+            assert!(pod_spec.containers[0]
+                .volume_mounts
+                .iter()
+                .flatten()
+                .any(|mount| mount.mount_path == "/stackable/data/events"));
+        }
+    }
+
+    #[test]
+    fn s3_event_logs_get_the_endpoint_and_credentials() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              config:
+                logDir: s3://spark-logs/events
+                s3:
+                  endpoint: http://minio:9000
+                  credentialsSecret: minio-credentials
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let rolegroup_config = &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"];
+        let spark_defaults =
+            &rolegroup_config[&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert_eq!(
+            spark_defaults.get(SPARK_DEFAULTS_S3A_ENDPOINT),
+            Some(&"http://minio:9000".to_string())
+        );
+        assert_eq!(
+            spark_defaults.get(SPARK_DEFAULTS_S3_IMPL),
+            Some(&S3A_FILE_SYSTEM_CLASS.to_string())
+        );
+        assert_eq!(spark_log_dir(rolegroup_config), None);
+
+        let statefulset = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+            rolegroup_config,
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap();
+        // No event log volume for S3
+        assert_eq!(statefulset.volume_claim_templates, None);
+        let credentials = statefulset.template.spec.unwrap().containers[0]
+            .env
+            .iter()
+            .flatten()
+            .filter_map(|env| {
+                let secret_key = env.value_from.as_ref()?.secret_key_ref.as_ref()?;
+                Some((
+                    env.name.clone(),
+                    secret_key.name.clone()?,
+                    secret_key.key.clone(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            credentials,
+            vec![
+                (
+                    AWS_ACCESS_KEY_ID.to_string(),
+                    "minio-credentials".to_string(),
+                    S3_ACCESS_KEY.to_string()
+                ),
+                (
+                    AWS_SECRET_ACCESS_KEY.to_string(),
+                    "minio-credentials".to_string(),
+                    S3_SECRET_KEY.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn s3_event_logs_need_credentials() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              config:
+                logDir: s3a://spark-logs/events
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            validate_spec(&sc),
+            Err(Error::InvalidEventLogConfig { log_dir }) if log_dir == "s3a://spark-logs/events"
+        ));
+    }
+
+    #[test]
+    fn hdfs_event_logs_use_the_namenode_and_core_site_of_the_url() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              config:
+                logDir: hdfs://namenode:8020/spark-logs
+              hdfsConfigMap: hdfs-config
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let rolegroup_config = &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"];
+        assert_eq!(
+            rolegroup_config[&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())]
+                .get(SPARK_DEFAULTS_HADOOP_DEFAULT_FS),
+            Some(&"hdfs://namenode:8020".to_string())
+        );
+        assert_eq!(spark_log_dir(rolegroup_config), None);
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Worker.to_string(), "default"),
+            rolegroup_config,
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        let core_site = pod_spec.containers[0]
+            .volume_mounts
+            .iter()
+            .flatten()
+            .find(|mount| mount.name == "hdfs-config")
+            .unwrap();
+        assert_eq!(
+            core_site.mount_path,
+            format!("{}/{}", spark_conf_dir(rolegroup_config), HDFS_CORE_SITE)
+        );
+        assert_eq!(core_site.sub_path.as_deref(), Some(HDFS_CORE_SITE));
+    }
+
+    #[test]
+    fn hdfs_urls_without_namenode_keep_the_default_fs_of_the_core_site() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              config:
+                logDir: hdfs:///spark-logs
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(
+            !validated_config(&sc)[&SparkRole::Worker.to_string()]["default"]
+                [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())]
+                .contains_key(SPARK_DEFAULTS_HADOOP_DEFAULT_FS)
+        );
+    }
+
+    #[test]
+    fn ha_master_url_lists_every_master_pod() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              haConfig:
+                zookeeperUrl: zk-0.zk:2181,zk-1.zk:2181
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 2
+                  backup:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            build_ha_master_url(&sc, &[(PORT_NAME_SPARK.to_string(), 7078)]).as_deref(),
+            Some(
+                "spark://simple-master-backup-0.simple-master-backup.default.svc.cluster.local:7078,\
+                simple-master-default-0.simple-master-default.default.svc.cluster.local:7078,\
+                simple-master-default-1.simple-master-default.default.svc.cluster.local:7078"
+            )
+        );
+        let spark_defaults = &validated_config(&sc)[&SparkRole::Master.to_string()]["default"]
+            [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert_eq!(
+            spark_defaults.get(SPARK_DEFAULTS_DEPLOY_RECOVERY_MODE),
+            Some(&"ZOOKEEPER".to_string())
+        );
+        assert_eq!(
+            spark_defaults.get(SPARK_DEFAULTS_DEPLOY_ZOOKEEPER_URL),
+            Some(&"zk-0.zk:2181,zk-1.zk:2181".to_string())
+        );
+        assert!(validate_spec(&sc).is_ok());
+    }
+
+    #[test]
+    fn ha_needs_at_least_two_masters() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              haConfig:
+                zookeeperUrl: zk-0.zk:2181
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            validate_spec(&sc),
+            Err(Error::MinimumMasterCountForHA {
+                count: 1,
+                minimum: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn clusters_need_a_master_unless_they_are_deleted() {
+        let mut sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 0
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 2
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            validate_spec(&sc),
+            Err(Error::InvalidMasterInstanceCount { .. })
+        ));
+        sc.metadata.deletion_timestamp = Some(Time(Utc::now()));
+        assert!(validate_spec(&sc).is_ok());
+    }
+
+    #[test]
+    fn pod_template_override_is_merged_by_name_and_keeps_the_labels() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      podTemplateOverride:
+                        metadata:
+                          labels:
+                            app.kubernetes.io/instance: other
+                        spec:
+                          containers:
+                            - name: spark
+                              image: spark-debug
+                            - name: debug
+                              image: busybox
+            ",
+        )
+        .unwrap();
+        let template = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+            &validated_config(&sc)[&SparkRole::Master.to_string()]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template;
+        assert_eq!(
+            template.metadata.unwrap().labels.unwrap()["app.kubernetes.io/instance"],
+            "simple"
+        );
+        let containers = template.spec.unwrap().containers;
+        assert_eq!(
+            containers
+                .iter()
+                .map(|container| (container.name.as_str(), container.image.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![("spark", Some("spark-debug")), ("debug", Some("busybox"))]
+        );
+        // The generated fields of the overridden container are kept
+        assert!(containers[0].args.is_some());
+    }
+
+    #[test]
+    fn termination_grace_period_defaults_depend_on_the_role() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  fast:
+                    replicas: 1
+                    config:
+                      terminationGracePeriodSeconds: 10
+              historyServers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let grace_period = |role: SparkRole, group: &str| {
+            build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(role.to_string(), group),
+                &validated_config(&sc)[&role.to_string()][group],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap()
+            .termination_grace_period_seconds
+        };
+        assert_eq!(
+            grace_period(SparkRole::Master, "default"),
+            Some(DEFAULT_MASTER_TERMINATION_GRACE_PERIOD_SECONDS)
+        );
+        assert_eq!(
+            grace_period(SparkRole::Worker, "default"),
+            Some(DEFAULT_WORKER_TERMINATION_GRACE_PERIOD_SECONDS)
+        );
+        assert_eq!(grace_period(SparkRole::Worker, "fast"), Some(10));
+        assert_eq!(grace_period(SparkRole::HistoryServer, "default"), None);
+    }
+
+    #[test]
+    fn workers_deregister_in_a_pre_stop_hook_by_default() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      preStopCommand:
+                        - sleep
+                        - '5'
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+              historyServers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let pre_stop_command = |role: SparkRole| {
+            let pod_spec = build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(role.to_string(), "default"),
+                &validated_config(&sc)[&role.to_string()]["default"],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap();
+            pod_spec.containers[0]
+                .lifecycle
+                .clone()
+                .and_then(|lifecycle| lifecycle.pre_stop)
+                .and_then(|handler| handler.exec)
+                .and_then(|exec| exec.command)
+        };
+        assert_eq!(
+            pre_stop_command(SparkRole::Worker),
+            Some(vec!["sbin/stop-slave.sh".to_string()])
+        );
+        assert_eq!(
+            pre_stop_command(SparkRole::Master),
+            Some(vec!["sleep".to_string(), "5".to_string()])
+        );
+        assert_eq!(pre_stop_command(SparkRole::HistoryServer), None);
+    }
+
+    #[test]
+    fn previous_version_is_reported_until_the_upgrade_is_rolled_out() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.1.1
+            status:
+              version: 3.0.1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            version_upgraded_from(&sc, true, true).as_deref(),
+            Some("3.0.1")
+        );
+        assert_eq!(
+            version_upgraded_from(&sc, false, false).as_deref(),
+            Some("3.0.1")
+        );
+        assert_eq!(version_upgraded_from(&sc, false, true), None);
+
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+            ",
+        )
+        .unwrap();
+        // A new cluster is not upgraded
+        assert_eq!(version_upgraded_from(&sc, true, false), None);
+    }
+
+    #[test]
+    fn master_ui_ingress_routes_the_host_to_the_web_port() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masterUiIngress:
+                host: spark.example.com
+                tlsSecret: spark-tls
+                ingressClassName: nginx
+            ",
+        )
+        .unwrap();
+        let ingress = build_ui_ingress(
+            &sc,
+            &SparkRole::Master,
+            "simple-master-ui",
+            sc.spec.master_ui_ingress.as_ref().unwrap(),
+            "simple-master",
+            false,
+        )
+        .unwrap();
+        let spec = ingress.spec.unwrap();
+        assert_eq!(spec.ingress_class_name.as_deref(), Some("nginx"));
+        let rule = &spec.rules.unwrap()[0];
+        assert_eq!(rule.host.as_deref(), Some("spark.example.com"));
+        let path = &rule.http.as_ref().unwrap().paths[0];
+        assert_eq!(path.path.as_deref(), Some("/"));
+        assert_eq!(path.path_type.as_deref(), Some("Prefix"));
+        let service = path.backend.service.as_ref().unwrap();
+        assert_eq!(service.name, "simple-master");
+        assert_eq!(
+            service.port.as_ref().unwrap().name.as_deref(),
+            Some(PORT_NAME_WEB)
+        );
+        let tls = &spec.tls.unwrap()[0];
+        assert_eq!(tls.secret_name.as_deref(), Some("spark-tls"));
+        assert_eq!(tls.hosts, Some(vec!["spark.example.com".to_string()]));
+    }
+
+    #[test]
+    fn history_server_ingress_strips_the_path_prefix_behind_the_reverse_proxy() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              historyServerIngress:
+                host: spark.example.com
+                pathPrefix: /history/
+            ",
+        )
+        .unwrap();
+        let ingress_config = sc.spec.history_server_ingress.as_ref().unwrap();
+        let path = |rewrite_path: bool| {
+            let ingress = build_ui_ingress(
+                &sc,
+                &SparkRole::HistoryServer,
+                "simple-history-ui",
+                ingress_config,
+                "simple-history-server",
+                rewrite_path,
+            )
+            .unwrap();
+            let path = ingress.spec.unwrap().rules.unwrap()[0]
+                .http
+                .as_ref()
+                .unwrap()
+                .paths[0]
+                .clone();
+            (
+                path.path.unwrap(),
+                path.path_type.unwrap(),
+                ingress
+                    .metadata
+                    .annotations
+                    .unwrap_or_default()
+                    .get(ANNOTATION_NGINX_REWRITE_TARGET)
+                    .cloned(),
+            )
+        };
+        assert_eq!(
+            path(true),
+            (
+                "/history(/|$)(.*)".to_string(),
+                "ImplementationSpecific".to_string(),
+                Some("/$2".to_string())
+            )
+        );
+        assert_eq!(
+            path(false),
+            ("/history".to_string(), "Prefix".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn network_policy_only_admits_the_cluster_clients_and_the_ingress_controller() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              enableNetworkPolicy: true
+              ingressControllerNamespace: nginx
+            ",
+        )
+        .unwrap();
+        let spec = build_network_policy(&sc, None).unwrap().spec.unwrap();
+        assert_eq!(spec.policy_types, Some(vec!["Ingress".to_string()]));
+        let match_labels = |selector: &Option<LabelSelector>| {
+            selector
+                .as_ref()
+                .and_then(|selector| selector.match_labels.clone())
+                .unwrap_or_default()
+        };
+        let port_names = |rule: &NetworkPolicyIngressRule| {
+            rule.ports
+                .iter()
+                .flatten()
+                .filter_map(|port| match &port.port {
+                    Some(IntOrString::String(name)) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let cluster_labels = spec.pod_selector.match_labels.unwrap();
+        assert_eq!(cluster_labels["app.kubernetes.io/instance"], "simple");
+        let rules = spec.ingress.unwrap();
+        assert_eq!(rules.len(), 3);
+
+        // All ports between the pods of the cluster
+        let peer = &rules[0].from.as_ref().unwrap()[0];
+        assert_eq!(match_labels(&peer.pod_selector), cluster_labels);
+        assert!(rules[0].ports.is_none());
+
+        // The master port for clients
+        let peer = &rules[1].from.as_ref().unwrap()[0];
+        assert_eq!(match_labels(&peer.pod_selector)[SPARK_CLIENT_LABEL], "true");
+        assert!(port_names(&rules[1]).contains(&PORT_NAME_SPARK.to_string()));
+
+        // The web UIs for the ingress controller
+        let peer = &rules[2].from.as_ref().unwrap()[0];
+        assert_eq!(
+            match_labels(&peer.namespace_selector)[LABEL_NAMESPACE_NAME],
+            "nginx"
+        );
+        assert_eq!(port_names(&rules[2]), vec![PORT_NAME_WEB.to_string()]);
+    }
+
+    #[test]
+    fn authentication_secret_is_read_from_the_secret_and_cannot_be_overridden() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              authentication:
+                secretRef: spark-auth
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  other:
+                    replicas: 1
+                    configOverrides:
+                      spark-defaults.conf:
+                        spark.authenticate.secret: plain
+            ",
+        )
+        .unwrap();
+        let validated_config = validated_config(&sc);
+        let role = SparkRole::Master.to_string();
+        let default_config = &validated_config[&role]["default"];
+        let spark_defaults =
+            &default_config[&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert_eq!(spark_defaults[SPARK_DEFAULTS_AUTHENTICATE], "true");
+        assert_eq!(
+            spark_defaults[SPARK_DEFAULTS_AUTHENTICATE_SECRET],
+            format!("${{env:{}}}", SPARK_AUTHENTICATE_SECRET_ENV)
+        );
+        assert!(validate_authentication(
+            &sc,
+            &sc.server_rolegroup_ref(&role, "default"),
+            default_config
+        )
+        .is_ok());
+        assert!(matches!(
+            validate_authentication(
+                &sc,
+                &sc.server_rolegroup_ref(&role, "other"),
+                &validated_config[&role]["other"]
+            ),
+            Err(Error::InconsistentAuthenticationSecret { .. })
+        ));
+
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(&role, "default"),
+            default_config,
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        let secret_key_ref = pod_spec.containers[0]
+            .env
+            .iter()
+            .flatten()
+            .find(|env| env.name == SPARK_AUTHENTICATE_SECRET_ENV)
+            .and_then(|env| env.value_from.as_ref())
+            .and_then(|value_from| value_from.secret_key_ref.clone())
+            .unwrap();
+        assert_eq!(secret_key_ref.name.as_deref(), Some("spark-auth"));
+        assert_eq!(secret_key_ref.key, SPARK_DEFAULTS_AUTHENTICATE_SECRET);
+    }
+
+    #[test]
+    fn runtime_class_of_the_rolegroup_overrides_the_role() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  runtimeClassName: gvisor
+                roleGroups:
+                  default:
+                    replicas: 1
+                  isolated:
+                    replicas: 1
+                    config:
+                      runtimeClassName: kata
+            ",
+        )
+        .unwrap();
+        let role = SparkRole::Worker.to_string();
+        let runtime_class_name = |group: &str| {
+            build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(&role, group),
+                &validated_config(&sc)[&role][group],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap()
+            .runtime_class_name
+        };
+        assert_eq!(runtime_class_name("default").as_deref(), Some("gvisor"));
+        assert_eq!(runtime_class_name("isolated").as_deref(), Some("kata"));
+    }
+
+    #[test]
+    fn required_node_affinity_applies_to_all_roles() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              requiredNodeAffinity:
+                - key: kubernetes.io/arch
+                  operator: In
+                  values:
+                    - amd64
+            ",
+        )
+        .unwrap();
+        for role in [
+            SparkRole::Master,
+            SparkRole::Worker,
+            SparkRole::HistoryServer,
+        ]
+        .iter()
+        {
+            let affinity = build_affinity(&sc, role).unwrap();
+            let terms = affinity
+                .node_affinity
+                .and_then(|node_affinity| {
+                    node_affinity.required_during_scheduling_ignored_during_execution
+                })
+                .unwrap()
+                .node_selector_terms;
+            assert_eq!(terms.len(), 1);
+            let requirement = &terms[0].match_expressions.as_ref().unwrap()[0];
+            assert_eq!(requirement.key, "kubernetes.io/arch");
+            assert_eq!(requirement.operator, "In");
+            assert_eq!(requirement.values, Some(vec!["amd64".to_string()]));
+            // Workers are still spread across the nodes
+            assert_eq!(
+                affinity.pod_anti_affinity.is_some(),
+                *role == SparkRole::Worker
+            );
+        }
+    }
+
+    #[test]
+    fn dns_policy_defaults_to_cluster_first_with_host_net_for_the_host_network() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  host:
+                    replicas: 1
+                    config:
+                      hostNetwork: true
+                  custom:
+                    replicas: 1
+                    config:
+                      hostNetwork: true
+                      dnsPolicy: None
+                      dnsConfig:
+                        nameservers:
+                          - 10.0.0.10
+                        options:
+                          - name: ndots
+                            value: '2'
+            ",
+        )
+        .unwrap();
+        let role = SparkRole::Worker.to_string();
+        let pod_spec = |group: &str| {
+            build_rolegroup_statefulset(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(&role, group),
+                &validated_config(&sc)[&role][group],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+            .spec
+            .unwrap()
+            .template
+            .spec
+            .unwrap()
+        };
+        let default = pod_spec("default");
+        assert_eq!(default.dns_policy, None);
+        assert_eq!(default.dns_config, None);
+        assert_eq!(
+            pod_spec("host").dns_policy.as_deref(),
+            Some("ClusterFirstWithHostNet")
+        );
+        let custom = pod_spec("custom");
+        assert_eq!(custom.dns_policy.as_deref(), Some("None"));
+        let dns_config = custom.dns_config.unwrap();
+        assert_eq!(dns_config.nameservers, Some(vec!["10.0.0.10".to_string()]));
+        let option = &dns_config.options.unwrap()[0];
+        assert_eq!(option.name.as_deref(), Some("ndots"));
+        assert_eq!(option.value.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn masters_declare_the_rest_submission_port() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+                  custom:
+                    replicas: 1
+                    config:
+                      masterRestPort: 16066
+            ",
+        )
+        .unwrap();
+        let validated_config = validated_config(&sc);
+        let role = SparkRole::Master.to_string();
+        let rest_port = |group: &str| {
+            build_ports(
+                &sc,
+                &sc.server_rolegroup_ref(&role, group),
+                &validated_config[&role][group],
+            )
+            .unwrap()
+            .into_iter()
+            .find(|(name, _port)| name == PORT_NAME_REST)
+            .map(|(_name, port)| port)
+        };
+        assert_eq!(rest_port("default"), Some(6066));
+        assert_eq!(rest_port("custom"), Some(16066));
+    }
+
+    #[test]
+    fn executor_defaults_of_the_rolegroup_conflict_with_config_overrides() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  executorCores: 2
+                roleGroups:
+                  default:
+                    replicas: 1
+                    config:
+                      executorMemory: 4g
+                  conflicting:
+                    replicas: 1
+                    configOverrides:
+                      spark-defaults.conf:
+                        spark.executor.cores: '4'
+            ",
+        )
+        .unwrap();
+        let role = SparkRole::Worker.to_string();
+        let config_map = |group: &str| {
+            build_rolegroup_config_map(
+                &sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(&role, group),
+                &validated_config(&sc)[&role][group],
+            )
+        };
+        let spark_defaults = config_map("default")
+            .unwrap()
+            .data
+            .unwrap()
+            .remove(SPARK_DEFAULTS_CONF)
+            .unwrap();
+        assert!(spark_defaults.contains("spark.executor.cores 2\n"));
+        assert!(spark_defaults.contains("spark.executor.memory 4g\n"));
+        assert!(matches!(
+            config_map("conflicting"),
+            Err(Error::ConflictingExecutorConfig { property, .. })
+                if property == SPARK_DEFAULTS_EXECUTOR_CORES
+        ));
+    }
+
+    #[test]
+    fn sql_extensions_catalogs_and_jars_are_added_to_the_spark_defaults() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              sqlExtensions:
+                - io.delta.sql.DeltaSparkSessionExtension
+                - org.apache.iceberg.spark.extensions.IcebergSparkSessionExtensions
+              catalogs:
+                iceberg:
+                  class: org.apache.iceberg.spark.SparkCatalog
+                  options:
+                    type: hadoop
+                    warehouse: s3a://warehouse/
+              extraJars:
+                - /stackable/jars/delta-core.jar
+                - /stackable/jars/iceberg-spark-runtime.jar
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let spark_defaults = &validated_config(&sc)[&SparkRole::Worker.to_string()]["default"]
+            [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert_eq!(
+            spark_defaults[SPARK_DEFAULTS_SQL_EXTENSIONS],
+            "io.delta.sql.DeltaSparkSessionExtension,\
+            org.apache.iceberg.spark.extensions.IcebergSparkSessionExtensions"
+        );
+        assert_eq!(
+            spark_defaults["spark.sql.catalog.iceberg"],
+            "org.apache.iceberg.spark.SparkCatalog"
+        );
+        assert_eq!(spark_defaults["spark.sql.catalog.iceberg.type"], "hadoop");
+        assert_eq!(
+            spark_defaults["spark.sql.catalog.iceberg.warehouse"],
+            "s3a://warehouse/"
+        );
+        assert_eq!(
+            spark_defaults[SPARK_DEFAULTS_JARS],
+            "/stackable/jars/delta-core.jar,/stackable/jars/iceberg-spark-runtime.jar"
+        );
+    }
+
+    #[test]
+    fn event_logs_are_compressed_with_zstd_by_default() {
+        let mut sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let compression = |sc: &SparkCluster| {
+            let spark_defaults = &validated_config(sc)[&SparkRole::Worker.to_string()]["default"]
+                [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+            (
+                spark_defaults
+                    .get(SPARK_DEFAULTS_EVENT_LOG_COMPRESS)
+                    .cloned(),
+                spark_defaults
+                    .get(SPARK_DEFAULTS_EVENT_LOG_COMPRESSION_CODEC)
+                    .cloned(),
+            )
+        };
+        assert_eq!(
+            compression(&sc),
+            (Some("true".to_string()), Some("zstd".to_string()))
+        );
+        sc.spec.event_log_codec = Some("lz4".to_string());
+        assert_eq!(
+            compression(&sc),
+            (Some("true".to_string()), Some("lz4".to_string()))
+        );
+        sc.spec.event_log_compress = Some(false);
+        assert_eq!(compression(&sc), (None, None));
+    }
+
+    #[test]
+    fn hive_site_is_generated_from_the_metastore_uris_or_mounted_from_the_config_map() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              hiveMetastoreUris:
+                - thrift://hive-0:9083
+                - thrift://hive-1:9083
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let role = SparkRole::Worker.to_string();
+        let rolegroup = sc.server_rolegroup_ref(&role, "default");
+        let validated_config = validated_config(&sc);
+        let rolegroup_config = &validated_config[&role]["default"];
+        let spark_defaults =
+            &rolegroup_config[&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())];
+        assert_eq!(
+            spark_defaults[SPARK_DEFAULTS_SQL_CATALOG_IMPLEMENTATION],
+            "hive"
+        );
+        assert_eq!(
+            spark_defaults[SPARK_DEFAULTS_HIVE_METASTORE_URIS],
+            "thrift://hive-0:9083,thrift://hive-1:9083"
+        );
+        let data = build_rolegroup_config_map(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &rolegroup,
+            rolegroup_config,
+        )
+        .unwrap()
+        .data
+        .unwrap();
+        assert!(data[HIVE_SITE_XML].contains(
+            "<name>hive.metastore.uris</name>\n    \
+            <value>thrift://hive-0:9083,thrift://hive-1:9083</value>"
+        ));
+
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              hiveMetastoreUris:
+                - thrift://hive-0:9083
+              hiveConfigMapRef: hive-site
+              workers:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let validated_config = validated_config(&sc);
+        let rolegroup_config = &validated_config[&role]["default"];
+        let data = build_rolegroup_config_map(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &rolegroup,
+            rolegroup_config,
+        )
+        .unwrap()
+        .data
+        .unwrap();
+        assert!(!data.contains_key(HIVE_SITE_XML));
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &rolegroup,
+            rolegroup_config,
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        let volume = pod_spec
+            .volumes
+            .iter()
+            .flatten()
+            .find(|volume| volume.name == "hive-config")
+            .unwrap();
+        assert_eq!(
+            volume.config_map.as_ref().unwrap().name.as_deref(),
+            Some("hive-site")
+        );
+        let mount = pod_spec.containers[0]
+            .volume_mounts
+            .iter()
+            .flatten()
+            .find(|mount| mount.name == "hive-config")
+            .unwrap();
+        assert!(mount.mount_path.ends_with("/hive-site.xml"));
+        assert_eq!(mount.sub_path.as_deref(), Some(HIVE_SITE_XML));
+    }
+
+    #[test]
+    fn aws_role_is_annotated_on_the_service_account_and_replaces_the_s3_keys() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              serviceAccountName: spark-driver
+              awsRoleArn: arn:aws:iam::123456789012:role/spark
+              awsRegion: eu-central-1
+              config:
+                logDir: s3a://spark-logs/events
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(validate_spec(&sc).is_ok());
+        // The given ServiceAccount is only annotated, it is not owned by the cluster
+        let service_account = build_service_account(&sc).unwrap().unwrap();
+        assert_eq!(
+            service_account.metadata.name.as_deref(),
+            Some("spark-driver")
+        );
+        assert!(service_account.metadata.owner_references.is_none());
+        assert_eq!(
+            service_account.metadata.annotations.unwrap()[EKS_ROLE_ARN_ANNOTATION],
+            "arn:aws:iam::123456789012:role/spark"
+        );
+
+        let role = SparkRole::Master.to_string();
+        let validated_config = validated_config(&sc);
+        let rolegroup_config = &validated_config[&role]["default"];
+        assert_eq!(
+            rolegroup_config[&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())]
+                [SPARK_DEFAULTS_S3A_CREDENTIALS_PROVIDER],
+            WEB_IDENTITY_CREDENTIALS_PROVIDER_CLASS
+        );
+        let pod_spec = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(&role, "default"),
+            rolegroup_config,
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .spec
+        .unwrap();
+        assert!(pod_spec.containers[0]
+            .env
+            .iter()
+            .flatten()
+            .any(|env| env.name == AWS_REGION && env.value.as_deref() == Some("eu-central-1")));
+    }
+
+    #[test]
+    fn gcp_service_account_is_annotated_and_needs_a_gcs_bucket() {
+        let mut sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              gcpServiceAccount: spark@my-project.iam.gserviceaccount.com
+              config:
+                logDir: gs://spark-logs/events
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        assert!(validate_spec(&sc).is_ok());
+        let service_account = build_service_account(&sc).unwrap().unwrap();
+        assert_eq!(
+            service_account.metadata.annotations.unwrap()[GKE_SERVICE_ACCOUNT_ANNOTATION],
+            "spark@my-project.iam.gserviceaccount.com"
+        );
+        assert_eq!(
+            validated_config(&sc)[&SparkRole::Master.to_string()]["default"]
+                [&PropertyNameKind::File(SPARK_DEFAULTS_CONF.to_string())]
+                [SPARK_DEFAULTS_GOOGLE_CLOUD_AUTH_TYPE],
+            "APPLICATION_DEFAULT"
+        );
+
+        sc.spec
+            .config
+            .as_mut()
+            .and_then(|common_configuration| common_configuration.config.as_mut())
+            .unwrap()
+            .log_dir = Some("gs:///events".to_string());
+        assert!(matches!(
+            validate_spec(&sc),
+            Err(Error::InvalidGcsEventLogDir { log_dir }) if log_dir == "gs:///events"
+        ));
+    }
+
+    #[test]
+    fn vault_secrets_are_annotated_by_the_file_name_of_their_mount_path() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              workers:
+                config:
+                  vaultRole: spark
+                  vaultSecrets:
+                    - path: database/creds/spark
+                      mountPath: /stackable/secrets/db-creds
+                roleGroups:
+                  default:
+                    replicas: 1
+            ",
+        )
+        .unwrap();
+        let role = SparkRole::Worker.to_string();
+        let annotations = build_rolegroup_statefulset(
+            &sc,
+            &[(PORT_NAME_SPARK.to_string(), 7077)],
+            &sc.server_rolegroup_ref(&role, "default"),
+            &validated_config(&sc)[&role]["default"],
+            "secrets",
+            "config",
+        )
+        .unwrap()
+        .spec
+        .unwrap()
+        .template
+        .metadata
+        .and_then(|metadata| metadata.annotations)
+        .unwrap();
+        assert_eq!(annotations[VAULT_AGENT_INJECT_ANNOTATION], "true");
+        assert_eq!(annotations[VAULT_ROLE_ANNOTATION], "spark");
+        assert_eq!(
+            annotations["vault.hashicorp.com/agent-inject-secret-db-creds"],
+            "database/creds/spark"
+        );
+        assert_eq!(
+            annotations["vault.hashicorp.com/secret-volume-path-db-creds"],
+            "/stackable/secrets"
+        );
+
+        assert!(vault_annotations(&NodeConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn logging_fixes_are_applied_to_pending_clusters() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+            spec:
+              version: 3.0.1
+              log4jConfig: |
+                log4j.rootCategory=INFO, console
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+            status:
+              phase: Pending
+              appliedNonCriticalConfig:
+                labels: {}
+                log4jConfig: |
+                  log4j.rootCategory=INFO, broken
+                logLevels:
+                  simple-master-default: null
+            ",
+        )
+        .unwrap();
+        let (effective_sc, deferred) = defer_non_critical_changes(sc);
+        assert!(deferred.is_empty());
+        assert_eq!(
+            effective_sc.spec.log4j_config.as_deref(),
+            Some("log4j.rootCategory=INFO, console\n")
+        );
+    }
+
+    #[test]
+    fn network_policy_lets_the_operator_read_the_master_state() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              enableNetworkPolicy: true
+            ",
+        )
+        .unwrap();
+        let rules = build_network_policy(&sc, Some("stackable-operators"))
+            .unwrap()
+            .spec
+            .unwrap()
+            .ingress
+            .unwrap();
+        let web_ui_rule = rules
+            .iter()
+            .find(|rule| {
+                rule.ports
+                    .iter()
+                    .flatten()
+                    .any(|port| port.port == Some(IntOrString::String(PORT_NAME_WEB.to_string())))
+            })
+            .unwrap();
+        let operator_peer = web_ui_rule
+            .from
+            .iter()
+            .flatten()
+            .find(|peer| peer.pod_selector.is_some())
+            .unwrap();
+        assert_eq!(
+            operator_peer
+                .namespace_selector
+                .as_ref()
+                .and_then(|selector| selector.match_labels.clone())
+                .unwrap()[LABEL_NAMESPACE_NAME],
+            "stackable-operators"
+        );
+        assert_eq!(
+            operator_peer
+                .pod_selector
+                .as_ref()
+                .and_then(|selector| selector.match_labels.clone())
+                .unwrap()["app.kubernetes.io/name"],
+            "spark-operator"
+        );
+    }
+
+    #[test]
+    fn applications_running_longer_than_the_threshold_are_stale() {
+        let now = Utc::now();
+        let started_hours_ago = |hours: i64| {
+            (now - stackable_operator::k8s_openapi::chrono::Duration::hours(hours))
+                .timestamp_millis()
+        };
+        let master_state = MasterState {
+            status: MASTER_STATUS_ALIVE.to_string(),
+            cores: 8,
+            cores_used: 4,
+            memory: 8192,
+            memory_used: 4096,
+            active_apps: vec![
+                crate::master_state::MasterApplication {
+                    id: "app-stuck".to_string(),
+                    start_time: started_hours_ago(25),
+                },
+                crate::master_state::MasterApplication {
+                    id: "app-recent".to_string(),
+                    start_time: started_hours_ago(1),
+                },
+            ],
+            completed_apps: Vec::new(),
+        };
+        assert_eq!(
+            stale_applications(&master_state, 24.0, now),
+            vec![("app-stuck".to_string(), Duration::from_secs(25 * 3600))]
+        );
+        assert!(stale_applications(&master_state, 48.0, now).is_empty());
+
+        let stale = condition(CONDITION_STALE_APPLICATION, "True");
+        assert_eq!(
+            condition_event_type(Some("False"), &stale),
+            Some(EVENT_TYPE_WARNING)
+        );
+    }
+
+    #[test]
+    fn master_pods_are_replaced_by_scale_downs_and_template_changes() {
+        let statefulset = |sc: &SparkCluster| {
+            build_rolegroup_statefulset(
+                sc,
+                &[(PORT_NAME_SPARK.to_string(), 7077)],
+                &sc.server_rolegroup_ref(SparkRole::Master.to_string(), "default"),
+                &validated_config(sc)[&SparkRole::Master.to_string()]["default"],
+                "secrets",
+                "config",
+            )
+            .unwrap()
+        };
+        let cluster = |version: &str, replicas: u16| -> SparkCluster {
+            serde_yaml::from_str(&format!(
+                "
+                apiVersion: spark.stackable.tech/v1alpha1
+                kind: SparkCluster
+                metadata:
+                  name: simple
+                  namespace: default
+                  uid: 1a2b3c
+                spec:
+                  version: {}
+                  masters:
+                    roleGroups:
+                      default:
+                        replicas: {}
+                  workers:
+                    roleGroups:
+                      default:
+                        replicas: 1
+                ",
+                version, replicas
+            ))
+            .unwrap()
+        };
+        let existing = statefulset(&cluster("3.0.1", 3));
+        assert!(!master_pods_replaced(
+            &existing,
+            &statefulset(&cluster("3.0.1", 3))
+        ));
+        assert!(!master_pods_replaced(
+            &existing,
+            &statefulset(&cluster("3.0.1", 5))
+        ));
+        assert!(master_pods_replaced(
+            &existing,
+            &statefulset(&cluster("3.0.1", 1))
+        ));
+        assert!(master_pods_replaced(
+            &existing,
+            &statefulset(&cluster("3.1.1", 3))
+        ));
+
+        let mut unhashed = existing.clone();
+        unhashed.metadata.annotations = None;
+        assert!(master_pods_replaced(
+            &unhashed,
+            &statefulset(&cluster("3.0.1", 3))
+        ));
+    }
+
+    #[test]
+    fn scaling_a_cluster_without_a_default_worker_role_group_adds_one() {
+        let sc: SparkCluster = serde_yaml::from_str(
+            "
+            apiVersion: spark.stackable.tech/v1alpha1
+            kind: SparkCluster
+            metadata:
+              name: simple
+              namespace: default
+              uid: 1a2b3c
+            spec:
+              version: 3.0.1
+              masters:
+                roleGroups:
+                  default:
+                    replicas: 1
+              workers:
+                config:
+                  cores: 2
+                roleGroups:
+                  2core2g:
+                    replicas: 2
+            ",
+        )
+        .unwrap();
+        // What the API server writes to the `specReplicasPath` of `kubectl scale --replicas=5`
+        let mut scaled = serde_json::to_value(&sc).unwrap();
+        scaled["spec"]["workers"]["roleGroups"]["default"] = serde_json::json!({"replicas": 5});
+        let scaled: SparkCluster = serde_json::from_value(scaled).unwrap();
+
+        let worker = SparkRole::Worker.to_string();
+        assert_eq!(
+            rolegroup_replicas(&scaled, &scaled.server_rolegroup_ref(&worker, "2core2g")).unwrap(),
+            2
+        );
+        assert_eq!(
+            rolegroup_replicas(&scaled, &scaled.server_rolegroup_ref(&worker, "default")).unwrap(),
+            5
+        );
+        let validated_config = validated_config(&scaled);
+        assert!(validated_config[&worker].contains_key("default"));
+        assert!(validated_config[&worker].contains_key("2core2g"));
+    }
+
+    #[test]
+    fn api_unavailable_backoff_is_reported_once_and_ends_with_any_answer() {
+        let error_requeue = Duration::from_secs(5);
+        let api_availability = ApiAvailability::default();
+        let (delays, warnings): (Vec<_>, Vec<_>) = (0..5)
+            .map(|_| api_availability.record_unavailable(error_requeue))
+            .unzip();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_secs(5),
+                Duration::from_secs(5),
+                Duration::from_secs(10),
+                Duration::from_secs(20),
+                Duration::from_secs(40),
+            ]
+        );
+        assert_eq!(warnings, vec![false, false, true, false, false]);
+        assert!(api_availability.backoff());
+
+        // E.g. a failed validation, the API server answered
+        assert!(api_availability.record_available());
+        assert!(!api_availability.backoff());
+        assert!(!api_availability.record_available());
+        assert_eq!(
+            api_availability.record_unavailable(error_requeue),
+            (error_requeue, false)
+        );
+    }
+}